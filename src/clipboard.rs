@@ -0,0 +1,134 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// The clipboard is how the registers `+` and `*` reach the clipboard of the system
+pub enum Clipboard {
+    /// The text is sent to the terminal with an OSC 52 escape sequence, which sets the clipboard
+    /// of the terminal, even through ssh. The terminal does not give the clipboard back, so
+    /// putting from the registers uses the last text yanked in them.
+    Osc52,
+    /// The text is given to a command on its standard input, such as `xclip -selection clipboard`
+    /// or `wl-copy`. If a paste command is provided (`xclip -o -selection clipboard`, `wl-paste`),
+    /// its output is read when putting from the registers.
+    Commands {
+        copy: String,
+        paste: Option<String>,
+    },
+}
+
+impl Clipboard {
+    /// Sends a text to the clipboard. The register `+` is the clipboard, `*` is the primary
+    /// selection.
+    pub fn copy(&self, register: char, text: &str) {
+        match self {
+            Clipboard::Osc52 => {
+                // The sequence goes straight to the terminal, next to the output of ncurses
+                print!("{}", osc52_sequence(register, text));
+                std::io::stdout()
+                    .flush()
+                    .map_err(|err| eprintln!("Cannot write to the clipboard: {err:?}"))
+                    .ok();
+            }
+            Clipboard::Commands { copy, .. } => {
+                run_copy_command(copy, text)
+                    .map_err(|err| eprintln!("Cannot run '{copy}': {err:?}"))
+                    .ok();
+            }
+        }
+    }
+
+    /// Returns the text of the clipboard, if it can be read
+    pub fn paste(&self) -> Option<String> {
+        match self {
+            Clipboard::Commands { paste: Some(paste), .. } => {
+                let output = Command::new("sh")
+                    .arg("-c")
+                    .arg(paste)
+                    .stderr(Stdio::null())
+                    .output()
+                    .map_err(|err| eprintln!("Cannot run '{paste}': {err:?}"))
+                    .ok()?;
+                String::from_utf8(output.stdout).ok()
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Runs the copy command, with the text on its standard input
+fn run_copy_command(command: &str, text: &str) -> std::io::Result<()> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes())?;
+    }
+    child.wait()?;
+    Ok(())
+}
+
+/// Returns the escape sequence that sets the clipboard (`+`) or the primary selection (`*`) of
+/// the terminal
+fn osc52_sequence(register: char, text: &str) -> String {
+    let selection = if register == '*' { 'p' } else { 'c' };
+    format!("\x1b]52;{selection};{}\x07", base64(text.as_bytes()))
+}
+
+/// Encodes data in base64, as expected by OSC 52
+fn base64(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64_ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_osc52_sequence() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64("héllo\n".as_bytes()), "aMOpbGxvCg==");
+        assert_eq!(osc52_sequence('+', "foo"), "\x1b]52;c;Zm9v\x07");
+        assert_eq!(osc52_sequence('*', "foo"), "\x1b]52;p;Zm9v\x07");
+    }
+
+    #[test]
+    fn test_clipboard_commands() {
+        let dir = std::env::temp_dir().join(format!("red-clipboard-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("clipboard");
+        let clipboard = Clipboard::Commands {
+            copy: format!("cat > '{}'", file.display()),
+            paste: Some(format!("cat '{}'", file.display())),
+        };
+        clipboard.copy('+', "copied\ntext");
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "copied\ntext");
+        assert_eq!(clipboard.paste(), Some("copied\ntext".to_string()));
+
+        // Without a paste command, the clipboard cannot be read
+        let clipboard = Clipboard::Commands { copy: "true".to_string(), paste: None };
+        assert_eq!(clipboard.paste(), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
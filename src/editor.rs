@@ -1,6 +1,10 @@
 use ncurses::*;
 
+use crate::clipboard::Clipboard;
 use crate::editor_model::EditorModel;
+use crate::input::InputDecoder;
+use crate::graphemes::{display_width, grapheme_to_byte};
+use crate::screen::{highlight_segments, Highlight};
 
 pub struct TextEditor {
     model: EditorModel,
@@ -10,26 +14,34 @@ pub struct TextEditor {
 impl TextEditor {
     const X_BASELINE: i32 = 4;
     const Y_BASELINE: i32 = 2;
+    /// Time (in milliseconds) to wait after an escape for the rest of an Alt combination
+    const ESCAPE_DELAY: i32 = 25;
 
-    pub fn new(filename: String) -> Self {
+    pub fn new(filename: String, persistent_undo: bool, clipboard: Clipboard) -> Self {
         Self {
-            model: EditorModel::from_file(filename),
+            model: EditorModel::from_file(filename, persistent_undo, clipboard),
             background_color: 0,
         }
     }
 
     pub fn display(&mut self) {
+        // The locale must be set for ncurses to print UTF-8 text
+        setlocale(LcCategory::all, "");
+
         //Start NCurses
         initscr();
         cbreak();
         keypad(stdscr(), true);
         noecho();
+        set_escdelay(TextEditor::ESCAPE_DELAY);
 
         use_default_colors();
         start_color();
         init_pair(1, COLOR_BLACK, -1);
         init_pair(2, COLOR_MAGENTA, -1);
         init_pair(3, COLOR_BLACK, COLOR_CYAN);
+        init_pair(4, COLOR_BLACK, COLOR_YELLOW);
+        init_pair(5, COLOR_WHITE, COLOR_MAGENTA);
         attron(COLOR_PAIR(1));
 
         // Get the default color pairs
@@ -48,16 +60,39 @@ impl TextEditor {
         self.draw_screen();
 
         // Handle user inputs
-        let mut ch = getch();
-        while ch != KEY_F(1) {
-            // Handle keys via the model
-            self.model.key_tapped(ch as u32);
+        // The raw inputs are decoded into keys, since a character can be made of several bytes
+        let mut decoder = InputDecoder::new();
+        loop {
+            // After an escape, the next key is only waited for a short time, to know if it was
+            // an Alt combination or a simple escape.
+            let ch = if decoder.is_escape_pending() {
+                timeout(TextEditor::ESCAPE_DELAY);
+                let ch = getch();
+                timeout(-1);
+                ch
+            } else {
+                getch()
+            };
+            if ch == KEY_F(1) {
+                break;
+            }
 
-            // Draw based on the model
-            self.draw_screen();
+            let key = if ch == ERR {
+                decoder.flush()
+            } else {
+                decoder.push(ch)
+            };
+
+            if let Some(key) = key {
+                // Handle keys via the model
+                self.model.key_tapped(key);
+                if self.model.is_exit_requested() {
+                    break;
+                }
 
-            // Get the next key
-            ch = getch();
+                // Draw based on the model
+                self.draw_screen();
+            }
         }
 
         /* Terminate ncurses. */
@@ -69,21 +104,25 @@ impl TextEditor {
     /// Function in charge of drawing the entire visible screen
     /// It is called after every key is tapped, once the model has been updated.
     fn draw_screen(&mut self) {
-        let lines = self.model.get_lines();
+        let buffer = self.model.get_buffer();
         let screen = self.model.get_screen_info();
         let cursor = self.model.get_cursor();
-        let selection = self.model.get_selection();
 
         // Number of time that a breakline happened while drawing the provided range
         let mut breakline_count = 0;
         let mut cursor_y_pos: i32 = -1;
-        let mut cursor_x_pos: i32 = cursor.x as i32;
+        let mut cursor_x_pos: i32 = 0;
 
         // Start by clearing the entire screen
         wmove(stdscr(), 0, 0);
         clrtobot();
 
-        for line_number in 0..lines.len() {
+        // Only the lines starting from the top of the screen can be visible
+        let last_line = buffer.len_lines().min(screen.max_line().max(0) as usize);
+        let first_line = (screen.top.max(0) as usize).min(last_line);
+        // The selection and the matches of the search, for each visible line
+        let highlights = self.model.get_highlights(first_line..last_line);
+        for line_number in screen.top as usize..last_line {
             let line_number = line_number as i32;
             // The line in the screen reference
             let line_in_screen = line_number + breakline_count - screen.top;
@@ -109,111 +148,44 @@ impl TextEditor {
                 // Print the line (line number followed by )
                 // If the text goes beyond the screen, we split the line in sublines
                 // and print each one of them accordingly.
-                let line = &lines[line_number as usize];
+                let line = &buffer.line(line_number as usize);
                 let ranges = screen.split_line(line);
-                let has_selection =
-                    selection.is_some() && selection.unwrap().contains_line(line_number as usize);
-
-                if ranges.len() <= 1 {
-                    if has_selection {
-                        // The line is partially selected
-                        // There are three parts to draw:
-                        // - the beginning
-                        // - the selected
-                        // - the end
-
-                        let mut start = 0;
-                        let mut end = line.len();
-
-                        if selection.unwrap().start().y == line_number as usize {
-                            start = selection.unwrap().start().x;
-                        }
-
-                        if selection.unwrap().end().y == line_number as usize {
-                            end = selection.unwrap().end().x;
-                        }
-
-                        let part1 = &line[0..start];
-                        let part2 = &line[start..end];
-                        let part3 = &line[end..line.len()];
-
-                        addstr(part1);
-                        attron(COLOR_PAIR(3));
-                        addstr(part2);
-                        attron(COLOR_PAIR(1));
-                        addstr(part3);
-                    } else {
-                        addstr(line);
-                    }
-                } else {
-                    // If the cursor is placed on this line, we must correct its position
-                    // Correct the cursor position to be placed correctly
-                    if cursor.y as i32 == line_number {
-                        cursor_x_pos = cursor.x as i32 % screen.w;
-                        cursor_y_pos += cursor.x as i32 / screen.w;
-                    }
 
-                    // Print all the sublines
-                    let initial_breakline_count = breakline_count;
-                    for range in ranges {
-                        let range_start = range.start;
-                        let range_end = range.end;
-                        let subline = &line[range];
-                        wmove(
-                            stdscr(),
-                            line_in_screen + breakline_count - initial_breakline_count,
-                            TextEditor::X_BASELINE,
-                        );
-
-                        if has_selection {
-                            // by default, the entire subline is printed as as selected
-                            // These values are changed depending on the selection
-                            let mut start = 0;
-                            let mut end = subline.len();
-
-                            // If the selection starts in this line, change the start 
-                            if selection.unwrap().start().y == line_number as usize {
-                                // If the start in the range, then change it
-                                if selection.unwrap().start().x <= range_end && selection.unwrap().start().x >= range_start {
-                                    start = selection.unwrap().start().x - range_start;
-                                }
-                                // If the starts is after the range, then do not highlight anything
-                                else if selection.unwrap().start().x > range_end {
-                                    // It means the selection does not start now
-                                    start = subline.len();
-                                }
-                            }
-
-                            // If the selection ends in this line, change the end
-                            if selection.unwrap().end().y == line_number as usize {
-                                // if the end is before the range, then do not select anything
-                                if selection.unwrap().end().x < range_start {
-                                    start = subline.len();
-                                }
-                                // if the end is in the range, the change the end of the visual
-                                else if selection.unwrap().end().x <= range_end && selection.unwrap().end().x >= range_start {
-                                    end = selection.unwrap().end().x - range_start;
-                                }
-                            }
-
-                            let part1 = &subline[0..start];
-                            let part2 = &subline[start..end];
-                            let part3 = &subline[end..subline.len()];
-                            addstr(part1);
-                            attron(COLOR_PAIR(3));
-                            addstr(part2);
-                            attron(COLOR_PAIR(1));
-                            addstr(part3);
-
-                        } else {
-                            addstr(subline);
-                        }
-
-                        breakline_count += 1;
+                let line_highlights = &highlights[line_number as usize - first_line];
+
+                // If the cursor is placed on this line, we must find in which subline it is,
+                // and how many cells are used before it.
+                if cursor.y as i32 == line_number {
+                    let at = grapheme_to_byte(line, cursor.x);
+                    let subline = ranges
+                        .iter()
+                        .position(|range| at < range.end)
+                        .unwrap_or(ranges.len() - 1);
+                    let start = ranges[subline].start;
+                    cursor_x_pos = display_width(&line[start..at.max(start)]) as i32;
+                    cursor_y_pos += subline as i32;
+                }
+
+                // Print all the sublines, with the selection and the matches highlighted
+                for (i, range) in ranges.iter().enumerate() {
+                    wmove(
+                        stdscr(),
+                        line_in_screen + i as i32,
+                        TextEditor::X_BASELINE,
+                    );
+
+                    for (segment, highlight) in highlight_segments(range.clone(), line_highlights) {
+                        attron(COLOR_PAIR(match highlight {
+                            None => 1,
+                            Some(Highlight::Selection) => 3,
+                            Some(Highlight::Match) => 4,
+                            Some(Highlight::CurrentMatch) => 5,
+                        }));
+                        addstr(&line[segment]);
                     }
-                    // We have to remove 1 breakline
-                    breakline_count -= 1;
+                    attron(COLOR_PAIR(1));
                 }
+                breakline_count += ranges.len() as i32 - 1;
                 /*
                  */
                 clrtoeol();
@@ -228,6 +200,15 @@ impl TextEditor {
         hline('-' as u32, 1000);
         mvprintw(LINES() - 1, 0, self.model.get_status_message().as_str());
 
+        // The message, if any, is drawn over the text, just above the status bar
+        let message = self.model.get_message();
+        let first_line = LINES() - 2 - message.len() as i32;
+        for (i, line) in message.iter().enumerate() {
+            wmove(stdscr(), first_line + i as i32, 0);
+            clrtoeol();
+            addstr(line);
+        }
+
         // Finally, we draw the cursor
         if cursor_y_pos >= 0 {
             wmove(
@@ -1,4 +1,9 @@
-use crate::motion::Motion;
+use crate::ex::Command;
+use crate::motion::{Motion, TextObject};
+use crate::operator::Operator;
+use crate::selection::SelectionKind;
+use crate::substitute::ConfirmAnswer;
+use crate::undo_redo::TimeTravel;
 
 /// Enum that holds a change to apply to the model
 ///
@@ -10,7 +15,7 @@ use crate::motion::Motion;
 pub enum EditorAction {
     /// Add a character at the current cursor position
     AddCharAtCursor {
-        ch: u32,
+        ch: char,
     },
     /// Delete a character at the current cursor position
     DeleteCharAtCursor,
@@ -23,6 +28,10 @@ pub enum EditorAction {
         dx: i32,
         dy: i32,
     },
+    /// Move the cursor by half of the height of the screen
+    ScrollHalfScreen {
+        down: bool,
+    },
     /// Move by a desired amount of words. Can be negative
     MoveByWords {
         n_words: i32,
@@ -30,66 +39,123 @@ pub enum EditorAction {
     ApplyMotion {
         motion: Motion
     },
+    /// Apply an operator (delete, change, yank) on the text covered by the motion
+    ApplyOperator {
+        operator: Operator,
+        motion: Motion,
+    },
+    /// Select the text object around the cursor
+    SelectTextObject {
+        object: TextObject,
+    },
     // Switch between modes
     SwitchToInsertMode,
     SwitchToNormalMode,
     SwitchToCommandMode,
-    /// Toggle the selection mode: ON or OFF
-    ToggleSelectionState,
-    /// Yank the current selection (if available)
-    Yank,
-    /// Put the current yanking
-    Put,
+    /// Open the search prompt, for `/`, or for `?` if `backward` is set
+    SwitchToSearchMode {
+        backward: bool,
+    },
+    /// Search the pattern typed in the search prompt, or the last pattern if it is empty
+    Search {
+        pattern: String,
+        backward: bool,
+    },
+    /// Show where the pattern being typed in the search prompt is found
+    PreviewSearch {
+        pattern: String,
+        backward: bool,
+    },
+    /// Search the word under the cursor, for `*`, or for `#` if `backward` is set
+    SearchWordUnderCursor {
+        backward: bool,
+        count: usize,
+    },
+    /// Replace the current match of `:s///c` or not, depending on the answer of the user
+    ConfirmSubstitution {
+        answer: ConfirmAnswer,
+    },
+    /// Start a selection of the provided kind, or stop it if it is already of this kind
+    ToggleSelection {
+        kind: SelectionKind,
+    },
+    /// Apply an operator on the current selection (if available)
+    OperateOnSelection {
+        operator: Operator,
+    },
+    /// Join the lines of the selection, or the line of the selection with the next one
+    JoinSelectedLines,
+    /// Replace every character of the selection
+    ReplaceSelection {
+        ch: char,
+    },
+    /// Move the cursor to the other end of the selection
+    SwapSelectionEnds,
+    /// Select again the last selection
+    Reselect,
+    /// Set a mark at the cursor, for `ma`
+    SetMark {
+        mark: char,
+    },
+    /// Insert text before the block selection, or after it if `append` is set. The text typed on
+    /// the first line is copied on the other lines of the block.
+    BlockInsert {
+        append: bool,
+    },
+    /// Put the content of the register `count` times, after the cursor or before it
+    Put {
+        before: bool,
+        count: usize,
+    },
     /// Called when the escape key is pressed
     AbortCurrentAction,
-    /// Save the file
-    Save,
-    /// Exit the program
-    Exit,
+    /// Execute a command typed on the command line
+    ExecuteCommand {
+        command: Command,
+    },
     /// A composite action contains a list of actions to execute
     CompositeAction {
         actions: Vec<Box<EditorAction>>
     },
-    /// Jump the cursor to the provided line
-    JumpToLine {
-        line: usize
+    /// Execute the same action several times, for instance "5j"
+    Repeat {
+        count: usize,
+        action: Box<EditorAction>,
+    },
+    /// Repeat the last change, with a new count if provided
+    RepeatLastChange {
+        count: Option<usize>,
+    },
+    /// Execute an action with a register, for instance "ayy yanks a line in the register 'a'
+    WithRegister {
+        register: char,
+        action: Box<EditorAction>,
+    },
+    /// Show an error in the status bar, for instance when a command line cannot be parsed
+    ShowError {
+        message: String,
+    },
+    /// Keep the command line that was executed, in the register ':'
+    RememberCommandLine {
+        command: String,
     },
-    /// Undo action
+    /// Start recording the keys typed in a macro register, for instance "qa"
+    RecordMacro {
+        register: char,
+    },
+    /// Play the keys of a macro register `count` times. Without a register ("@@"), the last
+    /// played macro is played again.
+    PlayMacro {
+        register: Option<char>,
+        count: usize,
+    },
+    /// Undo the last change
     Undo,
+    /// Redo the last undone change
+    Redo,
+    /// Go to an older or newer state of the text, following the undo tree chronologically
+    TimeTravel {
+        travel: TimeTravel,
+    },
     None,
 }
-
-impl EditorAction {
-    /// Returns true if this action can be undone using the undo redo manager
-    pub fn can_be_undo(&self) -> bool {
-        match self {
-            EditorAction::AddCharAtCursor { ch } => true,
-            EditorAction::DeleteCharAtCursor => true,
-            EditorAction::JumpLineAtCursor => true,
-            EditorAction::MoveCursorDown => false,
-            EditorAction::MoveCursor { dx, dy } => false,
-            EditorAction::MoveByWords { n_words } => false,
-            EditorAction::ApplyMotion { motion } => false,
-            EditorAction::SwitchToInsertMode => false,
-            EditorAction::SwitchToNormalMode => false,
-            EditorAction::SwitchToCommandMode => false,
-            EditorAction::ToggleSelectionState => false,
-            EditorAction::Yank => false,
-            EditorAction::Put => false,
-            EditorAction::AbortCurrentAction => false,
-            EditorAction::Save => false,
-            EditorAction::Exit => false,
-            EditorAction::CompositeAction { actions } => true,
-            EditorAction::JumpToLine { line } => false,
-            EditorAction::Undo => false,
-            EditorAction::None => false,
-        }        
-    }
-
-    pub fn undo_action(&self) -> EditorAction {
-        match self {
-            EditorAction::AddCharAtCursor { ch } => EditorAction::DeleteCharAtCursor,
-           _ => EditorAction::None 
-        }
-    }
-}
@@ -1,34 +1,44 @@
 use crate::{
+    clipboard::Clipboard,
     modes::insert_mode::InsertMode,
-    modes::{command_mode::CommandMode, normal_mode::NormalMode},
-    motion::Motion,
+    modes::{command_mode::CommandMode, confirm_mode::ConfirmMode, normal_mode::NormalMode, search_mode::SearchMode},
+    motion::{first_non_blank, Motion, MotionKind, TextObject},
+    operator::Operator,
     editor_action::EditorAction,
-    screen::Screen,
-    yanker::Yanker,
+    screen::{Highlight, Screen},
+    search::{find_line, find_next, word_at, BufferLines, Pattern},
+    substitute::{expand_replacement, ConfirmAnswer, Substitute},
+    ex::{Address, AddressKind, Command, CommandKind, LineRange},
+    yanker::{Register, RegisterKind, Yanker},
     cursor::Cursor,
-    selection::Selection, undo_redo::UndoRedoManager,
+    selection::{Selection, SelectionKind},
+    undo_redo::{content_hash, undo_file_path, TextChange, UndoMove, UndoRedoManager},
+    text_buffer::TextBuffer,
+    graphemes::{byte_to_grapheme, grapheme_count, grapheme_to_byte, graphemes},
+    regex::{text_between, Lines, Pos},
+    key::Key,
 };
 
+use std::collections::{HashMap, VecDeque};
 use std::fs;
-use std::process::exit;
-
-use ncurses::{KEY_BACKSPACE, KEY_DOWN, KEY_LEFT, KEY_RIGHT, KEY_UP, endwin};
-
-// It is required to recreate constants for the specials keys
-// This makes it easier to parse them when received
-pub const LEFT: Option<char> = char::from_u32(KEY_LEFT as u32);
-pub const RIGHT: Option<char> = char::from_u32(KEY_RIGHT as u32);
-pub const UP: Option<char> = char::from_u32(KEY_UP as u32);
-pub const DOWN: Option<char> = char::from_u32(KEY_DOWN as u32);
-pub const ENTER: Option<char> = char::from_u32(10 as u32);
-pub const ESCAPE: Option<char> = char::from_u32(27 as u32);
-pub const BACKSPACE: Option<char> = char::from_u32(KEY_BACKSPACE as u32);
+use std::ops::{Range, RangeInclusive};
+use std::path::Path;
 
 /// A trait that defines an edition mode.
 /// For instance, the insert or the normal mode.
 pub trait EditorMode {
-    fn key_tapped(&mut self, ch: u32) -> EditorAction;
+    fn key_tapped(&mut self, key: Key) -> EditorAction;
     fn get_description(&self) -> String;
+
+    /// Returns true while the mode waits for more keys to complete a command, for instance after
+    /// 'd' in normal mode
+    fn is_pending(&self) -> bool {
+        false
+    }
+
+    /// Tells the mode if a selection is being made, and its kind: keys such as 'd' act on the
+    /// selection instead of waiting for a motion
+    fn set_selection_kind(&mut self, _kind: Option<SelectionKind>) {}
 }
 
 /// The editor model is the class which holds the text data and is in charge of all the editions.
@@ -36,65 +46,294 @@ pub struct EditorModel {
     filename: String,
     cursor: Cursor,
     screen: Screen,
-    /// Contains the lines of the text
-    lines: Vec<String>,
+    /// Contains the text of the file
+    buffer: TextBuffer,
     /// The editor mode is in charge of parsing key tapped and returning editor actions
     editor_mode: Box<dyn EditorMode>,
     /// The yanker keeps track of what is yanked and what is put
     yanker: Yanker,
+    /// Register given to the action being handled, such as 'a' in "ayy
+    register: Option<char>,
+    /// Text typed during the current insert session, kept in the register '.'
+    inserted_text: String,
+    /// Set by a block insert: where the text typed is copied when the insert session ends
+    block_insert: Option<BlockInsert>,
+    /// Lines shown to the user until the next key, for instance by `:registers`
+    message: Vec<String>,
+    /// Shown in the status bar until the next key, for instance when a search fails
+    status: Option<String>,
+    /// The last pattern searched, which `n` and `N` search again
+    search: Option<Pattern>,
+    /// Set if the last search was made with `?` or `#`
+    search_backward: bool,
+    /// Set if the matches of the last search are highlighted. `:nohlsearch` unsets it until the
+    /// next search.
+    highlight_search: bool,
+    /// While a pattern is typed in the search prompt: the pattern, and where it is found
+    search_preview: Option<(Pattern, Option<Cursor>)>,
+    /// While `:s///c` asks to confirm each replacement: the replacements left
+    substitutions: Option<Substitutions>,
+    /// While `:g` runs: the lines left to execute the command on. They are kept on their lines
+    /// like the marks.
+    global_lines: Option<VecDeque<usize>>,
     /// The selection is a property of the model. By default it is none.
     selection: Option<Selection>,
+    /// The last selection made, which `gv` selects again
+    last_selection: Option<Selection>,
+    /// Marks set with `m`, which Ex addresses such as `'a` refer to
+    marks: HashMap<char, Cursor>,
     /// The undo redo manager is in charge of keeping track of the last actions
     undo_redo_mgr: UndoRedoManager,
+    /// Set when the user asked to quit the editor
+    exit_requested: bool,
+    /// Sequence number of the undo state of the text when the file was last read or written:
+    /// when the current state is another one, there are changes to write
+    saved_state: usize,
+    /// True while in insert mode: the undo transaction of the insert session is open
+    is_insert_session_open: bool,
+    /// If true, the undo history is written to an undo file when saving, and read back when
+    /// opening the file
+    persistent_undo: bool,
+    /// True while in command mode: the keys typed on the command line are not repeated by '.'
+    is_command_line_open: bool,
+    /// Number of changes made to the buffer since the file was opened
+    n_changes: usize,
+    /// Keys of the command being typed, including its insert session if it opens one
+    command_keys: Vec<Key>,
+    /// Value of `n_changes` when the command being typed started
+    n_changes_before_command: usize,
+    /// Keys of the last command that changed the buffer, replayed by '.'
+    last_change_keys: Vec<Key>,
+    /// Keys recorded in each macro register
+    macros: HashMap<char, Vec<Key>>,
+    /// Register of the macro being recorded, if any
+    recording_register: Option<char>,
+    /// Keys typed since the recording started
+    recorded_keys: Vec<Key>,
+    /// Last macro played, played again by "@@"
+    last_played_macro: Option<char>,
+    /// Keys of the macros being played, which are processed as if they were typed
+    macro_keys: VecDeque<Key>,
+    /// Number of macros played since the last typed key
+    n_macro_plays: usize,
+}
+
+/// Lines of a block insert (`I` or `A` in visual block mode), other than the line where the text
+/// is typed
+struct BlockInsert {
+    lines: RangeInclusive<usize>,
+    /// Column where the text is inserted on each line
+    column: usize,
+    /// If set, the lines which are too short are padded with spaces up to the column. Otherwise
+    /// they are left untouched.
+    pad: bool,
+}
+
+/// The replacements of a `:s` command, made one after the other
+struct Substitutions {
+    /// The replacements left, in the order of the buffer
+    left: VecDeque<Replacement>,
+    /// Number of bytes added to the buffer by the replacements made so far, which moves the
+    /// replacements left
+    shift: isize,
+    /// Number of replacements made
+    n_replaced: usize,
+    /// Number of lines where replacements were made
+    n_lines: usize,
+    /// Line of the last replacement made, before the replacements
+    last_line: Option<usize>,
+    /// Offset of the end of the last replacement made
+    end: Option<usize>,
+    /// Set if each replacement is confirmed by the user
+    confirm: bool,
+}
+
+/// A match of `:s`, and the text replacing it
+struct Replacement {
+    /// Byte range of the match, before any replacement
+    range: Range<usize>,
+    text: String,
+    /// Line where the match starts, before any replacement
+    line: usize,
+}
+
+impl Substitutions {
+    /// Returns the byte range of the next replacement, in the buffer as it is now
+    fn next_range(&self) -> Option<Range<usize>> {
+        let range = &self.left.front()?.range;
+        Some(range.start.saturating_add_signed(self.shift)..range.end.saturating_add_signed(self.shift))
+    }
+}
+
+/// Returns the number followed by the singular or the plural word, such as "1 line" or "2 lines"
+fn plural(n: usize, singular: &str, plural: &str) -> String {
+    format!("{n} {}", if n == 1 { singular } else { plural })
 }
 
+/// Maximum number of macros played by a single command, to stop macros which call themselves
+/// without ever failing
+const MAX_MACRO_PLAYS: usize = 100_000;
+
+/// Number of columns a line is shifted by `>` and `<`
+const SHIFT_WIDTH: usize = 4;
+
 impl EditorModel {
     #[cfg(test)]
     pub fn new() -> Self {
-
-        let mut tmp: Vec<String> = Vec::new();
-        tmp.push("Hello World".to_owned());
-        tmp.push("This is another sentence".to_owned());
         Self {
             cursor: Cursor { x: 0, y: 0 },
-            lines: tmp,
+            buffer: TextBuffer::from("Hello World\nThis is another sentence"),
             editor_mode: Box::new(NormalMode::new()),
             screen: Screen { top: 0, h: 0, w: 0 },
-            yanker: Yanker::new(),
+            yanker: Yanker::new(Clipboard::Osc52),
+            register: None,
+            inserted_text: String::new(),
+            block_insert: None,
+            last_selection: None,
+            marks: HashMap::new(),
+            message: Vec::new(),
+            status: None,
+            search: None,
+            search_backward: false,
+            highlight_search: false,
+            search_preview: None,
+            substitutions: None,
+            global_lines: None,
             selection: None,
             filename: "new_file.txt".to_string(),
-            undo_redo_mgr: UndoRedoManager::new()
+            undo_redo_mgr: UndoRedoManager::new(),
+            exit_requested: false,
+            saved_state: 0,
+            is_insert_session_open: false,
+            persistent_undo: false,
+            is_command_line_open: false,
+            n_changes: 0,
+            command_keys: Vec::new(),
+            n_changes_before_command: 0,
+            last_change_keys: Vec::new(),
+            macros: HashMap::new(),
+            recording_register: None,
+            recorded_keys: Vec::new(),
+            last_played_macro: None,
+            macro_keys: VecDeque::new(),
+            n_macro_plays: 0,
         }
     }
 
-    pub fn from_file(filename: String) -> Self {
-        let lines = if let Ok(content) = fs::read_to_string(filename.clone()) {
-            content.lines().map(|s| s.to_string()).collect()
+    /// Opens a file. If `persistent_undo` is set, the undo history saved with the file is
+    /// restored, as long as the content of the file did not change since.
+    /// The registers `+` and `*` go to the provided clipboard.
+    pub fn from_file(filename: String, persistent_undo: bool, clipboard: Clipboard) -> Self {
+        let buffer = if let Ok(content) = fs::read_to_string(filename.clone()) {
+            TextBuffer::from(content.lines().collect::<Vec<&str>>().join("\n").as_str())
         } else {
-            let mut tmp = Vec::new();
-            tmp.push("".to_string());
-            tmp
+            TextBuffer::new()
         };
-        Self {
+        let mut model = Self {
             cursor: Cursor { x: 0, y: 0 },
-            lines,
+            buffer,
             editor_mode: Box::new(NormalMode::new()),
             screen: Screen { top: 0, h: 0, w: 0 },
-            yanker: Yanker::new(),
+            yanker: Yanker::new(clipboard),
+            register: None,
+            inserted_text: String::new(),
+            block_insert: None,
+            last_selection: None,
+            marks: HashMap::new(),
+            message: Vec::new(),
+            status: None,
+            search: None,
+            search_backward: false,
+            highlight_search: false,
+            search_preview: None,
+            substitutions: None,
+            global_lines: None,
             selection: None,
             filename,
-            undo_redo_mgr: UndoRedoManager::new()
+            undo_redo_mgr: UndoRedoManager::new(),
+            exit_requested: false,
+            saved_state: 0,
+            is_insert_session_open: false,
+            persistent_undo,
+            is_command_line_open: false,
+            n_changes: 0,
+            command_keys: Vec::new(),
+            n_changes_before_command: 0,
+            last_change_keys: Vec::new(),
+            macros: HashMap::new(),
+            recording_register: None,
+            recorded_keys: Vec::new(),
+            last_played_macro: None,
+            macro_keys: VecDeque::new(),
+            n_macro_plays: 0,
+        };
+        model.yanker.set_read_only('%', model.filename.clone());
+        if persistent_undo {
+            model.load_undo_file();
+            model.saved_state = model.undo_redo_mgr.get_sequence_number();
         }
+        model
     }
 
-    pub fn save_file(&self) {
-        let contents = self.lines.join("\n");
-        fs::write(&self.filename, contents).map_err(|err| println!("{err:?}")).ok();
+    /// Writes the buffer to its file, or to another file, for `:w`. Another file is not
+    /// overwritten unless `bang` is set.
+    /// Returns false if the file was not written, the error being shown in the status bar.
+    fn write_file(&mut self, file: Option<String>, bang: bool) -> bool {
+        let path = file.unwrap_or_else(|| self.filename.clone());
+        let is_own_file = path == self.filename;
+        if !is_own_file && !bang && Path::new(&path).exists() {
+            self.status = Some("E13: File exists (add ! to override)".to_string());
+            return false;
+        }
+        let contents = self.buffer.to_string();
+        if let Err(err) = fs::write(&path, &contents) {
+            self.status = Some(format!("E212: Can't open file for writing: {err}"));
+            return false;
+        }
+        if is_own_file {
+            if self.persistent_undo {
+                self.save_undo_file(&contents);
+            }
+            self.saved_state = self.undo_redo_mgr.get_sequence_number();
+        }
+        self.status = Some(format!("\"{path}\" {}L, {}B written", self.buffer.len_lines(), contents.len()));
+        true
+    }
+
+    /// Returns true if the text changed since the file was last read or written
+    fn is_modified(&self) -> bool {
+        self.undo_redo_mgr.get_sequence_number() != self.saved_state
+    }
+
+    /// Returns the path used to identify the file in the undo file
+    fn undo_key(&self) -> String {
+        fs::canonicalize(&self.filename)
+            .map(|path| path.to_string_lossy().to_string())
+            .unwrap_or(self.filename.clone())
+    }
+
+    fn save_undo_file(&self, contents: &str) {
+        let data = self.undo_redo_mgr.serialize(&self.undo_key(), content_hash(contents));
+        let path = undo_file_path(Path::new(&self.filename));
+        fs::write(path, data).map_err(|err| eprintln!("Cannot write the undo file: {err:?}")).ok();
+    }
+
+    /// Restores the undo history from the undo file, if it matches the content of the buffer
+    fn load_undo_file(&mut self) {
+        let path = undo_file_path(Path::new(&self.filename));
+        let Ok(data) = fs::read_to_string(path) else {
+            return;
+        };
+        let hash = content_hash(&self.buffer.to_string());
+        match UndoRedoManager::deserialize(&data, &self.undo_key(), hash) {
+            Some(mgr) => self.undo_redo_mgr = mgr,
+            None => eprintln!("The undo file does not match {}, ignoring it", self.filename),
+        }
     }
 
     #[cfg(test)]
     pub fn set_text(&mut self, text: String) {
-        self.lines = text.lines().map(|s| s.to_string()).collect();
+        self.buffer = TextBuffer::from(text.lines().collect::<Vec<&str>>().join("\n").as_str());
     }
 
     #[cfg(test)]
@@ -104,14 +343,29 @@ impl EditorModel {
         self.cursor.y = 0;
     }
 
+    #[cfg(test)]
+    pub fn get_yanker_content(&self) -> Option<String> {
+        self.yanker.get('"').map(|register| register.content)
+    }
+
+    #[cfg(test)]
+    pub fn get_register(&self, register: char) -> Option<Register> {
+        self.yanker.get(register)
+    }
+
+    /// Returns the lines to show to the user, which are empty most of the time
+    pub fn get_message(&self) -> &[String] {
+        &self.message
+    }
+
     #[cfg(test)]
     pub fn force_insert_mode(&mut self) {
-        self.handle_editor_action(EditorAction::SwitchToInsertMode, false);
+        self.handle_editor_action(EditorAction::SwitchToInsertMode);
     }
 
     #[cfg(test)]
     pub fn force_normal_mode(&mut self) {
-        self.handle_editor_action(EditorAction::SwitchToNormalMode, false);
+        self.handle_editor_action(EditorAction::SwitchToNormalMode);
     }
 
     pub fn set_screen_h(&mut self, h: i32) {
@@ -132,13 +386,72 @@ impl EditorModel {
     }
 
     /// Returns the start and end position of the current selection
+    #[cfg(test)]
     pub fn get_selection(&self) -> Option<Selection> {
         self.selection
     }
 
-    /// Returns the lines of the model
-    pub fn get_lines(&self) -> &Vec<String> {
-        &self.lines
+    /// Returns the last pattern searched, and true if it was searched backward
+    pub fn get_search(&self) -> Option<(&Pattern, bool)> {
+        Some((self.search.as_ref()?, self.search_backward))
+    }
+
+    /// Returns the parts of the provided lines to highlight: the matches of the search, and the
+    /// selection over them. The ranges are in bytes, one list for each line.
+    pub fn get_highlights(&self, lines: Range<usize>) -> Vec<Vec<(Range<usize>, Highlight)>> {
+        let mut highlights = vec![Vec::new(); lines.len()];
+        let buffer_lines = BufferLines::new(&self.buffer);
+        let (pattern, current) = match &self.search_preview {
+            Some((pattern, found)) => (Some(pattern), *found),
+            None if self.highlight_search => (self.search.as_ref(), None),
+            None => (None, None),
+        };
+        let current = current.map(|cursor| Pos {
+            line: cursor.y,
+            offset: grapheme_to_byte(buffer_lines.line(cursor.y), cursor.x),
+        });
+        // A match may continue on the next lines
+        let mut highlight = |start: Pos, end: Pos, kind: Highlight| {
+            for line in start.line.max(lines.start)..=end.line.min(lines.end - 1) {
+                let from = if line == start.line { start.offset } else { 0 };
+                let to = if line == end.line { end.offset } else { buffer_lines.line(line).len() };
+                highlights[line - lines.start].push((from..to, kind));
+            }
+        };
+        if let Some(pattern) = pattern {
+            for y in lines.clone() {
+                for found in pattern.find_in_line(&buffer_lines, y) {
+                    let kind = if Some(found.start) == current { Highlight::CurrentMatch } else { Highlight::Match };
+                    highlight(found.start, found.end, kind);
+                }
+            }
+        }
+        // The match `:s///c` asks to replace
+        if let Some(range) = self.substitutions.as_ref().and_then(Substitutions::next_range) {
+            highlight(self.offset_to_pos(range.start), self.offset_to_pos(range.end), Highlight::CurrentMatch);
+        }
+
+        if let Some(selection) = self.selection {
+            for y in lines.clone() {
+                let line = buffer_lines.line(y);
+                let range = selection.line_range(y, grapheme_count(line));
+                if !range.is_empty() {
+                    let range = grapheme_to_byte(line, range.start)..grapheme_to_byte(line, range.end);
+                    highlights[y - lines.start].push((range, Highlight::Selection));
+                }
+            }
+        }
+        highlights
+    }
+
+    /// Returns the text buffer of the model
+    pub fn get_buffer(&self) -> &TextBuffer {
+        &self.buffer
+    }
+
+    /// Returns true when the user asked to quit the editor
+    pub fn is_exit_requested(&self) -> bool {
+        self.exit_requested
     }
 
     pub fn get_status_message(&self) -> String {
@@ -150,6 +463,11 @@ impl EditorModel {
             } else {
                 ""
             },
+            &self
+                .recording_register
+                .map(|register| format!("recording @{register}"))
+                .unwrap_or_default(),
+            self.status.as_deref().unwrap_or_default(),
         ]
         .join("  |  ")
     }
@@ -157,19 +475,41 @@ impl EditorModel {
     // Part of the model that receives inputs.
     // This could be defined in a trait
 
+    /// Returns the number of graphemes of the line where the cursor is
     fn get_current_line_length(&self) -> usize {
-        self.lines[self.cursor.y].len()
+        self.get_line_length(self.cursor.y)
+    }
+
+    /// Returns the number of graphemes of the provided line
+    fn get_line_length(&self, line: usize) -> usize {
+        grapheme_count(&self.buffer.line(line))
     }
 
     /// Changes self with the new provided cursor, while applying the logic for the selection
     fn set_cursor(&mut self, new_cursor_pos: Cursor) {
         self.cursor = new_cursor_pos;
+        self.scroll_to_cursor();
 
         if self.selection.is_some() {
             self.selection.as_mut().unwrap().set_new_end(new_cursor_pos);
         }
     }
 
+    /// Moves the screen so that the line of the cursor is displayed, for instance after a jump
+    fn scroll_to_cursor(&mut self) {
+        self.scroll_to_line(self.cursor.y);
+    }
+
+    /// Moves the screen so that the provided line is displayed
+    fn scroll_to_line(&mut self, y: usize) {
+        let y = y as i32;
+        if y < self.screen.top {
+            self.screen.top = y;
+        } else if self.screen.h > 0 && y >= self.screen.max_line() {
+            self.screen.top = y - self.screen.h + 1;
+        }
+    }
+
     fn left_arrow_tapped(&mut self) {
         if self.cursor.x > 0 {
             self.set_cursor(Cursor {
@@ -191,7 +531,7 @@ impl EditorModel {
 
     /// If the x-cursor is beyonds the limits of the line, moves it to the length of the line
     fn fit_xcursor_to_line(&mut self, cursor: &mut Cursor) {
-        let n = self.lines[cursor.y].len();
+        let n = self.get_line_length(cursor.y);
         if n > 0 && cursor.x > n - 1 {
             cursor.x = n - 1;
         } else if n == 0 {
@@ -215,7 +555,7 @@ impl EditorModel {
 
     fn bottom_arrow_tapped(&mut self) {
         // handle the screen
-        if self.cursor.y < self.lines.len() - 1 {
+        if self.cursor.y < self.buffer.len_lines() - 1 {
             // 1. move the screen down
             if self.cursor.y == (self.screen.max_line() - 1) as usize {
                 self.screen.top += 1;
@@ -229,163 +569,1300 @@ impl EditorModel {
     }
 
     /// Changes self according to what the given action asks for
-    fn handle_editor_action(&mut self, action: EditorAction, is_undo: bool) {
-
-        // Keep track of the action through the undo redo manager
-        if !is_undo && action.can_be_undo() {
-            let clone = action.clone();
-            self.undo_redo_mgr.add_action(clone, self.cursor);
-        }
+    ///
+    /// Each action is an undo transaction: all the changes made by an action (including all the
+    /// actions of a composite action) are undone together. Entering the insert mode also opens a
+    /// transaction, which is closed when leaving it, so that everything typed during an insert
+    /// session is a single undo step.
+    fn handle_editor_action(&mut self, action: EditorAction) {
+        self.undo_redo_mgr.begin_transaction(self.cursor);
+        self.apply_editor_action(action);
+        self.undo_redo_mgr.end_transaction(self.cursor);
+    }
 
+    fn apply_editor_action(&mut self, action: EditorAction) {
         match action {
-            EditorAction::AddCharAtCursor { ch } => self.add_character_at_cursor(ch),
-            EditorAction::JumpLineAtCursor => self.add_new_line(),
-            EditorAction::DeleteCharAtCursor => self.remove_character_at_cursor(),
+            EditorAction::AddCharAtCursor { ch } => {
+                if self.is_insert_session_open {
+                    self.inserted_text.push(ch);
+                }
+                self.add_character_at_cursor(ch)
+            }
+            EditorAction::JumpLineAtCursor => {
+                if self.is_insert_session_open {
+                    self.inserted_text.push('\n');
+                }
+                self.add_new_line()
+            }
+            EditorAction::DeleteCharAtCursor => {
+                if self.is_insert_session_open {
+                    self.inserted_text.pop();
+                }
+                self.remove_character_at_cursor()
+            }
             EditorAction::MoveCursorDown => {
-                if self.cursor.y < self.lines.len() - 1 {
+                if self.cursor.y < self.buffer.len_lines() - 1 {
                     self.set_cursor(Cursor {
                         x: 0,
                         y: self.cursor.y + 1,
                     });
                 }
             }
-            EditorAction::MoveCursor { dx, dy } => match (dx, dy) {
-                (1, 0) => self.right_arrow_tapped(),
-                (-1, 0) => self.left_arrow_tapped(),
-                (0, 1) => self.top_arrow_tapped(),
-                (0, -1) => self.bottom_arrow_tapped(),
-                _ => {}
-            },
+            EditorAction::MoveCursor { dx, dy } => {
+                let y = self.cursor.y;
+                match (dx, dy) {
+                    (1, 0) => self.right_arrow_tapped(),
+                    (-1, 0) => self.left_arrow_tapped(),
+                    (0, 1) => self.top_arrow_tapped(),
+                    (0, -1) => self.bottom_arrow_tapped(),
+                    _ => {}
+                }
+                // Moving up on the first line, or down on the last one, fails
+                if dy != 0 && self.cursor.y == y {
+                    self.abort_macro();
+                }
+            }
+            EditorAction::ScrollHalfScreen { down } => {
+                for _ in 0..(self.screen.h / 2).max(1) {
+                    if down {
+                        self.bottom_arrow_tapped();
+                    } else {
+                        self.top_arrow_tapped();
+                    }
+                }
+            }
             EditorAction::MoveByWords { n_words } => {
-                let selection = Motion::Words { n_words }.apply(self);
-                self.set_cursor(Cursor {
-                    x: selection.1.x(),
-                    y: selection.1.y(),
-                });
+                if let Some(selection) = (Motion::Words { n_words }).apply(self) {
+                    self.set_cursor(Cursor {
+                        x: selection.1.x(),
+                        y: selection.1.y(),
+                    });
+                } else {
+                    self.abort_macro();
+                }
             }
             EditorAction::ApplyMotion { motion } => {
-                let selection = motion.apply(self);
-                self.set_cursor(Cursor {
-                    x: selection.1.x(),
-                    y: selection.1.y(),
-                });
+                if let Some(selection) = motion.apply(self) {
+                    let cursor = Cursor {
+                        x: selection.1.x(),
+                        y: selection.1.y(),
+                    };
+                    if let Some(reverse) = motion.search_reverse() {
+                        self.highlight_search = true;
+                        self.report_search_wrap(cursor, reverse);
+                    }
+                    self.set_cursor(cursor);
+                } else {
+                    if let (Some(_), Some(pattern)) = (motion.search_reverse(), &self.search) {
+                        self.status = Some(format!("Pattern not found: {}", pattern.as_str()));
+                    }
+                    self.abort_macro();
+                }
+            }
+            EditorAction::ApplyOperator { operator, motion } => {
+                self.apply_operator(operator, &motion);
             }
+            EditorAction::SelectTextObject { object } => self.select_text_object(object),
             EditorAction::SwitchToInsertMode => {
+                self.end_insert_session();
+                self.undo_redo_mgr.begin_transaction(self.cursor);
+                self.is_insert_session_open = true;
+                self.is_command_line_open = false;
                 self.editor_mode = Box::new(InsertMode {});
             }
             EditorAction::SwitchToNormalMode => {
+                self.end_insert_session();
+                if self.search_preview.take().is_some() {
+                    self.scroll_to_cursor();
+                }
+                self.is_command_line_open = false;
                 self.editor_mode = Box::new(NormalMode::new());
             }
             EditorAction::SwitchToCommandMode => {
-                self.editor_mode = Box::new(CommandMode::new());
+                self.end_insert_session();
+                self.is_command_line_open = true;
+                // Like in Vim, the command acts on the lines of the selection, which ends
+                self.editor_mode = match self.end_selection() {
+                    Some(_) => Box::new(CommandMode::with_command("'<,'>")),
+                    None => Box::new(CommandMode::new()),
+                };
             }
-            EditorAction::Save => {
-                self.save_file();
+            EditorAction::SwitchToSearchMode { backward } => {
+                self.end_insert_session();
+                self.is_command_line_open = true;
+                self.editor_mode = Box::new(SearchMode::new(backward));
             }
-            EditorAction::Exit => {
-                endwin();
-                exit(0);
+            EditorAction::Search { pattern, backward } => {
+                self.apply_editor_action(EditorAction::SwitchToNormalMode);
+                // An empty pattern searches the last pattern again
+                if pattern.is_empty() {
+                    self.search_backward = backward;
+                } else if !self.set_search(&pattern, backward) {
+                    return;
+                }
+                let motion = Motion::SearchNext { reverse: false };
+                self.apply_editor_action(EditorAction::ApplyMotion { motion });
+            }
+            EditorAction::PreviewSearch { pattern, backward } => {
+                // The pattern may be incomplete, like "\(": it is not shown until it is valid
+                self.search_preview = Pattern::new(&pattern).ok().map(|pattern| {
+                    let found = find_next(&self.buffer, &pattern, &self.cursor, backward);
+                    (pattern, found.map(|(cursor, _)| cursor))
+                });
+                match self.search_preview {
+                    Some((_, Some(found))) => self.scroll_to_line(found.y),
+                    _ => self.scroll_to_cursor(),
+                }
+            }
+            EditorAction::SearchWordUnderCursor { backward, count } => {
+                self.search_word_under_cursor(backward, count)
             }
+            EditorAction::ConfirmSubstitution { answer } => self.confirm_substitution(answer),
+            EditorAction::ExecuteCommand { command } => self.execute_command(command),
             EditorAction::CompositeAction { actions } => {
                 for action in actions {
-                    self.handle_editor_action(*action, is_undo)
+                    self.handle_editor_action(*action)
+                }
+            }
+            EditorAction::Repeat { count, action } => {
+                for _ in 0..count {
+                    self.handle_editor_action((*action).clone())
                 }
             }
             EditorAction::AbortCurrentAction => {
-                if self.selection.is_some() {
-                    self.selection = None;
+                self.end_selection();
+            }
+            EditorAction::ToggleSelection { kind } => match self.selection.as_mut() {
+                Some(selection) if selection.kind() != kind => selection.set_kind(kind),
+                Some(_) => {
+                    self.end_selection();
+                }
+                None => self.selection = Some(Selection::new(self.cursor, self.cursor, kind)),
+            },
+            EditorAction::JoinSelectedLines => {
+                if let Some(selection) = self.end_selection() {
+                    let last = selection.end().y.max(selection.start().y + 1);
+                    self.join_lines(selection.start().y, last);
                 }
             }
-            EditorAction::ToggleSelectionState => {
-                if self.selection.is_none() {
-                    self.selection = Some(Selection::new(self.cursor, self.cursor));
-                } else {
-                    self.selection = None;
-                }
-            }
-            EditorAction::Yank => {
-                if self.selection.is_some() {
-                    let mut content = "".to_string();
-                    let s = self.selection.unwrap().start().y;
-                    let e = self.selection.unwrap().end().y;
-                    for i in s..e+1 {
-                        if i == s && i == e {
-                            let x1 = self.selection.unwrap().start().x;
-                            let x2 = self.selection.unwrap().end().x+1;
-                            content.push_str(&self.lines[i][x1..x2]);
-                        } else if i == s {
-                            // Skip the beginning
-                            let x = self.selection.unwrap().start().x;
-                            let n = self.lines[i].len();
-                            content.push_str(&self.lines[i][x..n]);
-                        } else if i == e {
-                            // Skip the end
-                            let x = self.selection.unwrap().end().x+1;
-                            content.push_str(&self.lines[i][0..x]);
-                        } else {
-                            // it means we have yanked the full lines
-                            content.push_str(&self.lines[i]);
-                        }
-                    }
-                    self.yanker.yank(content);
-                    // Clear the current selection
-                    self.selection = None;
+            EditorAction::ReplaceSelection { ch } => self.replace_selection(ch),
+            EditorAction::SwapSelectionEnds => {
+                if let Some(selection) = self.selection.as_mut() {
+                    selection.swap_ends();
+                    self.cursor = selection.cursor();
                 }
             }
-            EditorAction::Put => {
-                if let Some(content) = self.yanker.get_content() {
-                    self.lines[self.cursor.y].insert_str(self.cursor.x, &content);
+            EditorAction::Reselect => {
+                if let Some(selection) = self.last_selection {
+                    // The text may have changed since: keep the selection inside the buffer
+                    let last_line = self.buffer.len_lines() - 1;
+                    let mut start = *selection.start();
+                    let mut end = *selection.end();
+                    start.y = start.y.min(last_line);
+                    end.y = end.y.min(last_line);
+                    self.cursor = end;
+                    self.selection = Some(Selection::new(start, end, selection.kind()));
                 }
             }
-            EditorAction::JumpToLine { line } => {
-                if line <= self.lines.len() {
-                    self.cursor.x = 0;
-                    self.cursor.y = line;
+            EditorAction::SetMark { mark } => {
+                self.marks.insert(mark, self.cursor);
+            }
+            EditorAction::OperateOnSelection { operator } => self.operate_on_selection(operator),
+            EditorAction::BlockInsert { append } => self.start_block_insert(append),
+            EditorAction::Put { before, count } => {
+                if let Some(mut register) = self.yanker.get(self.register.unwrap_or('"')) {
+                    // A block is repeated on each of its lines
+                    register.content = match register.kind {
+                        RegisterKind::Blockwise => register
+                            .content
+                            .split('\n')
+                            .map(|line| line.repeat(count))
+                            .collect::<Vec<String>>()
+                            .join("\n"),
+                        _ => register.content.repeat(count),
+                    };
+                    self.put(register, before);
                 }
             }
+            EditorAction::WithRegister { register, action } => {
+                let previous = self.register.replace(register);
+                self.handle_editor_action(*action);
+                self.register = previous;
+            }
+            EditorAction::ShowError { message } => {
+                self.status = Some(message);
+                self.abort_macro();
+            }
+            EditorAction::RememberCommandLine { command } => {
+                self.yanker.set_read_only(':', command);
+            }
             EditorAction::Undo => {
-                if let Some(to_undo) = self.undo_redo_mgr.undo() {
-                    // Move to the cursor position
-                    self.cursor = to_undo.1;
-                    // Apply the action
-                    self.handle_editor_action(to_undo.0, true);
-                }
+                self.travel_in_history(|undo_redo_mgr| undo_redo_mgr.undo().map(UndoMove::Undo).into_iter().collect());
+            }
+            EditorAction::Redo => {
+                self.travel_in_history(|undo_redo_mgr| undo_redo_mgr.redo().map(UndoMove::Redo).into_iter().collect());
+            }
+            EditorAction::TimeTravel { travel } => {
+                self.travel_in_history(|undo_redo_mgr| undo_redo_mgr.time_travel(travel));
             }
+            EditorAction::RepeatLastChange { count } => self.repeat_last_change(count),
+            EditorAction::RecordMacro { register } => self.start_recording(register),
+            EditorAction::PlayMacro { register, count } => self.play_macro(register, count),
             EditorAction::None => {}
         }
 
     }
 
-    fn add_character_at_cursor(&mut self, ch: u32) {
-        if let Some(ch) = std::char::from_u32(ch) {
-            // Edit the model
-            if let Some(line) = self.lines.get_mut(self.cursor.y) {
-                // Note: O(n) operation to insert inside a string ...
-                if self.cursor.x <= line.len() {
-                    // safe guards
-                    line.insert(self.cursor.x, ch);
+    /// Applies the operator on the text covered by the motion
+    fn apply_operator(&mut self, operator: Operator, motion: &Motion) {
+        let (start, end) = match motion.apply(self) {
+            Some(selection) => selection,
+            None => return self.abort_macro(),
+        };
+        let mut start = Cursor { x: start.x(), y: start.y() };
+        let mut end = Cursor { x: end.x(), y: end.y() };
+        if end < start {
+            std::mem::swap(&mut start, &mut end);
+        }
+
+        if motion.kind() == MotionKind::Linewise || operator.is_linewise() {
+            self.apply_linewise_operator(operator, start.y, end.y);
+            return;
+        }
+
+        let start_offset = self.cursor_to_offset(&start);
+        let mut end_offset = match motion.kind() {
+            MotionKind::Inclusive => self.cursor_to_offset(&Cursor { x: end.x + 1, y: end.y }),
+            _ if end.x == 0 && end.y > start.y => {
+                // An exclusive motion ending at the start of a line stops at the end of the
+                // previous line, like `dw` on the last word of a line
+                self.buffer.line_range(end.y - 1).end
+            }
+            _ => self.cursor_to_offset(&end),
+        };
+        if let Motion::Words { n_words } = motion {
+            let line = self.buffer.line(end.y);
+            let chars = graphemes(&line);
+            if *n_words > 0 && end.x > 0 && end.x < chars.len() && chars[end.x - 1] != " " {
+                // The motion did not reach the start of a word: it stopped at the end of the
+                // buffer, whose last word is included
+                end_offset = self.buffer.line_range(end.y).end;
+            }
+            if *n_words > 0 && operator == Operator::Change {
+                // Like in Vim, `cw` does not change the blanks after the word
+                let text = self.buffer.slice(start_offset..end_offset);
+                end_offset = start_offset + text.trim_end_matches([' ', '\t']).len();
+            }
+        }
+        if start_offset >= end_offset {
+            // Nothing to operate on, but a change still starts an insertion, like `ci(` on "()"
+            if operator == Operator::Change {
+                self.cursor = start;
+                self.apply_editor_action(EditorAction::SwitchToInsertMode);
+            }
+            return;
+        }
+
+        self.apply_charwise_operator(operator, start, start_offset..end_offset);
+    }
+
+    /// Applies the operator on the provided byte range, which starts at the `start` cursor
+    fn apply_charwise_operator(&mut self, operator: Operator, start: Cursor, range: Range<usize>) {
+        let text = self.buffer.slice(range.clone());
+        if let Some(transformed) = operator.transform(&text) {
+            self.replace_text(range, &transformed);
+            self.set_cursor(start);
+            return;
+        }
+        self.keep_text(operator, Register::new(text, RegisterKind::Charwise));
+        if operator == Operator::Yank {
+            self.set_cursor(start);
+            return;
+        }
+        self.replace_text(range, "");
+        self.cursor = start;
+        if operator == Operator::Change {
+            self.apply_editor_action(EditorAction::SwitchToInsertMode);
+        }
+    }
+
+    /// Applies the operator on the selection, which ends
+    fn operate_on_selection(&mut self, operator: Operator) {
+        let selection = match self.end_selection() {
+            Some(selection) => selection,
+            None => return,
+        };
+        let start = *selection.start();
+        let end = *selection.end();
+        if operator.is_linewise() {
+            return self.apply_linewise_operator(operator, start.y, end.y);
+        }
+        match selection.kind() {
+            SelectionKind::Charwise => {
+                // The end of the selection is included. On an empty line, or past the end of
+                // the line, this is the line break.
+                let end = if end.x < self.get_line_length(end.y) {
+                    Cursor { x: end.x + 1, y: end.y }
+                } else if end.y + 1 < self.buffer.len_lines() {
+                    Cursor { x: 0, y: end.y + 1 }
+                } else {
+                    Cursor { x: self.get_line_length(end.y), y: end.y }
+                };
+                let range = self.cursor_to_offset(&start)..self.cursor_to_offset(&end);
+                if range.is_empty() && operator == Operator::Change {
+                    self.cursor = start;
+                    self.apply_editor_action(EditorAction::SwitchToInsertMode);
+                } else if !range.is_empty() {
+                    self.apply_charwise_operator(operator, start, range);
+                }
+            }
+            SelectionKind::Linewise => self.apply_linewise_operator(operator, start.y, end.y),
+            SelectionKind::Blockwise => {
+                let (left, right) = selection.block_columns();
+                self.apply_blockwise_operator(operator, start.y..=end.y, left..right + 1);
+            }
+        }
+    }
+
+    /// Applies the operator on the block made of the provided lines and columns
+    fn apply_blockwise_operator(&mut self, operator: Operator, lines: RangeInclusive<usize>, columns: Range<usize>) {
+        // Byte range of the block on each line
+        let ranges: Vec<Range<usize>> = lines
+            .clone()
+            .map(|y| {
+                let line = self.buffer.line(y);
+                let n = grapheme_count(&line);
+                let line_start = self.buffer.line_to_byte(y);
+                let start = line_start + grapheme_to_byte(&line, columns.start.min(n));
+                let end = line_start + grapheme_to_byte(&line, columns.end.min(n));
+                start..end
+            })
+            .collect();
+        let text: Vec<String> = ranges.iter().map(|range| self.buffer.slice(range.clone())).collect();
+        self.keep_text(operator, Register::new(text.join("\n"), RegisterKind::Blockwise));
+
+        if operator.transform("").is_some() {
+            for (range, text) in ranges.into_iter().zip(text).rev() {
+                let transformed = operator.transform(&text).unwrap_or(text);
+                self.replace_text(range, &transformed);
+            }
+        } else if operator != Operator::Yank {
+            // From the last line, so that the ranges of the other lines stay valid
+            for range in ranges.into_iter().rev() {
+                self.replace_text(range, "");
+            }
+        }
+        let mut cursor = Cursor { x: columns.start, y: *lines.start() };
+        if operator == Operator::Change {
+            self.cursor = cursor;
+            self.block_insert = Some(BlockInsert {
+                lines: *lines.start() + 1..=*lines.end(),
+                column: columns.start,
+                pad: false,
+            });
+            self.apply_editor_action(EditorAction::SwitchToInsertMode);
+        } else {
+            self.fit_xcursor_to_line(&mut cursor);
+            self.set_cursor(cursor);
+        }
+    }
+
+    /// Starts an insertion before the block selection, or after it. The text typed is copied on
+    /// all the lines of the block when the insertion ends.
+    fn start_block_insert(&mut self, append: bool) {
+        let selection = match self.end_selection() {
+            Some(selection) => selection,
+            None => return,
+        };
+        let (left, right) = selection.block_columns();
+        let first = selection.start().y;
+        let column = if append { right + 1 } else { left };
+        let n = self.get_line_length(first);
+        if n < column {
+            // Like on the other lines, append after the end of a short line
+            let at = self.buffer.line_range(first).end;
+            self.replace_text(at..at, &" ".repeat(column - n));
+        }
+        self.cursor = Cursor { x: column, y: first };
+        self.block_insert = Some(BlockInsert {
+            lines: first + 1..=selection.end().y,
+            column,
+            pad: append,
+        });
+        self.apply_editor_action(EditorAction::SwitchToInsertMode);
+    }
+
+    /// Copies the text typed during a block insert on the other lines of the block.
+    /// Nothing is copied if the text has several lines.
+    fn finish_block_insert(&mut self, block: BlockInsert, text: &str) {
+        if text.is_empty() || text.contains('\n') {
+            return;
+        }
+        for y in block.lines {
+            let n = self.get_line_length(y);
+            if n < block.column && !block.pad {
+                continue;
+            }
+            let line_end = self.buffer.line_range(y).end;
+            let padding = " ".repeat(block.column.saturating_sub(n));
+            let at = if n < block.column {
+                line_end
+            } else {
+                self.cursor_to_offset(&Cursor { x: block.column, y })
+            };
+            self.replace_text(at..at, &(padding + text));
+        }
+    }
+
+    /// Puts the content of a register after the cursor, or before it if `before` is set.
+    /// Lines are put below the line of the cursor, or above it, and the cursor goes to the first
+    /// of them.
+    fn put(&mut self, register: Register, before: bool) {
+        if register.kind == RegisterKind::Blockwise {
+            return self.put_block(&register.content, before);
+        }
+        if register.kind == RegisterKind::Linewise {
+            let mut content = register.content;
+            if !content.ends_with('\n') {
+                content.push('\n');
+            }
+            let y = if before { self.cursor.y } else { self.cursor.y + 1 };
+            let at = if y < self.buffer.len_lines() {
+                self.buffer.line_to_byte(y)
+            } else {
+                // There is no line break after the last line: add one before the new lines
+                content.pop();
+                content.insert(0, '\n');
+                self.buffer.len_bytes()
+            };
+            self.replace_text(at..at, &content);
+            let x = first_non_blank(&self.buffer.line(y));
+            self.set_cursor(Cursor { x, y });
+            return;
+        }
+
+        let mut cursor = self.cursor;
+        if !before && cursor.x < self.get_current_line_length() {
+            cursor.x += 1;
+        }
+        let at = self.cursor_to_offset(&cursor);
+        self.replace_text(at..at, &register.content);
+        // The cursor goes on the last character put, or at the start of a text of several lines
+        if !register.content.contains('\n') && !register.content.is_empty() {
+            let end = at + register.content.len();
+            let line = self.buffer.line(cursor.y);
+            let line_start = self.buffer.line_to_byte(cursor.y);
+            cursor.x = byte_to_grapheme(&line, end - line_start).max(1) - 1;
+        }
+        self.set_cursor(cursor);
+    }
+
+    /// Puts a block after the cursor, or before it: each line of the block goes in the same
+    /// column, on the line of the cursor and the lines below.
+    fn put_block(&mut self, content: &str, before: bool) {
+        let mut column = self.cursor.x;
+        if !before && column < self.get_current_line_length() {
+            column += 1;
+        }
+        let width = content.split('\n').map(grapheme_count).max().unwrap_or(0);
+        for (i, text) in content.split('\n').enumerate() {
+            let y = self.cursor.y + i;
+            if y >= self.buffer.len_lines() {
+                let at = self.buffer.len_bytes();
+                self.replace_text(at..at, "\n");
+            }
+            let n = self.get_line_length(y);
+            let mut text = text.to_string();
+            if n < column {
+                // Short lines are padded up to the column
+                text.insert_str(0, &" ".repeat(column - n));
+            } else if n > column {
+                // The text after the block stays aligned
+                text.push_str(&" ".repeat(width - grapheme_count(&text)));
+            }
+            let at = self.cursor_to_offset(&Cursor { x: column.min(n), y });
+            self.replace_text(at..at, &text);
+        }
+        self.set_cursor(Cursor { x: column, y: self.cursor.y });
+    }
+
+    /// Keeps the text an operator acts on in the registers
+    fn keep_text(&mut self, operator: Operator, content: Register) {
+        match operator {
+            Operator::Yank => self.yanker.yank(self.register, content),
+            Operator::Delete | Operator::Change => self.yanker.delete(self.register, content),
+            _ => {}
+        }
+    }
+
+    /// Makes the pattern the last search, for `n` and `N` and for the register '/'.
+    /// Returns false if the pattern is not valid, the error being shown in the status bar.
+    fn set_search(&mut self, pattern: &str, backward: bool) -> bool {
+        match Pattern::new(pattern) {
+            Ok(pattern) => {
+                self.yanker.set_read_only('/', pattern.as_str().to_string());
+                self.search = Some(pattern);
+                self.search_backward = backward;
+                self.highlight_search = true;
+                true
+            }
+            Err(error) => {
+                self.status = Some(error);
+                self.abort_macro();
+                false
+            }
+        }
+    }
+
+    /// Searches the word under the cursor, or the first word after it, as a whole word
+    fn search_word_under_cursor(&mut self, backward: bool, count: usize) {
+        let line = self.buffer.line(self.cursor.y);
+        let range = match word_at(&line, self.cursor.x) {
+            Some(range) => range,
+            None => {
+                self.status = Some("No string under cursor".to_string());
+                return self.abort_macro();
+            }
+        };
+        if !self.set_search(&format!("\\<{}\\>", &line[range.clone()]), backward) {
+            return;
+        }
+        // Searching backward from the start of the word does not find the word itself
+        self.set_cursor(Cursor { x: byte_to_grapheme(&line, range.start), y: self.cursor.y });
+        let motion = Motion::SearchNext { reverse: false };
+        let motion = if count > 1 { Motion::Repeat { motion: Box::new(motion), count } } else { motion };
+        self.apply_editor_action(EditorAction::ApplyMotion { motion });
+    }
+
+    /// Tells the user when a search went past the end of the buffer, and continued from its start
+    fn report_search_wrap(&mut self, found: Cursor, reverse: bool) {
+        let backward = self.search_backward != reverse;
+        if backward && found >= self.cursor {
+            self.status = Some("search hit TOP, continuing at BOTTOM".to_string());
+        } else if !backward && found <= self.cursor {
+            self.status = Some("search hit BOTTOM, continuing at TOP".to_string());
+        }
+    }
+
+    /// Executes a command typed on the command line, and shows its error if it fails
+    fn execute_command(&mut self, command: Command) {
+        if let Err(message) = self.run_command(command) {
+            self.apply_editor_action(EditorAction::ShowError { message });
+        }
+    }
+
+    /// Executes an Ex command, and returns the message to show if it fails
+    fn run_command(&mut self, command: Command) -> Result<(), String> {
+        let range = command.range.as_ref();
+        match command.kind {
+            CommandKind::GotoLine => {
+                let (_, y) = self.resolve_range(range)?;
+                let x = first_non_blank(&self.buffer.line(y));
+                self.set_cursor(Cursor { x, y });
+            }
+            CommandKind::Write { file, bang } => {
+                self.write_file(file, bang);
+            }
+            CommandKind::Quit { bang } => {
+                if !bang && self.is_modified() {
+                    return Err("E37: No write since last change (add ! to override)".to_string());
+                }
+                self.exit_requested = true;
+            }
+            CommandKind::WriteQuit { file, bang, only_if_modified } => {
+                let needs_write = !only_if_modified || file.is_some() || self.is_modified();
+                if !needs_write || self.write_file(file, bang) {
+                    self.exit_requested = true;
+                }
+            }
+            CommandKind::Substitute(substitute) => {
+                let (first, last) = self.resolve_range(range)?;
+                self.substitute(first, last, substitute)?;
+            }
+            CommandKind::Registers => self.show_registers(),
+            CommandKind::NoHighlightSearch => self.highlight_search = false,
+            CommandKind::TimeTravel(travel) => self.apply_editor_action(EditorAction::TimeTravel { travel }),
+            CommandKind::Delete { register, count } => {
+                let (mut first, mut last) = self.resolve_range(range)?;
+                if let Some(count) = count {
+                    first = last;
+                    last = last.saturating_add(count - 1).min(self.buffer.len_lines() - 1);
+                }
+                let previous = self.register;
+                self.register = register.or(previous);
+                self.apply_linewise_operator(Operator::Delete, first, last);
+                self.register = previous;
+            }
+            CommandKind::Move { address } => {
+                let (first, last) = self.resolve_range(range)?;
+                let above = self.resolve_destination(&address)?;
+                self.move_lines(first, last, above)?;
+            }
+            CommandKind::Copy { address } => {
+                let (first, last) = self.resolve_range(range)?;
+                let above = self.resolve_destination(&address)?;
+                let text = self.buffer.slice(self.buffer.line_to_byte(first)..self.buffer.line_range(last).end);
+                self.insert_lines(above, &text);
+                let y = above + last - first;
+                self.set_cursor(Cursor { x: first_non_blank(&self.buffer.line(y)), y });
+            }
+            CommandKind::Normal { keys } => {
+                if range.is_none() {
+                    // Without range, the keys are typed where the cursor is
+                    self.type_normal_keys(&keys);
+                    return Ok(());
+                }
+                let (first, last) = self.resolve_range(range)?;
+                for y in first..=last.min(self.buffer.len_lines() - 1) {
+                    self.cursor = Cursor { x: 0, y };
+                    self.type_normal_keys(&keys);
+                }
+            }
+            CommandKind::Print => {
+                let (first, last) = self.resolve_range(range)?;
+                for y in first..=last {
+                    self.message.push(self.buffer.line(y));
+                }
+            }
+            CommandKind::Global { pattern, invert, command } => {
+                // Without range, all the lines are searched
+                let whole_buffer = LineRange::whole_buffer();
+                let (first, last) = self.resolve_range(Some(range.unwrap_or(&whole_buffer)))?;
+                self.global(first, last, &pattern, invert, *command)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Executes the command on each line from `first` to `last` matching the pattern, or on each
+    /// line not matching it if `invert` is set, for `:g` and `:v`.
+    ///
+    /// The lines are found before executing the command: the command is not executed on the
+    /// lines removed by the previous executions. All the executions are a single undo step.
+    fn global(&mut self, first: usize, last: usize, pattern: &str, invert: bool, command: Command) -> Result<(), String> {
+        // Like for `:s`, an empty pattern is the last search, and the pattern becomes the last search
+        let pattern = match (pattern, &self.search) {
+            ("", Some(search)) => search.as_str().to_string(),
+            ("", None) => return Err("E35: No previous regular expression".to_string()),
+            (pattern, _) => pattern.to_string(),
+        };
+        if !self.set_search(&pattern, self.search_backward) {
+            return Ok(());
+        }
+        let lines: VecDeque<usize> = match &self.search {
+            Some(search) => {
+                let buffer_lines = BufferLines::new(&self.buffer);
+                (first..=last)
+                    .filter(|y| search.find_in_line(&buffer_lines, *y).is_empty() == invert)
+                    .collect()
+            }
+            None => VecDeque::new(),
+        };
+        if lines.is_empty() && invert {
+            return Err(format!("Pattern found in every line: {pattern}"));
+        } else if lines.is_empty() {
+            return Err(format!("Pattern not found: {pattern}"));
+        }
+
+        self.global_lines = Some(lines);
+        let mut result = Ok(());
+        while let Some(y) = self.global_lines.as_mut().and_then(VecDeque::pop_front) {
+            self.cursor = Cursor { x: 0, y };
+            result = self.run_command(command.clone());
+            if result.is_err() {
+                break;
+            }
+        }
+        self.global_lines = None;
+        result
+    }
+
+    /// Types keys in normal mode, for `:normal`. An insertion or a command which the keys leave
+    /// incomplete ends, like with Escape.
+    fn type_normal_keys(&mut self, keys: &str) {
+        // The keys are a command of their own, which '.' can repeat
+        self.command_keys.clear();
+        for ch in keys.chars() {
+            self.process_key(ch.into());
+        }
+        self.end_selection();
+        self.apply_editor_action(EditorAction::SwitchToNormalMode);
+    }
+
+    /// Returns the number of lines above the place where `:m` and `:t` put lines: the lines go
+    /// below the line of the address, or above the first line for the address 0
+    fn resolve_destination(&self, address: &Address) -> Result<usize, String> {
+        if address.kind == AddressKind::Line(0) && address.offset == 0 {
+            return Ok(0);
+        }
+        Ok(self.resolve_address(address, self.cursor.y)? + 1)
+    }
+
+    /// Moves the lines from `first` to `last` (included) so that `above` lines are above them
+    fn move_lines(&mut self, first: usize, last: usize, above: usize) -> Result<(), String> {
+        if above > first && above <= last {
+            return Err("E134: Cannot move a range of lines into itself".to_string());
+        }
+        let n_lines = last - first + 1;
+        let y = if above == first || above == last + 1 {
+            // The lines are already there
+            last
+        } else {
+            let text = self.buffer.slice(self.buffer.line_to_byte(first)..self.buffer.line_range(last).end);
+            // The lines below the others move first, so that the place of the others stays valid
+            if above > last {
+                self.insert_lines(above, &text);
+                self.remove_lines(first, last);
+                above - 1
+            } else {
+                self.remove_lines(first, last);
+                self.insert_lines(above, &text);
+                above + n_lines - 1
+            }
+        };
+        self.set_cursor(Cursor { x: first_non_blank(&self.buffer.line(y)), y });
+        Ok(())
+    }
+
+    /// Inserts lines so that `above` lines are above them. The text does not end with a line
+    /// break.
+    fn insert_lines(&mut self, above: usize, text: &str) {
+        if above < self.buffer.len_lines() {
+            let at = self.buffer.line_to_byte(above);
+            self.replace_text(at..at, &format!("{text}\n"));
+        } else {
+            // There is no line break after the last line: add one before the new lines
+            let at = self.buffer.len_bytes();
+            self.replace_text(at..at, &format!("\n{text}"));
+        }
+    }
+
+    /// Removes the lines from `first` to `last` (included), with their line break
+    fn remove_lines(&mut self, first: usize, last: usize) {
+        let start_offset = self.buffer.line_to_byte(first);
+        let end_offset = self.buffer.line_range(last).end;
+        // Remove the line break after the lines, or the one before the lines if they are at the
+        // end of the buffer
+        let range = if last + 1 < self.buffer.len_lines() {
+            start_offset..self.buffer.line_to_byte(last + 1)
+        } else if first > 0 {
+            start_offset - 1..end_offset
+        } else {
+            start_offset..end_offset
+        };
+        self.replace_text(range, "");
+    }
+
+    /// Returns the line of an address, counted from 0, the current line being `current`.
+    /// Line 0 is the first line, like line 1.
+    fn resolve_address(&self, address: &Address, current: usize) -> Result<usize, String> {
+        let invalid_range = || "E16: Invalid range".to_string();
+        let line = match &address.kind {
+            AddressKind::Current => current,
+            AddressKind::Last => self.buffer.len_lines() - 1,
+            AddressKind::Line(n) => n.max(&1) - 1,
+            AddressKind::Mark(mark @ ('<' | '>')) => {
+                let selection = self.selection.or(self.last_selection).ok_or("E20: Mark not set")?;
+                if *mark == '<' { selection.start().y } else { selection.end().y }
+            }
+            AddressKind::Mark(mark) => self.marks.get(mark).ok_or("E20: Mark not set")?.y,
+            AddressKind::Search { pattern, backward } => {
+                // An empty pattern is the last search
+                let compiled;
+                let pattern = if pattern.is_empty() {
+                    self.search.as_ref().ok_or("E35: No previous regular expression")?
+                } else {
+                    compiled = Pattern::new(pattern)?;
+                    &compiled
+                };
+                find_line(&self.buffer, pattern, current, *backward)
+                    .ok_or_else(|| format!("E486: Pattern not found: {}", pattern.as_str()))?
+            }
+        };
+        let line = i64::try_from(line)
+            .ok()
+            .and_then(|line| line.checked_add(address.offset))
+            .ok_or_else(invalid_range)?;
+        if line < 0 || line >= self.buffer.len_lines() as i64 {
+            return Err(invalid_range());
+        }
+        Ok(line as usize)
+    }
+
+    /// Returns the first and the last lines of a range, or the current line if there is no range
+    fn resolve_range(&self, range: Option<&LineRange>) -> Result<(usize, usize), String> {
+        let Some(range) = range else {
+            return Ok((self.cursor.y, self.cursor.y));
+        };
+        let start = self.resolve_address(&range.start, self.cursor.y)?;
+        let current = if range.end_from_start { start } else { self.cursor.y };
+        let end = self.resolve_address(&range.end, current)?;
+        Ok((start.min(end), start.max(end)))
+    }
+
+    /// Replaces the matches of a pattern in the lines from `first` to `last`, for `:s`.
+    ///
+    /// All the replacements are a single undo step, even when they are confirmed one by one.
+    fn substitute(&mut self, first: usize, last: usize, substitute: Substitute) -> Result<(), String> {
+        // Like in Vim, an empty pattern is the last search, and the pattern becomes the last search
+        let mut pattern = match (substitute.pattern.as_str(), &self.search) {
+            ("", Some(search)) => search.as_str().to_string(),
+            ("", None) => return Err("E35: No previous regular expression".to_string()),
+            (pattern, _) => pattern.to_string(),
+        };
+        if substitute.flags.ignore_case && !pattern.starts_with("\\c") {
+            pattern.insert_str(0, "\\c");
+        }
+        let in_global = self.global_lines.is_some();
+        if substitute.flags.confirm && in_global {
+            return Err("The flag c cannot be used in :g".to_string());
+        }
+        if !self.set_search(&pattern, self.search_backward) {
+            return Ok(());
+        }
+
+        let Some(mut substitutions) = self.find_replacements(first, last, &substitute) else {
+            // In `:g`, lines without a match are not an error
+            if in_global {
+                return Ok(());
+            }
+            return Err(format!("E486: Pattern not found: {pattern}"));
+        };
+        if substitute.flags.count_only {
+            let mut lines: Vec<usize> = substitutions.left.iter().map(|replacement| replacement.line).collect();
+            lines.dedup();
+            let n_matches = plural(substitutions.left.len(), "match", "matches");
+            self.status = Some(format!("{n_matches} on {}", plural(lines.len(), "line", "lines")));
+            return Ok(());
+        }
+        if substitutions.confirm {
+            // The transaction stays open until the last answer
+            self.undo_redo_mgr.begin_transaction(self.cursor);
+            self.is_command_line_open = true;
+            self.ask_confirmation(substitutions);
+            return Ok(());
+        }
+        while !substitutions.left.is_empty() {
+            self.replace_next_match(&mut substitutions);
+        }
+        self.finish_substitutions(substitutions);
+        Ok(())
+    }
+
+    /// Finds the matches of the last search in the lines from `first` to `last`, and the text
+    /// replacing each of them. Returns None if there are no matches.
+    fn find_replacements(&self, first: usize, last: usize, substitute: &Substitute) -> Option<Substitutions> {
+        let pattern = self.search.as_ref()?;
+        let lines = BufferLines::new(&self.buffer);
+        let mut left = VecDeque::new();
+        // End of the last match: a match spanning several lines hides the matches of its lines
+        let mut after = Pos { line: first, offset: 0 };
+        for y in first..=last {
+            for found in pattern.find_in_line(&lines, y) {
+                if found.start < after {
+                    continue;
+                }
+                let text = expand_replacement(&substitute.replacement, |i| {
+                    found.group(i).map(|(start, end)| text_between(&lines, start, end)).unwrap_or_default()
+                });
+                let start = self.buffer.line_to_byte(found.start.line) + found.start.offset;
+                let end = self.buffer.line_to_byte(found.end.line) + found.end.offset;
+                left.push_back(Replacement { range: start..end, text, line: y });
+                after = found.end;
+                if !substitute.flags.global {
+                    break;
                 }
             }
-            // Move the cursor one character to the right
+        }
+        if left.is_empty() {
+            return None;
+        }
+        Some(Substitutions {
+            left,
+            shift: 0,
+            n_replaced: 0,
+            n_lines: 0,
+            last_line: None,
+            end: None,
+            confirm: substitute.flags.confirm,
+        })
+    }
+
+    /// Makes the next replacement of `:s`
+    fn replace_next_match(&mut self, substitutions: &mut Substitutions) {
+        let Some(range) = substitutions.next_range() else {
+            return;
+        };
+        let Some(replacement) = substitutions.left.pop_front() else {
+            return;
+        };
+        self.replace_text(range.clone(), &replacement.text);
+        substitutions.shift += replacement.text.len() as isize - range.len() as isize;
+        substitutions.n_replaced += 1;
+        if substitutions.last_line != Some(replacement.line) {
+            substitutions.n_lines += 1;
+            substitutions.last_line = Some(replacement.line);
+        }
+        substitutions.end = Some(range.start + replacement.text.len());
+    }
+
+    /// Moves the cursor to the next match of `:s///c`, and asks whether to replace it.
+    /// When there are no matches left, the substitution ends.
+    fn ask_confirmation(&mut self, substitutions: Substitutions) {
+        let (Some(range), Some(replacement)) = (substitutions.next_range(), substitutions.left.front()) else {
+            return self.finish_substitutions(substitutions);
+        };
+        self.editor_mode = Box::new(ConfirmMode::new(replacement.text.clone()));
+        let cursor = self.offset_to_cursor(range.start);
+        self.set_cursor(cursor);
+        self.substitutions = Some(substitutions);
+    }
+
+    /// Handles the answer of the user to the question of `:s///c`
+    fn confirm_substitution(&mut self, answer: ConfirmAnswer) {
+        let Some(mut substitutions) = self.substitutions.take() else {
+            return;
+        };
+        match answer {
+            ConfirmAnswer::Yes => self.replace_next_match(&mut substitutions),
+            ConfirmAnswer::No => {
+                substitutions.left.pop_front();
+            }
+            ConfirmAnswer::All => {
+                while !substitutions.left.is_empty() {
+                    self.replace_next_match(&mut substitutions);
+                }
+            }
+            ConfirmAnswer::Last => {
+                self.replace_next_match(&mut substitutions);
+                substitutions.left.clear();
+            }
+            ConfirmAnswer::Quit => substitutions.left.clear(),
+        }
+        self.ask_confirmation(substitutions);
+    }
+
+    /// Reports the number of replacements made by `:s`, and moves the cursor to the line of the
+    /// last one
+    fn finish_substitutions(&mut self, substitutions: Substitutions) {
+        if let Some(end) = substitutions.end {
+            let y = self.buffer.byte_to_line(end);
+            let x = first_non_blank(&self.buffer.line(y));
+            self.set_cursor(Cursor { x, y });
+            // In `:g`, the count of a single line would hide the others
+            if self.global_lines.is_none() {
+                let n_replaced = plural(substitutions.n_replaced, "substitution", "substitutions");
+                self.status = Some(format!("{n_replaced} on {}", plural(substitutions.n_lines, "line", "lines")));
+            }
+        }
+        if substitutions.confirm {
+            // The replacements were confirmed one by one: close their transaction
+            self.apply_editor_action(EditorAction::SwitchToNormalMode);
+            self.undo_redo_mgr.end_transaction(self.cursor);
+        }
+    }
+
+    /// Ends the selection, which `gv` can select again, and returns it
+    fn end_selection(&mut self) -> Option<Selection> {
+        let selection = self.selection.take()?;
+        self.last_selection = Some(selection);
+        Some(selection)
+    }
+
+    /// Joins the lines from `first` to `last` (included) into one line. The leading blanks of
+    /// the joined lines are replaced by a space.
+    fn join_lines(&mut self, first: usize, last: usize) {
+        let last = last.min(self.buffer.len_lines() - 1);
+        for _ in first..last {
+            let line = self.buffer.line(first);
+            let next_line = self.buffer.line(first + 1);
+            let n_blanks = next_line.len() - next_line.trim_start_matches([' ', '\t']).len();
+            // No space is added after a blank, or before an empty line
+            let separator = if line.is_empty() || line.ends_with([' ', '\t']) || n_blanks == next_line.len() {
+                ""
+            } else {
+                " "
+            };
+            let end = self.buffer.line_range(first).end;
+            let next_start = self.buffer.line_to_byte(first + 1);
+            self.replace_text(end..next_start + n_blanks, separator);
+            self.cursor = Cursor { x: grapheme_count(&line), y: first };
+        }
+    }
+
+    /// Replaces every character of the selection by the provided one. The line breaks are kept.
+    fn replace_selection(&mut self, ch: char) {
+        let selection = match self.end_selection() {
+            Some(selection) => selection,
+            None => return,
+        };
+        for y in selection.start().y..=selection.end().y {
+            let line = self.buffer.line(y);
+            let range = selection.line_range(y, grapheme_count(&line));
+            if range.is_empty() {
+                continue;
+            }
+            let line_start = self.buffer.line_to_byte(y);
+            let start = line_start + grapheme_to_byte(&line, range.start);
+            let end = line_start + grapheme_to_byte(&line, range.end);
+            self.replace_text(start..end, &ch.to_string().repeat(range.len()));
+        }
+        let mut cursor = *selection.start();
+        match selection.kind() {
+            SelectionKind::Charwise => {}
+            SelectionKind::Linewise => cursor.x = 0,
+            SelectionKind::Blockwise => cursor.x = selection.block_columns().0,
+        }
+        self.fit_xcursor_to_line(&mut cursor);
+        self.set_cursor(cursor);
+    }
+
+    /// Shifts the lines from `first` to `last` (included) to the right or to the left. Empty
+    /// lines are not shifted right.
+    fn shift_lines(&mut self, first: usize, last: usize, right: bool) {
+        for y in first..=last {
+            let line = self.buffer.line(y);
+            let line_start = self.buffer.line_to_byte(y);
+            if right {
+                if !line.is_empty() {
+                    self.replace_text(line_start..line_start, &" ".repeat(SHIFT_WIDTH));
+                }
+            } else if line.starts_with('\t') {
+                self.replace_text(line_start..line_start + 1, "");
+            } else {
+                let n_spaces = line.len() - line.trim_start_matches(' ').len();
+                self.replace_text(line_start..line_start + n_spaces.min(SHIFT_WIDTH), "");
+            }
+        }
+    }
+
+    /// Lists the registers which are not empty, with the kind of their content: 'c' for
+    /// charwise, 'l' for linewise and 'b' for blockwise
+    fn show_registers(&mut self) {
+        self.message = vec!["Type Name Content".to_string()];
+        for (name, register) in self.yanker.list() {
+            let kind = match register.kind {
+                RegisterKind::Charwise => 'c',
+                RegisterKind::Linewise => 'l',
+                RegisterKind::Blockwise => 'b',
+            };
+            // Line breaks are shown as ^J, like in Vim
+            let content = register.content.replace('\n', "^J");
+            self.message.push(format!("  {kind}  \"{name}   {content}"));
+        }
+    }
+
+    /// Selects the text object around the cursor, and moves the cursor at its end
+    fn select_text_object(&mut self, object: TextObject) {
+        let (start, end) = match (Motion::TextObject { object }).apply(self) {
+            Some(selection) => selection,
+            None => return self.abort_macro(),
+        };
+        let start = Cursor { x: start.x(), y: start.y() };
+        // The end of a selection is included, while the end of the object is not
+        let end = if object.is_linewise() {
+            Cursor { x: self.get_line_length(end.y()).max(1) - 1, y: end.y() }
+        } else if end.x() > 0 {
+            Cursor { x: end.x() - 1, y: end.y() }
+        } else if end.y() > start.y {
+            Cursor { x: self.get_line_length(end.y() - 1).max(1) - 1, y: end.y() - 1 }
+        } else {
+            return;
+        };
+        self.selection = Some(Selection::new(start, end, SelectionKind::Charwise));
+        self.cursor = end;
+    }
+
+    /// Applies the operator on all the lines from `first` to `last` (included)
+    fn apply_linewise_operator(&mut self, operator: Operator, first: usize, last: usize) {
+        let start_offset = self.buffer.line_to_byte(first);
+        let end_offset = self.buffer.line_range(last).end;
+        // Yanked lines always end with a line break
+        let text = self.buffer.slice(start_offset..end_offset) + "\n";
+        if let Some(transformed) = operator.transform(&text) {
+            self.replace_text(start_offset..end_offset, transformed.trim_end_matches('\n'));
+            let mut cursor = Cursor { x: self.cursor.x, y: first };
+            self.fit_xcursor_to_line(&mut cursor);
+            self.cursor = cursor;
+            return;
+        }
+        self.keep_text(operator, Register::new(text, RegisterKind::Linewise));
+
+        match operator {
+            Operator::Yank => {
+                let mut cursor = Cursor { x: self.cursor.x, y: first };
+                self.fit_xcursor_to_line(&mut cursor);
+                self.cursor = cursor;
+            }
+            Operator::Delete => {
+                self.remove_lines(first, last);
+                self.cursor = Cursor { x: 0, y: first.min(self.buffer.len_lines() - 1) };
+            }
+            Operator::Change => {
+                // The lines are replaced by an empty line
+                self.replace_text(start_offset..end_offset, "");
+                self.cursor = Cursor { x: 0, y: first };
+                self.apply_editor_action(EditorAction::SwitchToInsertMode);
+            }
+            Operator::Indent | Operator::Outdent => {
+                self.shift_lines(first, last, operator == Operator::Indent);
+                let x = first_non_blank(&self.buffer.line(first));
+                self.cursor = Cursor { x, y: first };
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the offset in the buffer of the provided cursor.
+    /// If the cursor is beyond the end of its line, returns the offset of the end of the line.
+    fn cursor_to_offset(&self, cursor: &Cursor) -> usize {
+        let line = self.buffer.line(cursor.y);
+        self.buffer.line_to_byte(cursor.y) + grapheme_to_byte(&line, cursor.x)
+    }
+
+    /// Returns the cursor at the provided offset of the buffer
+    fn offset_to_cursor(&self, offset: usize) -> Cursor {
+        let pos = self.offset_to_pos(offset);
+        Cursor { x: byte_to_grapheme(&self.buffer.line(pos.line), pos.offset), y: pos.line }
+    }
+
+    /// Returns the line and the offset in the line of the provided offset of the buffer
+    fn offset_to_pos(&self, offset: usize) -> Pos {
+        let line = self.buffer.byte_to_line(offset);
+        Pos { line, offset: offset - self.buffer.line_to_byte(line) }
+    }
+
+    /// Closes the undo transaction of the insert session, if it is open
+    fn end_insert_session(&mut self) {
+        if self.is_insert_session_open {
+            self.is_insert_session_open = false;
+            let inserted_text = std::mem::take(&mut self.inserted_text);
+            if let Some(block) = self.block_insert.take() {
+                self.finish_block_insert(block, &inserted_text);
+            }
+            self.yanker.set_read_only('.', inserted_text);
+            self.undo_redo_mgr.end_transaction(self.cursor);
+        }
+    }
+
+    /// Replaces the text found in the provided byte range with the provided text.
+    ///
+    /// Every modification of the buffer must go through this function, so that it is recorded by
+    /// the undo redo manager.
+    fn replace_text(&mut self, range: Range<usize>, text: &str) {
+        let change = TextChange {
+            offset: range.start,
+            removed: self.buffer.slice(range),
+            inserted: text.to_string(),
+        };
+        self.shift_marks(&change);
+        self.apply_change(&change);
+        self.undo_redo_mgr.record(change);
+        self.n_changes += 1;
+    }
+
+    /// Keeps the marks, and the lines left to `:g`, on their lines when a change adds or removes
+    /// lines above them.
+    ///
+    /// A line is removed when its line break is: a change starting at the start of a line
+    /// removes this line and the next ones, while a change starting inside a line joins the next
+    /// ones to it. The marks of the removed lines are removed, like in Vim.
+    fn shift_marks(&mut self, change: &TextChange) {
+        let n_removed = change.removed.matches('\n').count();
+        let n_inserted = change.inserted.matches('\n').count();
+        if n_removed == 0 && n_inserted == 0 {
+            return;
+        }
+        let line = self.buffer.byte_to_line(change.offset);
+        let first = if change.offset == self.buffer.line_to_byte(line) { line } else { line + 1 };
+        let shift = |y: usize| {
+            if y < first {
+                Some(y)
+            } else if y < first + n_removed {
+                None
+            } else {
+                Some(y + n_inserted - n_removed)
+            }
+        };
+        self.marks.retain(|_, mark| match shift(mark.y) {
+            Some(y) => {
+                mark.y = y;
+                true
+            }
+            None => false,
+        });
+        if let Some(lines) = self.global_lines.as_mut() {
+            *lines = lines.iter().filter_map(|y| shift(*y)).collect();
+        }
+    }
+
+    /// Moves in the undo tree, with the moves returned by `travel`.
+    /// In a macro or `:normal`, the changes made so far are in a step that is still open: it is
+    /// finished first, so that it can be undone, and the next changes start a new step.
+    fn travel_in_history(&mut self, travel: impl FnOnce(&mut UndoRedoManager) -> Vec<UndoMove>) {
+        self.undo_redo_mgr.commit_open_step(self.cursor);
+        for undo_move in travel(&mut self.undo_redo_mgr) {
+            self.apply_undo_move(undo_move);
+        }
+        self.undo_redo_mgr.commit_open_step(self.cursor);
+    }
+
+    /// Undoes or redoes a step of the undo tree, and restores the cursor accordingly
+    fn apply_undo_move(&mut self, undo_move: UndoMove) {
+        match undo_move {
+            UndoMove::Undo(step) => {
+                for change in step.changes.iter().rev() {
+                    self.apply_change(&change.inverted());
+                }
+                self.cursor = step.cursor_before;
+            }
+            UndoMove::Redo(step) => {
+                for change in step.changes.iter() {
+                    self.apply_change(change);
+                }
+                self.cursor = step.cursor_after;
+            }
+        }
+    }
+
+    /// Applies a change to the buffer, without recording it
+    fn apply_change(&mut self, change: &TextChange) {
+        self.buffer.remove(change.offset..change.offset + change.removed.len());
+        self.buffer.insert(change.offset, &change.inserted);
+    }
+
+    fn add_character_at_cursor(&mut self, ch: char) {
+        // Edit the model
+        if self.cursor.x <= self.get_current_line_length() {
+            // safe guards
+            let at = self.cursor_to_offset(&self.cursor);
+            self.replace_text(at..at, ch.encode_utf8(&mut [0; 4]));
+            // Move the cursor after the inserted character.
+            // A combining character is merged with the previous grapheme, in which case the
+            // cursor does not move.
+            let line_start = self.buffer.line_to_byte(self.cursor.y);
+            let line = self.buffer.line(self.cursor.y);
+            self.cursor.x = byte_to_grapheme(&line, at + ch.len_utf8() - line_start);
+        } else {
             self.cursor.x += 1;
         }
     }
 
     fn remove_character_at_cursor(&mut self) {
-        // If x is bigger than 0, it means we remove exactly 1 character
+        // If x is bigger than 0, it means we remove exactly 1 grapheme
         if self.cursor.x > 0 {
-            let line = &mut self.lines[self.cursor.y];
-            if (self.cursor.x) <= line.len() {
-                // Delete the current character
-                line.remove(self.cursor.x - 1);
+            if self.cursor.x <= self.get_current_line_length() {
+                // Delete the previous grapheme
+                let start = self.cursor_to_offset(&Cursor { x: self.cursor.x - 1, y: self.cursor.y });
+                let end = self.cursor_to_offset(&self.cursor);
+                self.replace_text(start..end, "");
                 self.cursor.x -= 1;
             }
         } else if self.cursor.x == 0 && self.cursor.y > 0 {
-            // Delete the current line, append its content to the previous line
-            let line = self.lines.remove(self.cursor.y);
-            let previous_line = &mut self.lines[self.cursor.y - 1];
-            let dx = previous_line.len();
-            previous_line.push_str(line.as_str());
+            // Delete the line break, which appends the current line to the previous line
+            let dx = self.get_line_length(self.cursor.y - 1);
+            let at = self.buffer.line_to_byte(self.cursor.y);
+            self.replace_text(at - 1..at, "");
             self.cursor.y -= 1;
             self.cursor.x = dx;
         }
@@ -393,28 +1870,140 @@ impl EditorModel {
 
     /// Called upon enter key pressed. Asks for adding a break line at current position
     fn add_new_line(&mut self) {
-        // Get the part of the current line that is after the cursor
-        let line = &self.lines[self.cursor.y];
-        if self.cursor.x <= line.len() {
-            let tmp = line.split_at(self.cursor.x);
-            let old_line = tmp.0.to_string();
-            let new_line = tmp.1.to_string();
-            // Rewrite the old lines
-            self.lines[self.cursor.y] = old_line;
-            // Add the new line
-            self.lines.insert(self.cursor.y + 1, new_line);
-        } else {
-            // Simply add a blank line
-            self.lines.insert(self.cursor.y + 1, "".to_string());
-        }
+        // If the cursor is beyond the line, a blank line is simply added after it
+        let at = self.cursor_to_offset(&self.cursor);
+        self.replace_text(at..at, "\n");
         // Move the cursor below
         self.bottom_arrow_tapped();
         // Enforce the cursor to zero
         self.cursor.x = 0;
     }
 
-    pub fn key_tapped(&mut self, ch: u32) {
-        let action = self.editor_mode.key_tapped(ch);
-        self.handle_editor_action(action, false);
+    pub fn key_tapped(&mut self, key: Key) {
+        self.message.clear();
+        self.status = None;
+        if self.recording_register.is_some() {
+            // 'q' stops the recording, unless it is part of a command such as "fq"
+            let is_normal_mode = !self.is_insert_session_open && !self.is_command_line_open;
+            if key == Key::Char('q') && is_normal_mode && !self.editor_mode.is_pending() {
+                self.stop_recording();
+                return;
+            }
+            self.recorded_keys.push(key);
+        }
+        self.process_key(key);
+        self.play_macro_keys();
+    }
+
+    /// Sends a key to the editor mode, and handles the resulting action
+    fn process_key(&mut self, key: Key) {
+        self.editor_mode.set_selection_kind(self.selection.map(|selection| selection.kind()));
+        let action = self.editor_mode.key_tapped(key);
+        if let EditorAction::RepeatLastChange { .. } | EditorAction::PlayMacro { .. } = action {
+            // The replayed keys are recorded again, but not the '.' or the "@a" itself
+            self.command_keys.clear();
+            self.handle_editor_action(action);
+            return;
+        }
+
+        if self.command_keys.is_empty() {
+            self.n_changes_before_command = self.n_changes;
+        }
+        self.command_keys.push(key);
+        let was_command_line_open = self.is_command_line_open;
+        self.handle_editor_action(action);
+        self.record_change_keys(was_command_line_open);
+    }
+
+    /// Once a command is complete, keeps its keys if it changed the buffer, so that '.' can
+    /// repeat it
+    fn record_change_keys(&mut self, was_command_line_open: bool) {
+        if was_command_line_open || self.is_command_line_open {
+            self.command_keys.clear();
+            return;
+        }
+        if self.editor_mode.is_pending() || self.is_insert_session_open {
+            // The command is not complete yet
+            return;
+        }
+        if self.n_changes != self.n_changes_before_command {
+            self.last_change_keys = std::mem::take(&mut self.command_keys);
+        } else {
+            self.command_keys.clear();
+        }
+    }
+
+    /// Replays the keys of the last change.
+    /// If a count is provided, it replaces the count of the change.
+    fn repeat_last_change(&mut self, count: Option<usize>) {
+        let mut keys = self.last_change_keys.clone();
+        if let Some(count) = count {
+            let n_digits = keys
+                .iter()
+                .take_while(|key| matches!(key, Key::Char(ch) if ch.is_ascii_digit()))
+                .count();
+            keys.splice(0..n_digits, count.to_string().chars().map(Key::Char));
+        }
+
+        for key in keys {
+            self.process_key(key);
+        }
+    }
+
+    /// Starts recording the typed keys in a macro register.
+    /// An uppercase register appends the keys to the macro of the lowercase one.
+    fn start_recording(&mut self, register: char) {
+        let lowercase = register.to_ascii_lowercase();
+        self.recorded_keys = if register.is_ascii_uppercase() {
+            self.macros.get(&lowercase).cloned().unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        self.recording_register = Some(lowercase);
+    }
+
+    fn stop_recording(&mut self) {
+        if let Some(register) = self.recording_register.take() {
+            let keys = std::mem::take(&mut self.recorded_keys);
+            self.macros.insert(register, keys);
+        }
+    }
+
+    /// Queues the keys of a macro `count` times, before the keys that are still to be played.
+    /// This way, a macro can play itself without growing the stack.
+    fn play_macro(&mut self, register: Option<char>, count: usize) {
+        let register = match register.or(self.last_played_macro) {
+            Some(register) => register.to_ascii_lowercase(),
+            None => return,
+        };
+        self.last_played_macro = Some(register);
+        self.n_macro_plays += count;
+        if self.n_macro_plays > MAX_MACRO_PLAYS {
+            return self.abort_macro();
+        }
+        if let Some(keys) = self.macros.get(&register) {
+            for _ in 0..count {
+                for key in keys.iter().rev() {
+                    self.macro_keys.push_front(*key);
+                }
+            }
+        }
+    }
+
+    /// Processes the keys of the macros being played, as if they were typed.
+    /// All the changes made by the macros are a single undo step.
+    fn play_macro_keys(&mut self) {
+        self.undo_redo_mgr.begin_transaction(self.cursor);
+        while let Some(key) = self.macro_keys.pop_front() {
+            self.process_key(key);
+        }
+        self.undo_redo_mgr.end_transaction(self.cursor);
+        self.n_macro_plays = 0;
+    }
+
+    /// Called when a motion fails, for instance 'j' on the last line: the macros being played
+    /// stop there
+    fn abort_macro(&mut self) {
+        self.macro_keys.clear();
     }
 }
@@ -0,0 +1,507 @@
+use crate::substitute::Substitute;
+use crate::undo_redo::TimeTravel;
+use crate::yanker::Yanker;
+
+/// The line an address of an Ex command starts from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AddressKind {
+    /// `.`, or an address made of an offset only such as `+2`
+    Current,
+    /// `$`
+    Last,
+    /// A line number, counted from 1
+    Line(usize),
+    /// `'a`, the line of a mark. `'<` and `'>` are the start and the end of the last selection.
+    Mark(char),
+    /// `/pattern/`, the next line matching the pattern, or `?pattern?` if `backward` is set.
+    /// An empty pattern is the last search.
+    Search { pattern: String, backward: bool },
+}
+
+/// A line given to an Ex command, such as `.`, `$-1`, `'a` or `/foo/+1`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Address {
+    pub kind: AddressKind,
+    /// Number of lines added to the line, from the `+N` and `-N` after it
+    pub offset: i64,
+}
+
+impl Address {
+    pub fn new(kind: AddressKind) -> Self {
+        Self { kind, offset: 0 }
+    }
+}
+
+/// The lines an Ex command acts on, such as `%` or `'<,'>`. Both ends are included.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LineRange {
+    pub start: Address,
+    pub end: Address,
+    /// Set when the addresses are separated by `;` instead of `,`: the end is then relative to
+    /// the start instead of the current line, like in `/foo/;+2`
+    pub end_from_start: bool,
+}
+
+impl LineRange {
+    /// The range of `%`, from the first line to the last one
+    pub fn whole_buffer() -> Self {
+        Self {
+            start: Address::new(AddressKind::Line(1)),
+            end: Address::new(AddressKind::Last),
+            end_from_start: false,
+        }
+    }
+}
+
+/// A command typed on the command line, parsed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Command {
+    /// The lines given to the command. Without range, commands act on the current line.
+    pub range: Option<LineRange>,
+    pub kind: CommandKind,
+}
+
+/// The Ex commands, with their arguments
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CommandKind {
+    /// A range alone, like `:12` or `:$`, goes to its last line
+    GotoLine,
+    /// `:w [file]`. `:w!` writes even if the file exists.
+    Write { file: Option<String>, bang: bool },
+    /// `:q`. `:q!` quits even if the changes are not written.
+    Quit { bang: bool },
+    /// `:wq [file]`, or `:x [file]` which only writes if there are changes
+    WriteQuit { file: Option<String>, bang: bool, only_if_modified: bool },
+    /// `:s/pattern/replacement/flags`
+    Substitute(Substitute),
+    /// `:registers` or `:display`
+    Registers,
+    /// `:nohlsearch`
+    NoHighlightSearch,
+    /// `:earlier {N}` or `:later {N}`
+    TimeTravel(TimeTravel),
+    /// `:d [x] [count]`, which deletes the lines in the register x or in the unnamed register.
+    /// With a count, it deletes `count` lines from the last line of the range.
+    Delete { register: Option<char>, count: Option<usize> },
+    /// `:m {address}`, which moves the lines below the line of the address. The address 0 is
+    /// above the first line.
+    Move { address: Address },
+    /// `:t {address}` or `:co {address}`, which copies the lines below the line of the address
+    Copy { address: Address },
+    /// `:normal {keys}`, which types the keys in normal mode on each line
+    Normal { keys: String },
+    /// `:p`, which shows the lines
+    Print,
+    /// `:g/pattern/command`, which executes the command on each line matching the pattern, or
+    /// on each line not matching it if `invert` is set (`:g!` and `:v`)
+    Global { pattern: String, invert: bool, command: Box<Command> },
+}
+
+/// The names of the commands, with the number of letters they can be abbreviated to, like in Vim
+const COMMAND_NAMES: [(&str, usize); 19] = [
+    ("delete", 1),
+    ("move", 1),
+    ("copy", 2),
+    ("t", 1),
+    ("normal", 4),
+    ("print", 1),
+    ("global", 1),
+    ("vglobal", 1),
+    ("write", 1),
+    ("quit", 1),
+    ("wq", 2),
+    ("xit", 1),
+    ("exit", 3),
+    ("substitute", 1),
+    ("registers", 3),
+    ("display", 2),
+    ("nohlsearch", 3),
+    ("earlier", 2),
+    ("later", 3),
+];
+
+/// Parses a command line, such as `'<,'>s/a/b/g` or `w! notes.txt`.
+/// Returns None if there is nothing to execute, and the message to show if the command is not
+/// valid.
+pub fn parse_command(text: &str) -> Result<Option<Command>, String> {
+    let (range, rest) = parse_range(text)?;
+    let rest = rest.trim_start();
+    if rest.is_empty() {
+        return Ok(range.map(|range| Command { range: Some(range), kind: CommandKind::GotoLine }));
+    }
+
+    let name_end = rest.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(rest.len());
+    let (name, rest) = rest.split_at(name_end);
+    let full_name = COMMAND_NAMES
+        .iter()
+        .find(|(full_name, min_len)| name.len() >= *min_len && full_name.starts_with(name))
+        .map(|(full_name, _)| *full_name)
+        .ok_or_else(|| format!("E492: Not an editor command: {}", text.trim()))?;
+    let (bang, rest) = match rest.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+    let argument = rest.trim();
+    let file = (!argument.is_empty()).then(|| argument.to_string());
+
+    let kind = match full_name {
+        "write" => CommandKind::Write { file, bang },
+        "quit" => CommandKind::Quit { bang },
+        "wq" => CommandKind::WriteQuit { file, bang, only_if_modified: false },
+        "xit" | "exit" => CommandKind::WriteQuit { file, bang, only_if_modified: true },
+        // The pattern starts right after the name, its delimiter may be a blank
+        "substitute" => CommandKind::Substitute(Substitute::parse(rest.trim_start())?),
+        "registers" | "display" => CommandKind::Registers,
+        "nohlsearch" => CommandKind::NoHighlightSearch,
+        "earlier" | "later" => {
+            let direction = if full_name == "earlier" { -1 } else { 1 };
+            let travel = parse_time_travel(argument, direction)
+                .ok_or_else(|| format!("E475: Invalid argument: {argument}"))?;
+            CommandKind::TimeTravel(travel)
+        }
+        "delete" => {
+            // The register comes first, a number being the count
+            let mut chars = argument.chars();
+            let register = match chars.next() {
+                Some(register) if !register.is_ascii_digit() => {
+                    if !Yanker::is_writable(register) {
+                        return Err(format!("E354: Invalid register name: '{register}'"));
+                    }
+                    Some(register)
+                }
+                _ => None,
+            };
+            let rest = if register.is_some() { chars.as_str().trim_start() } else { argument };
+            let (count, rest) = if rest.starts_with(|c: char| c.is_ascii_digit()) {
+                let (count, rest) = parse_number(rest)?;
+                (Some(count), rest.trim_start())
+            } else {
+                (None, rest)
+            };
+            if count == Some(0) {
+                return Err("E939: Positive count required".to_string());
+            }
+            if !rest.is_empty() {
+                return Err(format!("E488: Trailing characters: {rest}"));
+            }
+            CommandKind::Delete { register, count }
+        }
+        "move" | "copy" | "t" => {
+            let address = match parse_address(argument)? {
+                (Some(address), "") => address,
+                _ => return Err("E14: Invalid address".to_string()),
+            };
+            if full_name == "move" {
+                CommandKind::Move { address }
+            } else {
+                CommandKind::Copy { address }
+            }
+        }
+        // The keys are typed as they are, including their trailing blanks
+        "normal" => match rest.trim_start() {
+            "" => return Err("E471: Argument required".to_string()),
+            keys => CommandKind::Normal { keys: keys.to_string() },
+        },
+        "print" => CommandKind::Print,
+        "global" | "vglobal" => parse_global(rest.trim_start(), bang || full_name == "vglobal")?,
+        _ => unreachable!("{full_name} is in COMMAND_NAMES"),
+    };
+
+    let takes_range = matches!(
+        kind,
+        CommandKind::Substitute(_)
+            | CommandKind::Delete { .. }
+            | CommandKind::Move { .. }
+            | CommandKind::Copy { .. }
+            | CommandKind::Normal { .. }
+            | CommandKind::Print
+            | CommandKind::Global { .. }
+    );
+    // `:normal!` is the same as `:normal`, since there are no mappings
+    let takes_bang = matches!(
+        kind,
+        CommandKind::Write { .. }
+            | CommandKind::Quit { .. }
+            | CommandKind::WriteQuit { .. }
+            | CommandKind::Normal { .. }
+    ) || full_name == "global";
+    let takes_argument = !matches!(
+        kind,
+        CommandKind::Quit { .. } | CommandKind::Registers | CommandKind::NoHighlightSearch | CommandKind::Print
+    );
+    if range.is_some() && !takes_range {
+        return Err("E481: No range allowed".to_string());
+    }
+    if bang && !takes_bang {
+        return Err("E477: No ! allowed".to_string());
+    }
+    if !argument.is_empty() && !takes_argument {
+        return Err(format!("E488: Trailing characters: {argument}"));
+    }
+    Ok(Some(Command { range, kind }))
+}
+
+/// Parses the arguments of `:g`, such as `/pattern/d`. Like for `:s`, the first character is the
+/// delimiter. Without command, the matching lines are shown.
+fn parse_global(args: &str, invert: bool) -> Result<CommandKind, String> {
+    let mut chars = args.chars();
+    let delimiter = match chars.next() {
+        None => return Err("E35: No previous regular expression".to_string()),
+        Some(c) if c.is_alphanumeric() || matches!(c, '\\' | '"' | '|') => {
+            return Err("E146: Regular expressions can't be delimited by letters".to_string())
+        }
+        Some(c) => c,
+    };
+    let (pattern, rest) = split_pattern(chars.as_str(), delimiter);
+    let command = match parse_command(rest)? {
+        Some(Command { kind: CommandKind::Global { .. }, .. }) => {
+            return Err("E147: Cannot do :global recursive".to_string())
+        }
+        Some(command) => command,
+        None => Command { range: None, kind: CommandKind::Print },
+    };
+    Ok(CommandKind::Global { pattern, invert, command: Box::new(command) })
+}
+
+/// Parses the argument of `:earlier` and `:later`, the direction being -1 for `:earlier`.
+///
+/// It is a number of changes, or a duration if it is followed by `s`, `m`, `h` or `d`
+/// (seconds, minutes, hours or days). It defaults to 1.
+fn parse_time_travel(argument: &str, direction: i64) -> Option<TimeTravel> {
+    let unit_start = argument
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(argument.len());
+    let (number, unit) = argument.split_at(unit_start);
+    let n: i64 = if number.is_empty() { 1 } else { number.parse().ok()? };
+    let n = n.checked_mul(direction)?;
+    match unit {
+        "" => Some(TimeTravel::Steps(n)),
+        "s" => Some(TimeTravel::Seconds(n)),
+        "m" => Some(TimeTravel::Seconds(n.checked_mul(60)?)),
+        "h" => Some(TimeTravel::Seconds(n.checked_mul(3600)?)),
+        "d" => Some(TimeTravel::Seconds(n.checked_mul(86400)?)),
+        _ => None,
+    }
+}
+
+/// Parses the range at the start of a command line, such as `%` in `%s/a/b/`.
+/// Returns the range if there is one, and the rest of the command line.
+pub fn parse_range(text: &str) -> Result<(Option<LineRange>, &str), String> {
+    let text = text.trim_start_matches([' ', ':']);
+    if let Some(rest) = text.strip_prefix('%') {
+        return Ok((Some(LineRange::whole_buffer()), rest));
+    }
+    let (start, rest) = match parse_address(text)? {
+        (Some(start), rest) => (start, rest),
+        (None, rest) => return Ok((None, rest)),
+    };
+    let rest = rest.trim_start();
+    let (end_from_start, rest) = if let Some(rest) = rest.strip_prefix(',') {
+        (false, rest)
+    } else if let Some(rest) = rest.strip_prefix(';') {
+        (true, rest)
+    } else {
+        let range = LineRange { start: start.clone(), end: start, end_from_start: false };
+        return Ok((Some(range), rest));
+    };
+    // Like in Vim, a missing address after the separator is the current line
+    let (end, rest) = parse_address(rest)?;
+    let end = end.unwrap_or(Address::new(AddressKind::Current));
+    Ok((Some(LineRange { start, end, end_from_start }), rest))
+}
+
+/// Parses an address, and returns it with the rest of the text
+fn parse_address(text: &str) -> Result<(Option<Address>, &str), String> {
+    let text = text.trim_start();
+    let mut chars = text.chars();
+    let (kind, mut rest) = match chars.next() {
+        Some('.') => (Some(AddressKind::Current), chars.as_str()),
+        Some('$') => (Some(AddressKind::Last), chars.as_str()),
+        Some('\'') => match chars.next() {
+            Some(mark) if mark.is_ascii_lowercase() || mark == '<' || mark == '>' => {
+                (Some(AddressKind::Mark(mark)), chars.as_str())
+            }
+            _ => return Err("E78: Unknown mark".to_string()),
+        },
+        Some(delimiter @ ('/' | '?')) => {
+            let (pattern, rest) = split_pattern(chars.as_str(), delimiter);
+            (Some(AddressKind::Search { pattern, backward: delimiter == '?' }), rest)
+        }
+        Some(c) if c.is_ascii_digit() => {
+            let (n, rest) = parse_number(text)?;
+            (Some(AddressKind::Line(n)), rest)
+        }
+        _ => (None, text),
+    };
+
+    // The offsets add up: `.+2-1` is the next line
+    let invalid_range = || "E16: Invalid range".to_string();
+    let mut offset: i64 = 0;
+    let mut has_offset = false;
+    while let Some(sign) = rest.chars().next().filter(|c| *c == '+' || *c == '-') {
+        has_offset = true;
+        rest = &rest[1..];
+        let n = if rest.starts_with(|c: char| c.is_ascii_digit()) {
+            let (n, after) = parse_number(rest)?;
+            rest = after;
+            i64::try_from(n).map_err(|_| invalid_range())?
+        } else {
+            1
+        };
+        offset = if sign == '+' { offset.checked_add(n) } else { offset.checked_sub(n) }.ok_or_else(invalid_range)?;
+    }
+
+    match kind {
+        Some(kind) => Ok((Some(Address { kind, offset }), rest)),
+        None if has_offset => Ok((Some(Address { kind: AddressKind::Current, offset }), rest)),
+        None => Ok((None, rest)),
+    }
+}
+
+/// Returns the pattern of a search address, up to the closing delimiter which may be omitted,
+/// and the text after it. An escaped delimiter is part of the pattern.
+fn split_pattern(text: &str, delimiter: char) -> (String, &str) {
+    let mut pattern = String::new();
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c == delimiter => return (pattern, &text[i + 1..]),
+            '\\' => match chars.next() {
+                Some((_, next)) if next == delimiter => pattern.push(next),
+                Some((_, next)) => {
+                    pattern.push('\\');
+                    pattern.push(next);
+                }
+                None => pattern.push('\\'),
+            },
+            c => pattern.push(c),
+        }
+    }
+    (pattern, "")
+}
+
+/// Parses the number at the start of the text, and returns it with the rest of the text
+fn parse_number(text: &str) -> Result<(usize, &str), String> {
+    let end = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let n = text[..end].parse().map_err(|_| "E16: Invalid range".to_string())?;
+    Ok((n, &text[end..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(kind: AddressKind, offset: i64) -> Address {
+        Address { kind, offset }
+    }
+
+    fn range(start: Address, end: Address) -> LineRange {
+        LineRange { start, end, end_from_start: false }
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("s/a/b/"), Ok((None, "s/a/b/")));
+        assert_eq!(parse_range("%s/a/b/"), Ok((Some(LineRange::whole_buffer()), "s/a/b/")));
+        let expected = range(address(AddressKind::Line(3), 0), address(AddressKind::Last, -1));
+        assert_eq!(parse_range("3,$-1s"), Ok((Some(expected), "s")));
+        let expected = range(address(AddressKind::Mark('<'), 0), address(AddressKind::Mark('>'), 0));
+        assert_eq!(parse_range("'<,'>s"), Ok((Some(expected), "s")));
+        // An offset alone is relative to the current line
+        let expected = range(address(AddressKind::Current, 1), address(AddressKind::Current, 2));
+        assert_eq!(parse_range(".+,+2s"), Ok((Some(expected), "s")));
+        assert!(parse_range("'Xs").is_err());
+        let invalid = Err("E16: Invalid range".to_string());
+        assert_eq!(parse_range("+9223372036854775807+1d"), invalid);
+        assert_eq!(parse_range("-9223372036854775808d"), invalid);
+        assert!(parse_range("-9223372036854775807-1d").is_ok());
+
+        // Searches, and ';' which makes the end relative to the start
+        let search = AddressKind::Search { pattern: "a/b".to_string(), backward: false };
+        let expected = LineRange {
+            start: address(search, 0),
+            end: address(AddressKind::Current, 3),
+            end_from_start: true,
+        };
+        assert_eq!(parse_range("/a\\/b/;+3d"), Ok((Some(expected), "d")));
+        let search = AddressKind::Search { pattern: "x".to_string(), backward: true };
+        let expected = range(address(AddressKind::Mark('a'), 0), address(search, -1));
+        assert_eq!(parse_range("'a,?x?-"), Ok((Some(expected), "")));
+    }
+
+    #[test]
+    fn test_parse_command() {
+        let command = |kind| Ok(Some(Command { range: None, kind }));
+        assert_eq!(parse_command(""), Ok(None));
+        assert_eq!(parse_command("wr"), command(CommandKind::Write { file: None, bang: false }));
+        assert_eq!(
+            parse_command("w! notes.txt"),
+            command(CommandKind::Write { file: Some("notes.txt".to_string()), bang: true })
+        );
+        assert_eq!(parse_command("q!"), command(CommandKind::Quit { bang: true }));
+        assert_eq!(
+            parse_command("x"),
+            command(CommandKind::WriteQuit { file: None, bang: false, only_if_modified: true })
+        );
+        assert_eq!(parse_command("noh"), command(CommandKind::NoHighlightSearch));
+        assert_eq!(parse_command("earlier 5m"), command(CommandKind::TimeTravel(TimeTravel::Seconds(-300))));
+        assert_eq!(parse_command("lat"), command(CommandKind::TimeTravel(TimeTravel::Steps(1))));
+
+        let substitute = Substitute::parse("/a/b/").unwrap();
+        assert_eq!(
+            parse_command("%su/a/b/"),
+            Ok(Some(Command { range: Some(LineRange::whole_buffer()), kind: CommandKind::Substitute(substitute) }))
+        );
+        let goto = Command { range: Some(range(address(AddressKind::Last, 0), address(AddressKind::Last, 0))), kind: CommandKind::GotoLine };
+        assert_eq!(parse_command(" $ "), Ok(Some(goto)));
+
+        // Commands on lines
+        assert_eq!(parse_command("d a"), command(CommandKind::Delete { register: Some('a'), count: None }));
+        assert_eq!(parse_command("d 3"), command(CommandKind::Delete { register: None, count: Some(3) }));
+        assert_eq!(parse_command("d 12"), command(CommandKind::Delete { register: None, count: Some(12) }));
+        assert_eq!(parse_command("d a 2"), command(CommandKind::Delete { register: Some('a'), count: Some(2) }));
+        assert_eq!(parse_command("d %"), Err("E354: Invalid register name: '%'".to_string()));
+        assert_eq!(parse_command("d 0"), Err("E939: Positive count required".to_string()));
+        assert_eq!(parse_command("d a b"), Err("E488: Trailing characters: b".to_string()));
+        assert_eq!(
+            parse_command("m0"),
+            command(CommandKind::Move { address: address(AddressKind::Line(0), 0) })
+        );
+        assert_eq!(
+            parse_command("t.-1"),
+            command(CommandKind::Copy { address: address(AddressKind::Current, -1) })
+        );
+        assert_eq!(parse_command("norm! Ax "), command(CommandKind::Normal { keys: "Ax ".to_string() }));
+        let global = |pattern: &str, invert, kind| {
+            let inner = Box::new(Command { range: None, kind });
+            command(CommandKind::Global { pattern: pattern.to_string(), invert, command: inner })
+        };
+        assert_eq!(parse_command("g/a\\/b/d"), global("a/b", false, CommandKind::Delete { register: None, count: None }));
+        assert_eq!(parse_command("g!#x#"), global("x", true, CommandKind::Print));
+        assert_eq!(parse_command("v/x/norm dd"), global("x", true, CommandKind::Normal { keys: "dd".to_string() }));
+        let range = Some(range(address(AddressKind::Current, 0), address(AddressKind::Current, 1)));
+        assert_eq!(
+            parse_command("g/x/.,+1d"),
+            command(CommandKind::Global {
+                pattern: "x".to_string(),
+                invert: false,
+                command: Box::new(Command { range, kind: CommandKind::Delete { register: None, count: None } }),
+            })
+        );
+
+        // Errors
+        assert_eq!(parse_command("g/a/g/b/d"), Err("E147: Cannot do :global recursive".to_string()));
+        assert_eq!(parse_command("m foo"), Err("E14: Invalid address".to_string()));
+        assert_eq!(parse_command("norm"), Err("E471: Argument required".to_string()));
+        assert_eq!(parse_command("foo"), Err("E492: Not an editor command: foo".to_string()));
+        assert_eq!(parse_command("re"), Err("E492: Not an editor command: re".to_string()));
+        assert_eq!(parse_command("1,2q"), Err("E481: No range allowed".to_string()));
+        assert_eq!(parse_command("noh!"), Err("E477: No ! allowed".to_string()));
+        assert_eq!(parse_command("q now"), Err("E488: Trailing characters: now".to_string()));
+        assert_eq!(parse_command("earlier x"), Err("E475: Invalid argument: x".to_string()));
+        assert_eq!(
+            parse_command("earlier 999999999999999d"),
+            Err("E475: Invalid argument: 999999999999999d".to_string())
+        );
+    }
+}
@@ -0,0 +1,66 @@
+//! Helpers to work with grapheme clusters.
+//!
+//! The x coordinate of the cursor is an index of grapheme cluster in the line: a letter followed
+//! by a combining accent, or an emoji made of several code points, is a single position for the
+//! cursor. These functions convert those indices into byte offsets of the line, which is what the
+//! text buffer expects.
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Returns the grapheme clusters of the line
+pub fn graphemes(line: &str) -> Vec<&str> {
+    line.graphemes(true).collect()
+}
+
+/// Returns the number of grapheme clusters of the line
+pub fn grapheme_count(line: &str) -> usize {
+    line.graphemes(true).count()
+}
+
+/// Returns the byte offset at which the grapheme with the provided index starts.
+/// If the index is beyond the line, returns the length of the line.
+pub fn grapheme_to_byte(line: &str, index: usize) -> usize {
+    line.grapheme_indices(true)
+        .nth(index)
+        .map(|(i, _)| i)
+        .unwrap_or(line.len())
+}
+
+/// Returns the index of the grapheme containing the provided byte offset
+pub fn byte_to_grapheme(line: &str, at: usize) -> usize {
+    line.grapheme_indices(true)
+        .take_while(|(i, g)| i + g.len() <= at)
+        .count()
+}
+
+/// Returns the number of cells used to display the text on a terminal.
+/// Wide characters, such as CJK ideographs, take two cells.
+pub fn display_width(text: &str) -> usize {
+    text.width()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::graphemes::*;
+
+    #[test]
+    fn test_grapheme_offsets() {
+        // 'e' followed by a combining acute accent is a single grapheme
+        let line = "cafe\u{301} 日本";
+        assert_eq!(grapheme_count(line), 7);
+        assert_eq!(grapheme_to_byte(line, 3), 3);
+        assert_eq!(grapheme_to_byte(line, 4), 6);
+        assert_eq!(grapheme_to_byte(line, 6), 10);
+        assert_eq!(grapheme_to_byte(line, 10), line.len());
+        assert_eq!(byte_to_grapheme(line, 6), 4);
+        assert_eq!(byte_to_grapheme(line, line.len()), 7);
+    }
+
+    #[test]
+    fn test_display_width() {
+        assert_eq!(display_width("abc"), 3);
+        assert_eq!(display_width("日本"), 4);
+        assert_eq!(display_width("e\u{301}"), 1);
+    }
+}
@@ -0,0 +1,225 @@
+use ncurses::{
+    KEY_BACKSPACE, KEY_DC, KEY_DOWN, KEY_END, KEY_ENTER, KEY_F0, KEY_HOME, KEY_LEFT, KEY_NPAGE,
+    KEY_PPAGE, KEY_RIGHT, KEY_UP,
+};
+
+use crate::key::Key;
+
+/// Number of function keys reserved by ncurses after `KEY_F0`
+const MAX_FUNCTION_KEY: i32 = 64;
+
+/// Decodes the raw values returned by `getch` into keys.
+///
+/// The terminal sends characters as UTF-8, one byte at a time: a character such as 'é' or an
+/// emoji is made of several bytes, that must be assembled before being handed to the editor.
+/// Control characters are decoded as `Key::Ctrl`, and a character sent right after an escape is
+/// decoded as `Key::Alt`. Values above 255 are the special keys of ncurses.
+///
+/// This is the only place where the key codes of ncurses are known.
+pub struct InputDecoder {
+    /// Bytes of the character being decoded
+    pending: Vec<u8>,
+    /// Total number of bytes of the character being decoded
+    expected: usize,
+    /// True if an escape was received, which might be the start of an Alt combination
+    escape_pending: bool,
+}
+
+impl InputDecoder {
+    pub fn new() -> Self {
+        Self {
+            pending: Vec::new(),
+            expected: 0,
+            escape_pending: false,
+        }
+    }
+
+    /// Returns true if an escape was received and the decoder waits for the next key to know if
+    /// it is an Alt combination. If no key comes, `flush` must be called.
+    pub fn is_escape_pending(&self) -> bool {
+        self.escape_pending
+    }
+
+    /// Called when no key followed an escape: returns the escape key itself
+    pub fn flush(&mut self) -> Option<Key> {
+        if self.escape_pending {
+            self.escape_pending = false;
+            Some(Key::Escape)
+        } else {
+            None
+        }
+    }
+
+    /// Feeds a raw value to the decoder.
+    ///
+    /// Returns the key once it is complete, or None if more bytes are expected.
+    /// Invalid bytes are decoded as the replacement character '�', and incomplete sequences are
+    /// dropped.
+    pub fn push(&mut self, raw: i32) -> Option<Key> {
+        if !(0..=0xFF).contains(&raw) {
+            // Special key: any incomplete character is dropped
+            self.pending.clear();
+            self.escape_pending = false;
+            return InputDecoder::special_key(raw);
+        }
+
+        let key = self.push_byte(raw as u8)?;
+        if self.escape_pending {
+            self.escape_pending = false;
+            match key {
+                Key::Char(ch) => Some(Key::Alt(ch)),
+                Key::Escape => {
+                    // Two escapes in a row: the first one is a real escape
+                    self.escape_pending = true;
+                    Some(Key::Escape)
+                }
+                _ => Some(key),
+            }
+        } else if key == Key::Escape {
+            self.escape_pending = true;
+            None
+        } else {
+            Some(key)
+        }
+    }
+
+    fn push_byte(&mut self, byte: u8) -> Option<Key> {
+        if byte & 0b1100_0000 == 0b1000_0000 {
+            // Continuation byte
+            if self.pending.is_empty() {
+                return Some(Key::Char(char::REPLACEMENT_CHARACTER));
+            }
+            self.pending.push(byte);
+            if self.pending.len() < self.expected {
+                return None;
+            }
+            let decoded = std::str::from_utf8(&self.pending)
+                .ok()
+                .and_then(|s| s.chars().next())
+                .unwrap_or(char::REPLACEMENT_CHARACTER);
+            self.pending.clear();
+            return Some(Key::Char(decoded));
+        }
+
+        // A new character starts: if the previous one was not finished, it is dropped
+        self.pending.clear();
+
+        let expected = match byte {
+            0x00..=0x7F => 1,
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            _ => 0,
+        };
+
+        if expected == 1 {
+            Some(InputDecoder::ascii_key(byte))
+        } else if expected == 0 {
+            Some(Key::Char(char::REPLACEMENT_CHARACTER))
+        } else {
+            self.pending.push(byte);
+            self.expected = expected;
+            None
+        }
+    }
+
+    /// Converts an ASCII byte into a key, handling the control characters
+    fn ascii_key(byte: u8) -> Key {
+        match byte {
+            b'\n' | b'\r' => Key::Enter,
+            b'\t' => Key::Tab,
+            0x1B => Key::Escape,
+            0x08 | 0x7F => Key::Backspace,
+            0x00 => Key::Ctrl(' '),
+            0x01..=0x1A => Key::Ctrl((b'a' + byte - 1) as char),
+            0x1C..=0x1F => Key::Ctrl((b'\\' + byte - 0x1C) as char),
+            _ => Key::Char(byte as char),
+        }
+    }
+
+    /// Converts a special key of ncurses into a key
+    fn special_key(code: i32) -> Option<Key> {
+        match code {
+            KEY_LEFT => Some(Key::Left),
+            KEY_RIGHT => Some(Key::Right),
+            KEY_UP => Some(Key::Up),
+            KEY_DOWN => Some(Key::Down),
+            KEY_HOME => Some(Key::Home),
+            KEY_END => Some(Key::End),
+            KEY_PPAGE => Some(Key::PageUp),
+            KEY_NPAGE => Some(Key::PageDown),
+            KEY_BACKSPACE => Some(Key::Backspace),
+            KEY_DC => Some(Key::Delete),
+            KEY_ENTER => Some(Key::Enter),
+            _ if code > KEY_F0 && code <= KEY_F0 + MAX_FUNCTION_KEY => {
+                Some(Key::F((code - KEY_F0) as u8))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::input::*;
+
+    fn decode(decoder: &mut InputDecoder, text: &str) -> Vec<Key> {
+        text.bytes()
+            .filter_map(|b| decoder.push(b as i32))
+            .collect()
+    }
+
+    #[test]
+    fn test_decode_utf8() {
+        let mut decoder = InputDecoder::new();
+        let keys = decode(&mut decoder, "aé日🦀");
+        assert_eq!(
+            keys,
+            vec![
+                Key::Char('a'),
+                Key::Char('é'),
+                Key::Char('日'),
+                Key::Char('🦀'),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decode_special_keys() {
+        let mut decoder = InputDecoder::new();
+        assert_eq!(decoder.push(KEY_LEFT), Some(Key::Left));
+        // An incomplete character followed by a special key is dropped
+        assert_eq!(decoder.push(0xC3), None);
+        assert_eq!(decoder.push(KEY_DOWN), Some(Key::Down));
+        assert_eq!(decoder.push('a' as i32), Some(Key::Char('a')));
+        assert_eq!(decoder.push(KEY_F0 + 1), Some(Key::F(1)));
+        assert_eq!(decoder.push('\n' as i32), Some(Key::Enter));
+        assert_eq!(decoder.push(0x7F), Some(Key::Backspace));
+    }
+
+    #[test]
+    fn test_decode_modifiers() {
+        let mut decoder = InputDecoder::new();
+        assert_eq!(decoder.push(0x12), Some(Key::Ctrl('r')));
+        assert_eq!(decoder.push(0x04), Some(Key::Ctrl('d')));
+
+        // Alt is sent as an escape followed by the character
+        assert_eq!(decoder.push(0x1B), None);
+        assert!(decoder.is_escape_pending());
+        assert_eq!(decoder.push('j' as i32), Some(Key::Alt('j')));
+
+        // An escape that is not followed by anything
+        assert_eq!(decoder.push(0x1B), None);
+        assert_eq!(decoder.flush(), Some(Key::Escape));
+        assert!(!decoder.is_escape_pending());
+    }
+
+    #[test]
+    fn test_decode_invalid_sequence() {
+        let mut decoder = InputDecoder::new();
+        assert_eq!(decoder.push(0x80), Some(Key::Char(char::REPLACEMENT_CHARACTER)));
+        assert_eq!(decoder.push(0xE6), None);
+        assert_eq!(decoder.push('b' as i32), Some(Key::Char('b')));
+        assert_eq!(decoder.push(0xFF), Some(Key::Char(char::REPLACEMENT_CHARACTER)));
+    }
+}
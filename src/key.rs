@@ -0,0 +1,35 @@
+/// A key pressed by the user.
+///
+/// This type does not depend on the library used to read the keyboard: the view is in charge of
+/// converting its inputs into keys (see `InputDecoder`), and the editor modes only consume keys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Key {
+    /// A printable character
+    Char(char),
+    /// A character typed while holding the control key. The character is always lower case, for
+    /// instance `Ctrl('r')` for `Ctrl-R`.
+    Ctrl(char),
+    /// A character typed while holding the alt (or meta) key, for instance `Alt('j')`
+    Alt(char),
+    Enter,
+    Escape,
+    Backspace,
+    Delete,
+    Tab,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    /// A function key, for instance `F(1)`
+    F(u8),
+}
+
+impl From<char> for Key {
+    fn from(ch: char) -> Self {
+        Key::Char(ch)
+    }
+}
@@ -1,3 +1,4 @@
+use clipboard::Clipboard;
 use editor::TextEditor;
 use gag::Redirect;
 use std::fs::OpenOptions;
@@ -5,6 +6,7 @@ use std::env;
 
 /// Define the ncurses_example module
 //mod ncurses_example;
+mod clipboard;
 mod cursor;
 mod editor;
 mod editor_action;
@@ -12,9 +14,18 @@ mod undo_redo;
 mod editor_model;
 mod modes;
 mod motion;
+mod operator;
 mod selection;
 mod screen;
+mod regex;
+mod search;
+mod ex;
+mod substitute;
 mod yanker;
+mod text_buffer;
+mod graphemes;
+mod input;
+mod key;
 
 mod test_model;
 
@@ -30,17 +41,28 @@ fn main() {
         .unwrap();
     let _print_redirect = Redirect::stderr(log).unwrap();
 
-    // Load a file
-    let args: Vec<String> = env::args().collect();
-    let file = if args.len() > 1 {
-        args[1].clone()
-    } else {
-        "".to_string()
+    // Load a file. With '--undofile', the undo history is kept across sessions.
+    let args: Vec<String> = env::args().skip(1).collect();
+    let persistent_undo = args.iter().any(|arg| arg == "--undofile");
+    // The registers '+' and '*' use the clipboard of the terminal, unless a command is given,
+    // such as '--copy-command=wl-copy --paste-command=wl-paste'
+    let option = |name: &str| {
+        args.iter()
+            .find_map(|arg| arg.strip_prefix(name)?.strip_prefix('='))
+            .map(|value| value.to_string())
     };
+    let clipboard = match option("--copy-command") {
+        Some(copy) => Clipboard::Commands { copy, paste: option("--paste-command") },
+        None => Clipboard::Osc52,
+    };
+    let file = args
+        .into_iter()
+        .find(|arg| !arg.starts_with("--"))
+        .unwrap_or("".to_string());
 
     eprintln!("Reading file: {file}");
 
     // Open the viewer
-    let mut viewer = TextEditor::new(file);
+    let mut viewer = TextEditor::new(file, persistent_undo, clipboard);
     viewer.display();
 }
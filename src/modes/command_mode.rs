@@ -1,5 +1,7 @@
 use crate::editor_model::*;
 use crate::editor_action::*;
+use crate::ex::parse_command;
+use crate::key::Key;
 use std::ops::Add;
 
 pub struct CommandMode {
@@ -9,35 +11,30 @@ pub struct CommandMode {
 
 impl CommandMode {
     fn enter_key_pressed(&mut self) -> EditorAction {
-        // Match the final command
-        match self.command.as_str() {
-            "w" => {
-                return EditorAction::CompositeAction {
-                    actions: vec![
-                        Box::new(EditorAction::Save),
-                        Box::new(EditorAction::SwitchToNormalMode),
-                    ],
-                }
-            }
-            "q" => {
-                return EditorAction::Exit
-            }
-            "wq" | "x" => {
-                return EditorAction::CompositeAction {
-                    actions: vec![
-                        Box::new(EditorAction::Save),
-                        Box::new(EditorAction::Exit),
-                    ],
-                }
-            }
-            _ => {
-                eprintln!("Unknown command: {}", self.command)
-            }
+        let command = self.command.clone();
+        EditorAction::CompositeAction {
+            actions: vec![
+                Box::new(EditorAction::RememberCommandLine { command }),
+                Box::new(self.execute_command()),
+            ],
         }
+    }
 
-        // Reset the command
-        self.command = String::new();
-        EditorAction::SwitchToNormalMode
+    /// Parses the command line. The model executes the command, or shows why it is not valid.
+    fn execute_command(&mut self) -> EditorAction {
+        let command = std::mem::take(&mut self.command);
+        let action = match parse_command(&command) {
+            Ok(Some(command)) => EditorAction::ExecuteCommand { command },
+            Ok(None) => EditorAction::None,
+            Err(message) => EditorAction::ShowError { message },
+        };
+        // The mode changes first: a command can open another mode, like `:s///c`
+        EditorAction::CompositeAction {
+            actions: vec![
+                Box::new(EditorAction::SwitchToNormalMode),
+                Box::new(action),
+            ],
+        }
     }
 
     fn backspace_key_pressed(&mut self) -> EditorAction {
@@ -51,17 +48,16 @@ impl CommandMode {
 }
 
 impl EditorMode for CommandMode {
-    fn key_tapped(&mut self, ch: u32) -> EditorAction {
-        match char::from_u32(ch) {
-            ENTER => self.enter_key_pressed(),
-            BACKSPACE => self.backspace_key_pressed(),
-            ESCAPE => self.escape_key_pressed(),
-            _ => {
-                if let Some(c) = char::from_u32(ch) {
-                    self.command.push(c);
-                }
+    fn key_tapped(&mut self, key: Key) -> EditorAction {
+        match key {
+            Key::Enter => self.enter_key_pressed(),
+            Key::Backspace => self.backspace_key_pressed(),
+            Key::Escape => self.escape_key_pressed(),
+            Key::Char(c) => {
+                self.command.push(c);
                 EditorAction::None
             }
+            _ => EditorAction::None,
         }
     }
     fn get_description(&self) -> String {
@@ -75,4 +71,12 @@ impl CommandMode {
             command: String::new(),
         }
     }
+
+    /// Opens the command line with a command already typed, such as the range `'<,'>` when there
+    /// is a selection
+    pub fn with_command(command: &str) -> Self {
+        Self {
+            command: command.to_string(),
+        }
+    }
 }
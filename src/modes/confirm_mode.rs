@@ -0,0 +1,35 @@
+use crate::editor_model::*;
+use crate::editor_action::*;
+use crate::key::Key;
+use crate::substitute::ConfirmAnswer;
+
+/// The question asked before each replacement of `:s///c`
+pub struct ConfirmMode {
+    /// The text replacing the match
+    replacement: String,
+}
+
+impl ConfirmMode {
+    pub fn new(replacement: String) -> Self {
+        Self { replacement }
+    }
+}
+
+impl EditorMode for ConfirmMode {
+    fn key_tapped(&mut self, key: Key) -> EditorAction {
+        let answer = match key {
+            Key::Char('y') => ConfirmAnswer::Yes,
+            Key::Char('n') => ConfirmAnswer::No,
+            Key::Char('a') => ConfirmAnswer::All,
+            Key::Char('l') => ConfirmAnswer::Last,
+            Key::Char('q') | Key::Escape => ConfirmAnswer::Quit,
+            _ => return EditorAction::None,
+        };
+        EditorAction::ConfirmSubstitution { answer }
+    }
+
+    fn get_description(&self) -> String {
+        // Line breaks are shown as ^M, like in Vim
+        format!("replace with {} (y/n/a/q/l)?", self.replacement.replace('\n', "^M"))
+    }
+}
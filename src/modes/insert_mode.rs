@@ -1,19 +1,22 @@
 use crate::editor_model::*;
 use crate::editor_action::*;
+use crate::key::Key;
 
 pub struct InsertMode {}
 
 impl EditorMode for InsertMode {
-    fn key_tapped(&mut self, ch: u32) -> EditorAction {
-        match char::from_u32(ch) {
-            DOWN => EditorAction::MoveCursor { dx: 0, dy: -1 },
-            UP => EditorAction::MoveCursor { dx: 0, dy: 1 },
-            LEFT => EditorAction::MoveCursor { dx: -1, dy: 0 },
-            RIGHT => EditorAction::MoveCursor { dx: 1, dy: 0 },
-            ENTER => EditorAction::JumpLineAtCursor,
-            BACKSPACE => EditorAction::DeleteCharAtCursor,
-            ESCAPE => EditorAction::SwitchToNormalMode,
-            _ => EditorAction::AddCharAtCursor { ch }
+    fn key_tapped(&mut self, key: Key) -> EditorAction {
+        match key {
+            Key::Down => EditorAction::MoveCursor { dx: 0, dy: -1 },
+            Key::Up => EditorAction::MoveCursor { dx: 0, dy: 1 },
+            Key::Left => EditorAction::MoveCursor { dx: -1, dy: 0 },
+            Key::Right => EditorAction::MoveCursor { dx: 1, dy: 0 },
+            Key::Enter => EditorAction::JumpLineAtCursor,
+            Key::Backspace => EditorAction::DeleteCharAtCursor,
+            Key::Escape => EditorAction::SwitchToNormalMode,
+            Key::Tab => EditorAction::AddCharAtCursor { ch: '\t' },
+            Key::Char(ch) => EditorAction::AddCharAtCursor { ch },
+            _ => EditorAction::None,
         }
     }
 
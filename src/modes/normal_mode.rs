@@ -1,199 +1,441 @@
 use crate::editor_model::*;
 use crate::editor_action::*;
+use crate::key::Key;
+use crate::motion::{Motion, TextObject};
+use crate::operator::Operator;
+use crate::selection::SelectionKind;
+use crate::undo_redo::TimeTravel;
+use crate::yanker::Yanker;
 
-const DIGIT_BASELINE: u32 = 48;
-const MAX_DIGIT: u32 = 10;
+/// Result of parsing the keys typed so far
+enum Parse<T> {
+    /// More keys are needed, for instance after 'f' or after an operator
+    Incomplete,
+    /// The keys form a complete command
+    Done(T),
+    /// The keys do not form any command, they are dropped
+    Invalid,
+}
+
+/// The largest count, like in Vim: a longer count is reduced to it
+const MAX_COUNT: usize = 999_999_999;
 
-fn is_digit(ch: u32) -> bool {
-    ch >= DIGIT_BASELINE && ch < DIGIT_BASELINE + MAX_DIGIT
+/// Splits the count typed before a command (such as "12" in "12G") from the rest of the keys.
+/// A count never starts with '0', and is at most `MAX_COUNT`.
+fn split_count(keys: &[Key]) -> (Option<usize>, &[Key]) {
+    let n_digits = match keys.first() {
+        Some(Key::Char('0')) => 0,
+        _ => keys
+            .iter()
+            .take_while(|key| matches!(key, Key::Char(ch) if ch.is_ascii_digit()))
+            .count(),
+    };
+    if n_digits == 0 {
+        return (None, keys);
+    }
+    let count = keys[..n_digits]
+        .iter()
+        .filter_map(|key| match key {
+            Key::Char(ch) => ch.to_digit(10),
+            _ => None,
+        })
+        .fold(0, |count: usize, digit| (count * 10 + digit as usize).min(MAX_COUNT));
+    (Some(count), &keys[n_digits..])
 }
 
-/// A buffering mode is a way to wait for another key.
-/// After pressing some keys, like 'r' or 'f', the editor waits for another key.
-/// The buffering mode specifies which action is expected to happen once the
-/// buffer is full.
-#[derive(Clone, Copy)]
-enum BufferingMode {
-    Replace,
-    Forward,
-    Backward,
-    /// When typing a number
-    Number,
+/// Returns the product of the counts typed before and after a register or an operator
+fn multiply_counts(count: Option<usize>, other: Option<usize>) -> usize {
+    (count.unwrap_or(1) * other.unwrap_or(1)).min(MAX_COUNT)
 }
 
-impl BufferingMode {
-    /// Return the action created by pressing the provided key while in this buffering mode.
-    ///
-    /// The parameter `is_buffering` can be changed by this function if the buffering is finished.
-    ///
-    /// For instance, if you are in 'forward' mode (after pressing 'f'), the action is to apply a
-    /// forward motion, and the buffering is finished.
-    ///
-    /// Otherwise, if you typed a number (go to line), as long as `G` is not pressed, you want to 
-    /// keep buffering.
-    fn get_action(&self, buffer: &Vec<u32>, is_buffering: &mut bool) -> EditorAction {
-        // Get the last element of the buffer
-        // This is the last key that was pressed
-        let ch = buffer.last().unwrap();
-        match char::from_u32(*ch) {
-            ESCAPE | ENTER | BACKSPACE => EditorAction::None,
-            _ => match *self {
-                BufferingMode::Replace => {
-                    *is_buffering = false;
-                    EditorAction::CompositeAction {
-                        actions: vec![
-                            Box::new(EditorAction::MoveCursor { dx: 1, dy: 0 }),
-                            Box::new(EditorAction::DeleteCharAtCursor),
-                            Box::new(EditorAction::AddCharAtCursor { ch: *ch }),
-                            Box::new(EditorAction::MoveCursor { dx: -1, dy: 0 }),
-                        ],
-                    }
-                }
-                BufferingMode::Forward => {
-                    *is_buffering = false;
-                    EditorAction::ApplyMotion {
-                        motion: crate::motion::Motion::Forward { ch: *ch },
-                    }
-                }
-                BufferingMode::Backward => {
-                    *is_buffering = false;
-                    EditorAction::ApplyMotion {
-                        motion: crate::motion::Motion::Backward { ch: *ch },
-                    }
-                }
-                BufferingMode::Number => {
-                    if !is_digit(*ch) {
-                        // If it is not a digit, the buffering is finished
-                        *is_buffering = false;
-                        // If 'G' is pressed, triggers a line move
-                        if char::from_u32(*ch).unwrap() == 'G' {
-                            // Compute the line number 
-                            // Don't forget to remove the last element, which will be 'G'
-                            let as_string = buffer.iter()
-                                .map(|ch| char::from_u32(*ch).unwrap().to_string())
-                                .collect::<Vec<String>>()
-                                .split_last().unwrap().1.join("");
-
-                            // Try to parse a number from this string
-                            if let Ok(line_number) = usize::from_str_radix(&as_string, 10) {
-                                return EditorAction::JumpToLine { line: line_number }
-                            }
-                        }
-                    } 
-                    EditorAction::None
-                },
-            },
-        }
+/// Returns an action that executes the provided one `count` times
+fn repeated(action: EditorAction, count: usize) -> EditorAction {
+    if count > 1 {
+        EditorAction::Repeat { count, action: Box::new(action) }
+    } else {
+        action
+    }
+}
+
+/// Returns a motion that applies the provided one `count` times
+fn repeated_motion(motion: Motion, count: usize) -> Motion {
+    if count > 1 {
+        Motion::Repeat { motion: Box::new(motion), count }
+    } else {
+        motion
+    }
+}
+
+/// Returns the character typed after a prefix key, such as the 'x' of "fx"
+fn char_argument(keys: &[Key]) -> Parse<char> {
+    match keys.get(1) {
+        None => Parse::Incomplete,
+        Some(Key::Char(ch)) => Parse::Done(*ch),
+        Some(_) => Parse::Invalid,
     }
 }
 
+/// The normal mode parses the keys as commands, which can be made of several keys: "12G",
+/// "fx", or an operator followed by a motion such as "d3w".
+///
+/// The keys are kept until they form a complete command.
 pub struct NormalMode {
-    is_buffering: bool,
-    buffering_mode: Option<BufferingMode>,
-    buffer: Vec<u32>,
+    /// Keys of the command being typed
+    pending: Vec<Key>,
+    /// Last search of a character on the line ('f', 'F', 't' or 'T'), repeated by ';'
+    last_search: Option<Motion>,
+    /// Kind of the visual selection being made, if any: operators then act on the selection
+    selecting: Option<SelectionKind>,
 }
 
 impl EditorMode for NormalMode {
-    fn key_tapped(&mut self, ch: u32) -> EditorAction {
-        if self.is_buffering && self.buffering_mode.is_some() {
-            // If we are buffering, send the next character to the buffering mode
-            self.buffer.push(ch);
-            return self.buffering_mode.unwrap().get_action(&self.buffer, &mut self.is_buffering);
-        } else {
-            // Otherwise, match the character with the expected action
-            match char::from_u32(ch) {
-                Some('j') | DOWN => EditorAction::MoveCursor { dx: 0, dy: -1 },
-                Some('k') | UP => EditorAction::MoveCursor { dx: 0, dy: 1 },
-                Some('h') | LEFT => EditorAction::MoveCursor { dx: -1, dy: 0 },
-                Some('l') | RIGHT => EditorAction::MoveCursor { dx: 1, dy: 0 },
-                BACKSPACE => EditorAction::MoveCursor { dx: -1, dy: 0 },
-                ENTER => EditorAction::MoveCursorDown,
-                ESCAPE => EditorAction::AbortCurrentAction,
-                Some('i') => EditorAction::SwitchToInsertMode,
-                Some('o') => EditorAction::CompositeAction {
-                    actions: vec![
-                        Box::new(EditorAction::MoveCursorDown),
-                        Box::new(EditorAction::JumpLineAtCursor),
-                        Box::new(EditorAction::MoveCursor { dx: 0, dy: 1 }),
-                        Box::new(EditorAction::SwitchToInsertMode),
-                    ],
-                },
-                Some('O') => EditorAction::CompositeAction {
-                    actions: vec![
-                        Box::new(EditorAction::MoveCursor { dx: 0, dy: 1 }),
-                        Box::new(EditorAction::JumpLineAtCursor),
-                        Box::new(EditorAction::SwitchToInsertMode),
-                    ],
-                },
-                Some('w') => EditorAction::MoveByWords { n_words: 1 },
-                Some('b') => EditorAction::MoveByWords { n_words: -1 },
-                Some(':') => EditorAction::SwitchToCommandMode,
-                Some('v') => EditorAction::ToggleSelectionState,
-                Some('y') => EditorAction::Yank,
-                Some('u') => EditorAction::Undo,
-                Some('p') => EditorAction::Put,
-                // x is like pressing backspace with a previous right arrow move
-                Some('x') => EditorAction::CompositeAction {
-                    actions: vec![
-                        Box::new(EditorAction::MoveCursor { dx: 1, dy: 0 }),
-                        Box::new(EditorAction::DeleteCharAtCursor),
-                    ],
-                },
-                Some('r') => {
-                    self.start_buffering(BufferingMode::Replace);
-                    EditorAction::None
-                }
-                Some('f') => {
-                    self.start_buffering(BufferingMode::Forward);
-                    EditorAction::None
-                }
-                Some('F') => {
-                    self.start_buffering(BufferingMode::Backward);
-                    EditorAction::None
-                }
-                Some(';') => {
-                    // re-apply the previous motion
-                    if self.buffering_mode.is_some() && self.buffer.len() > 0 {
-                        self.buffering_mode.unwrap().get_action(&self.buffer, &mut self.is_buffering)
-                    } else {
-                        EditorAction::None
-                    }
-                }
-                Some('1') | Some('2') | Some('3') | Some('4') | Some('5') | Some('6')
-                | Some('7') | Some('8') | Some('9') | Some('0') => {
-                    self.start_buffering(BufferingMode::Number);
-                    self.buffer.push(ch);
-                    EditorAction::None
-                },
-                _ => EditorAction::None,
+    fn key_tapped(&mut self, key: Key) -> EditorAction {
+        self.pending.push(key);
+        let keys = self.pending.clone();
+        match self.parse(&keys) {
+            Parse::Incomplete => EditorAction::None,
+            Parse::Done(action) => {
+                self.pending.clear();
+                action
+            }
+            Parse::Invalid => {
+                self.pending.clear();
+                EditorAction::None
             }
         }
     }
 
     fn get_description(&self) -> String {
-        if self.is_buffering {
+        if !self.pending.is_empty() {
             "Normal Mode (buffering)".to_string()
         } else {
             "Normal Mode".to_string()
         }
     }
+
+    fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    fn set_selection_kind(&mut self, kind: Option<SelectionKind>) {
+        self.selecting = kind;
+    }
 }
 
 impl NormalMode {
     pub fn new() -> Self {
         Self {
-            buffering_mode: None,
-            is_buffering: false,
-            buffer: Vec::new(),
+            pending: Vec::new(),
+            last_search: None,
+            selecting: None,
         }
     }
 
-    /// Call this function when we must start a new buffering mode.
+    /// Parses the keys typed so far into an action
+    fn parse(&mut self, keys: &[Key]) -> Parse<EditorAction> {
+        let (count, keys) = split_count(keys);
+        let key = match keys.first() {
+            Some(key) => *key,
+            None => return Parse::Incomplete,
+        };
+
+        if key == Key::Char('"') {
+            return self.parse_register(count, keys);
+        }
+        if let Some(kind) = self.selecting {
+            if let Some(parse) = self.parse_visual(kind, keys) {
+                return parse;
+            }
+        }
+        if self.selecting.is_some() && matches!(key, Key::Char('i') | Key::Char('a')) {
+            // Select a text object, such as "i(" or "aw"
+            return match self.parse_text_object(keys) {
+                Parse::Done(object) => Parse::Done(EditorAction::SelectTextObject { object }),
+                Parse::Incomplete => Parse::Incomplete,
+                Parse::Invalid => Parse::Invalid,
+            };
+        }
+        if let Some(operator) = Operator::from_key(key) {
+            return self.parse_operator(operator, count, &keys[1..]);
+        }
+
+        // The count repeats the command, unless the command uses it itself (like "3w" or "12G")
+        let n = count.unwrap_or(1);
+        let action = match key {
+            Key::Char('j') | Key::Down => repeated(EditorAction::MoveCursor { dx: 0, dy: -1 }, n),
+            Key::Char('k') | Key::Up => repeated(EditorAction::MoveCursor { dx: 0, dy: 1 }, n),
+            Key::Char('h') | Key::Left => repeated(EditorAction::MoveCursor { dx: -1, dy: 0 }, n),
+            Key::Char('l') | Key::Right => repeated(EditorAction::MoveCursor { dx: 1, dy: 0 }, n),
+            Key::Backspace => repeated(EditorAction::MoveCursor { dx: -1, dy: 0 }, n),
+            Key::Enter => repeated(EditorAction::MoveCursorDown, n),
+            Key::Escape => EditorAction::AbortCurrentAction,
+            Key::Ctrl('d') => EditorAction::ScrollHalfScreen { down: true },
+            Key::Ctrl('u') => EditorAction::ScrollHalfScreen { down: false },
+            Key::Char('i') => EditorAction::SwitchToInsertMode,
+            // Break the line after its last character
+            Key::Char('o') => EditorAction::CompositeAction {
+                actions: vec![
+                    Box::new(EditorAction::ApplyMotion { motion: Motion::LineEnd }),
+                    Box::new(EditorAction::MoveCursor { dx: 1, dy: 0 }),
+                    Box::new(EditorAction::JumpLineAtCursor),
+                    Box::new(EditorAction::SwitchToInsertMode),
+                ],
+            },
+            // Break the line before its first character, and go back to the new empty line
+            Key::Char('O') => EditorAction::CompositeAction {
+                actions: vec![
+                    Box::new(EditorAction::ApplyMotion { motion: Motion::LineStart }),
+                    Box::new(EditorAction::JumpLineAtCursor),
+                    Box::new(EditorAction::MoveCursor { dx: 0, dy: 1 }),
+                    Box::new(EditorAction::SwitchToInsertMode),
+                ],
+            },
+            Key::Char('w') => EditorAction::MoveByWords { n_words: i32::try_from(n).unwrap_or(i32::MAX) },
+            Key::Char('b') => EditorAction::MoveByWords { n_words: -i32::try_from(n).unwrap_or(i32::MAX) },
+            Key::Char(':') => EditorAction::SwitchToCommandMode,
+            Key::Char('/') => EditorAction::SwitchToSearchMode { backward: false },
+            Key::Char('?') => EditorAction::SwitchToSearchMode { backward: true },
+            Key::Char('*') => EditorAction::SearchWordUnderCursor { backward: false, count: n },
+            Key::Char('#') => EditorAction::SearchWordUnderCursor { backward: true, count: n },
+            Key::Char('v') => EditorAction::ToggleSelection { kind: SelectionKind::Charwise },
+            Key::Char('V') => EditorAction::ToggleSelection { kind: SelectionKind::Linewise },
+            Key::Ctrl('v') => EditorAction::ToggleSelection { kind: SelectionKind::Blockwise },
+            Key::Char('.') => EditorAction::RepeatLastChange { count },
+            // "qa" records a macro in the register 'a', "qA" appends to it
+            Key::Char('q') => match char_argument(keys) {
+                Parse::Done(register) if register.is_ascii_alphanumeric() => {
+                    EditorAction::RecordMacro { register }
+                }
+                Parse::Incomplete => return Parse::Incomplete,
+                _ => return Parse::Invalid,
+            },
+            // "ma" sets the mark 'a' at the cursor
+            Key::Char('m') => match char_argument(keys) {
+                Parse::Done(mark) if mark.is_ascii_lowercase() => EditorAction::SetMark { mark },
+                Parse::Incomplete => return Parse::Incomplete,
+                _ => return Parse::Invalid,
+            },
+            // "@a" plays the macro of the register 'a', "@@" plays the last played macro again
+            Key::Char('@') => match char_argument(keys) {
+                Parse::Done('@') => EditorAction::PlayMacro { register: None, count: n },
+                Parse::Done(register) if register.is_ascii_alphanumeric() => {
+                    EditorAction::PlayMacro { register: Some(register), count: n }
+                }
+                Parse::Incomplete => return Parse::Incomplete,
+                _ => return Parse::Invalid,
+            },
+            Key::Char('u') => repeated(EditorAction::Undo, n),
+            Key::Ctrl('r') => repeated(EditorAction::Redo, n),
+            Key::Char('p') => EditorAction::Put { before: false, count: n },
+            Key::Char('P') => EditorAction::Put { before: true, count: n },
+            // x deletes the characters under the cursor, like "dl"
+            Key::Char('x') => EditorAction::ApplyOperator {
+                operator: Operator::Delete,
+                motion: Motion::Chars { n: i32::try_from(n).unwrap_or(i32::MAX) },
+            },
+            // r replaces n characters, and leaves the cursor on the last one
+            Key::Char('r') => match char_argument(keys) {
+                Parse::Done(ch) => {
+                    let replace = EditorAction::CompositeAction {
+                        actions: vec![
+                            Box::new(EditorAction::MoveCursor { dx: 1, dy: 0 }),
+                            Box::new(EditorAction::DeleteCharAtCursor),
+                            Box::new(EditorAction::AddCharAtCursor { ch }),
+                        ],
+                    };
+                    EditorAction::CompositeAction {
+                        actions: vec![
+                            Box::new(repeated(replace, n)),
+                            Box::new(EditorAction::MoveCursor { dx: -1, dy: 0 }),
+                        ],
+                    }
+                }
+                Parse::Incomplete => return Parse::Incomplete,
+                Parse::Invalid => return Parse::Invalid,
+            },
+            // 'g' is the prefix of several commands, and of some motions
+            Key::Char('g') if keys.get(1) == Some(&Key::Char('v')) => EditorAction::Reselect,
+            Key::Char('g') if keys.get(1) == Some(&Key::Char('-')) => {
+                repeated(EditorAction::TimeTravel { travel: TimeTravel::Steps(-1) }, n)
+            }
+            Key::Char('g') if keys.get(1) == Some(&Key::Char('+')) => {
+                repeated(EditorAction::TimeTravel { travel: TimeTravel::Steps(1) }, n)
+            }
+            _ => {
+                return match self.parse_motion(keys, count) {
+                    Parse::Done(motion) => Parse::Done(EditorAction::ApplyMotion { motion }),
+                    Parse::Incomplete => Parse::Incomplete,
+                    Parse::Invalid => Parse::Invalid,
+                }
+            }
+        };
+        Parse::Done(action)
+    }
+
+    /// Parses the keys that act on the selection in visual mode, if they are not motions
+    fn parse_visual(&self, kind: SelectionKind, keys: &[Key]) -> Option<Parse<EditorAction>> {
+        let operator = |operator| EditorAction::OperateOnSelection { operator };
+        let action = match keys[0] {
+            Key::Char('y') => operator(Operator::Yank),
+            Key::Char('d') | Key::Char('x') | Key::Delete => operator(Operator::Delete),
+            Key::Char('c') | Key::Char('s') => operator(Operator::Change),
+            Key::Char('>') => operator(Operator::Indent),
+            Key::Char('<') => operator(Operator::Outdent),
+            Key::Char('~') => operator(Operator::ToggleCase),
+            Key::Char('u') => operator(Operator::Lowercase),
+            Key::Char('U') => operator(Operator::Uppercase),
+            Key::Char('J') => EditorAction::JoinSelectedLines,
+            Key::Char('o') => EditorAction::SwapSelectionEnds,
+            Key::Char('r') => match char_argument(keys) {
+                Parse::Done(ch) => EditorAction::ReplaceSelection { ch },
+                Parse::Incomplete => return Some(Parse::Incomplete),
+                Parse::Invalid => return Some(Parse::Invalid),
+            },
+            Key::Char('I') if kind == SelectionKind::Blockwise => {
+                EditorAction::BlockInsert { append: false }
+            }
+            Key::Char('A') if kind == SelectionKind::Blockwise => {
+                EditorAction::BlockInsert { append: true }
+            }
+            _ => return None,
+        };
+        Some(Parse::Done(action))
+    }
+
+    /// Parses a command preceded by a register, such as "ayy or "b3p.
+    /// A count can be typed before the register, after it, or both: they are multiplied.
+    fn parse_register(&mut self, count: Option<usize>, keys: &[Key]) -> Parse<EditorAction> {
+        let register = match char_argument(keys) {
+            Parse::Done(register) if Yanker::is_valid(register) => register,
+            Parse::Incomplete => return Parse::Incomplete,
+            _ => return Parse::Invalid,
+        };
+        let (command_count, command) = split_count(&keys[2..]);
+        if command.is_empty() {
+            return Parse::Incomplete;
+        }
+        let mut command_keys: Vec<Key> = match (count, command_count) {
+            (None, None) => Vec::new(),
+            (count, command_count) => {
+                let count = multiply_counts(count, command_count);
+                count.to_string().chars().map(Key::Char).collect()
+            }
+        };
+        command_keys.extend_from_slice(command);
+        match self.parse(&command_keys) {
+            Parse::Done(action) => {
+                Parse::Done(EditorAction::WithRegister { register, action: Box::new(action) })
+            }
+            Parse::Incomplete => Parse::Incomplete,
+            Parse::Invalid => Parse::Invalid,
+        }
+    }
+
+    /// Parses the keys typed after an operator: an optional count, then a motion.
+    /// Typing the operator key again operates on `count` lines.
+    fn parse_operator(&mut self, operator: Operator, count: Option<usize>, keys: &[Key]) -> Parse<EditorAction> {
+        let (motion_count, keys) = split_count(keys);
+        let count = match (count, motion_count) {
+            (None, None) => None,
+            (count, motion_count) => Some(multiply_counts(count, motion_count)),
+        };
+        let key = match keys.first() {
+            Some(key) => *key,
+            None => return Parse::Incomplete,
+        };
+
+        if Operator::from_key(key) == Some(operator) {
+            let motion = Motion::Lines { n: i32::try_from(count.unwrap_or(1)).unwrap_or(i32::MAX) - 1 };
+            return Parse::Done(EditorAction::ApplyOperator { operator, motion });
+        }
+        if matches!(key, Key::Char('i') | Key::Char('a')) {
+            return match self.parse_text_object(keys) {
+                Parse::Done(object) => {
+                    let motion = Motion::TextObject { object };
+                    Parse::Done(EditorAction::ApplyOperator { operator, motion })
+                }
+                Parse::Incomplete => Parse::Incomplete,
+                Parse::Invalid => Parse::Invalid,
+            };
+        }
+
+        match self.parse_motion(keys, count) {
+            Parse::Done(motion) => Parse::Done(EditorAction::ApplyOperator { operator, motion }),
+            Parse::Incomplete => Parse::Incomplete,
+            Parse::Invalid => Parse::Invalid,
+        }
+    }
+
+    /// Parses a text object: 'i' or 'a' followed by the object, such as "iw" or "a("
+    fn parse_text_object(&self, keys: &[Key]) -> Parse<TextObject> {
+        let inner = keys[0] == Key::Char('i');
+        match char_argument(keys) {
+            Parse::Done(ch) => match TextObject::from_keys(inner, ch) {
+                Some(object) => Parse::Done(object),
+                None => Parse::Invalid,
+            },
+            Parse::Incomplete => Parse::Incomplete,
+            Parse::Invalid => Parse::Invalid,
+        }
+    }
+
+    /// Parses a motion, repeated `count` times when it makes sense
     ///
-    /// For instance, after the 'f' key is pressed.
-    fn start_buffering(&mut self, mode: BufferingMode) {
-        self.buffer.clear();
-        self.is_buffering = true;
-        self.buffering_mode = Some(mode);
+    /// `count` is None if no count was typed: some motions, such as `G`, behave differently
+    /// without a count.
+    fn parse_motion(&mut self, keys: &[Key], count: Option<usize>) -> Parse<Motion> {
+        let repeat = count.unwrap_or(1);
+        let n = i32::try_from(repeat).unwrap_or(i32::MAX);
+        let motion = match keys[0] {
+            Key::Char('w') => Motion::Words { n_words: n },
+            Key::Char('b') => Motion::Words { n_words: -n },
+            Key::Char('W') => Motion::BigWords { n_words: n },
+            Key::Char('B') => Motion::BigWords { n_words: -n },
+            Key::Char('e') => Motion::WordEnds { n_words: n, big: false },
+            Key::Char('E') => Motion::WordEnds { n_words: n, big: true },
+            Key::Char('h') | Key::Left | Key::Backspace => Motion::Chars { n: -n },
+            Key::Char('l') | Key::Right | Key::Char(' ') => Motion::Chars { n },
+            Key::Char('j') | Key::Down => Motion::Lines { n },
+            Key::Char('k') | Key::Up => Motion::Lines { n: -n },
+            Key::Char('0') | Key::Home => Motion::LineStart,
+            Key::Char('^') => Motion::FirstNonBlank,
+            Key::Char('$') | Key::End => Motion::LineEnd,
+            Key::Char('G') => Motion::GotoLine { line: count },
+            Key::Char('%') => Motion::MatchingBracket,
+            Key::Char('n') => repeated_motion(Motion::SearchNext { reverse: false }, repeat),
+            Key::Char('N') => repeated_motion(Motion::SearchNext { reverse: true }, repeat),
+            Key::Char('H') => Motion::ScreenTop,
+            Key::Char('M') => Motion::ScreenMiddle,
+            Key::Char('L') => Motion::ScreenBottom,
+            Key::Char('g') => match char_argument(keys) {
+                Parse::Done('g') => Motion::GotoLine { line: Some(count.unwrap_or(1)) },
+                Parse::Done('e') => Motion::WordEnds { n_words: -n, big: false },
+                Parse::Done('E') => Motion::WordEnds { n_words: -n, big: true },
+                Parse::Incomplete => return Parse::Incomplete,
+                _ => return Parse::Invalid,
+            },
+            Key::Char(prefix @ ('f' | 'F' | 't' | 'T')) => {
+                let ch = match char_argument(keys) {
+                    Parse::Done(ch) => ch,
+                    Parse::Incomplete => return Parse::Incomplete,
+                    Parse::Invalid => return Parse::Invalid,
+                };
+                let motion = match prefix {
+                    'f' => Motion::Forward { ch },
+                    'F' => Motion::Backward { ch },
+                    't' => Motion::TillForward { ch },
+                    _ => Motion::TillBackward { ch },
+                };
+                self.last_search = Some(motion.clone());
+                repeated_motion(motion, repeat)
+            }
+            // Repeat the last search
+            Key::Char(';') => match &self.last_search {
+                Some(motion) => repeated_motion(motion.clone(), repeat),
+                None => return Parse::Invalid,
+            },
+            _ => return Parse::Invalid,
+        };
+        Parse::Done(motion)
     }
 }
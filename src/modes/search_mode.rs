@@ -0,0 +1,62 @@
+use crate::editor_model::*;
+use crate::editor_action::*;
+use crate::key::Key;
+
+/// The prompt opened by `/` and `?`, where the searched pattern is typed
+pub struct SearchMode {
+    /// The pattern typed so far
+    pattern: String,
+    /// Set for `?`, which searches before the cursor
+    backward: bool,
+}
+
+impl SearchMode {
+    pub fn new(backward: bool) -> Self {
+        Self {
+            pattern: String::new(),
+            backward,
+        }
+    }
+}
+
+impl SearchMode {
+    /// Shows the matches of the pattern typed so far
+    fn preview(&self) -> EditorAction {
+        EditorAction::PreviewSearch {
+            pattern: self.pattern.clone(),
+            backward: self.backward,
+        }
+    }
+}
+
+impl EditorMode for SearchMode {
+    fn key_tapped(&mut self, key: Key) -> EditorAction {
+        match key {
+            Key::Enter => EditorAction::Search {
+                pattern: std::mem::take(&mut self.pattern),
+                backward: self.backward,
+            },
+            // Erasing past the start of the pattern closes the prompt, like in Vim
+            Key::Backspace if self.pattern.is_empty() => EditorAction::SwitchToNormalMode,
+            Key::Backspace => {
+                self.pattern.pop();
+                self.preview()
+            }
+            Key::Escape => EditorAction::SwitchToNormalMode,
+            Key::Tab => {
+                self.pattern.push('\t');
+                self.preview()
+            }
+            Key::Char(c) => {
+                self.pattern.push(c);
+                self.preview()
+            }
+            _ => EditorAction::None,
+        }
+    }
+
+    fn get_description(&self) -> String {
+        let prompt = if self.backward { '?' } else { '/' };
+        format!("{prompt}{}", self.pattern)
+    }
+}
@@ -1,4 +1,12 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::ops::Range;
+
+use crate::cursor::Cursor;
 use crate::editor_model::EditorModel;
+use crate::graphemes::{byte_to_grapheme, grapheme_to_byte, graphemes};
+use crate::search::find_next;
+use crate::text_buffer::TextBuffer;
 
 /// A 2D point
 /// Represents the position
@@ -13,73 +21,169 @@ impl Position {
     }
 }
 
+/// Defines how the text between the two positions of a motion is affected by an operator
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MotionKind {
+    /// The character at the end position is not included (for instance `w`)
+    Exclusive,
+    /// The character at the end position is included (for instance `f`)
+    Inclusive,
+    /// All the lines between the two positions are included (for instance `j`)
+    Linewise,
+}
+
 #[derive(Clone, Debug)]
 /// Defines a motion
 pub enum Motion {
     Words {
         n_words: i32,
     },
+    /// move by n characters on the same line. Can be negative
+    Chars { n: i32 },
+    /// move by n lines. Can be negative
+    Lines { n: i32 },
     /// move forward until the next occurrence of the provided character is found on the same line
-    Forward { ch: u32 },
+    Forward { ch: char },
     /// move backward until the next occurrence of the provided character is found on the same line
-    Backward { ch: u32 },
+    Backward { ch: char },
+    /// move forward until the character before the next occurrence of the provided character
+    TillForward { ch: char },
+    /// move backward until the character after the previous occurrence of the provided character
+    TillBackward { ch: char },
+    /// move to the first character of the line
+    LineStart,
+    /// move to the first non-blank character of the line
+    FirstNonBlank,
+    /// move to the last character of the line
+    LineEnd,
+    /// move by words delimited by blanks only (WORDs). Can be negative
+    BigWords { n_words: i32 },
+    /// move to the end of the n-th next word, or of the n-th previous word if n is negative
+    WordEnds { n_words: i32, big: bool },
+    /// move to the provided line (starting at 1), or to the last line if None
+    GotoLine { line: Option<usize> },
+    /// move to the bracket matching the next bracket of the line
+    MatchingBracket,
+    /// move to the first, middle or last line displayed on the screen
+    ScreenTop,
+    ScreenMiddle,
+    ScreenBottom,
+    /// covers a text object around the cursor, such as a word or the content of parentheses
+    TextObject { object: TextObject },
+    /// move to the next match of the last search, in the direction of the search, or in the
+    /// other direction if `reverse` is set
+    SearchNext { reverse: bool },
+    /// applies the motion several times, for instance "3fx" to go to the third 'x'
+    Repeat { motion: Box<Motion>, count: usize },
 }
 
 impl Motion {
-    /// Returns the start and end position of the given selection
+    /// Returns how an operator uses the range of this motion
+    pub fn kind(&self) -> MotionKind {
+        match self {
+            Motion::Words { .. }
+            | Motion::Chars { .. }
+            | Motion::Backward { .. }
+            | Motion::TillBackward { .. }
+            | Motion::LineStart
+            | Motion::FirstNonBlank
+            | Motion::BigWords { .. }
+            | Motion::SearchNext { .. } => MotionKind::Exclusive,
+            Motion::Forward { .. }
+            | Motion::TillForward { .. }
+            | Motion::LineEnd
+            | Motion::WordEnds { .. }
+            | Motion::MatchingBracket => MotionKind::Inclusive,
+            Motion::Lines { .. }
+            | Motion::GotoLine { .. }
+            | Motion::ScreenTop
+            | Motion::ScreenMiddle
+            | Motion::ScreenBottom => MotionKind::Linewise,
+            Motion::TextObject { object } if object.is_linewise() => MotionKind::Linewise,
+            Motion::TextObject { .. } => MotionKind::Exclusive,
+            Motion::Repeat { motion, .. } => motion.kind(),
+        }
+    }
+
+    /// Returns the `reverse` flag of a search motion, or None for the other motions
+    pub fn search_reverse(&self) -> Option<bool> {
+        match self {
+            Motion::SearchNext { reverse } => Some(*reverse),
+            Motion::Repeat { motion, .. } => motion.search_reverse(),
+            _ => None,
+        }
+    }
+
+    /// Returns the start and end position of the given selection, or None if the motion fails
+    /// (for instance, if the searched character is not found).
     ///
     /// Implementation problem
     /// - this function uses an assumption on the model : it only works with the current
     ///   representation of a file
-    pub fn apply(&self, model: &EditorModel) -> (Position, Position) {
+    pub fn apply(&self, model: &EditorModel) -> Option<(Position, Position)> {
+        self.apply_from(model, model.get_cursor())
+    }
+
+    /// Same as `apply`, but the motion starts from the provided cursor
+    fn apply_from(&self, model: &EditorModel, cursor: &Cursor) -> Option<(Position, Position)> {
         use Motion::*;
-        let lines = model.get_lines();
-        let cursor = model.get_cursor();
+        let buffer = model.get_buffer();
 
         match *self {
             Forward { ch } => {
-                let ch = char::from_u32(ch).unwrap();
+                let ch = ch.to_string();
                 let mut x_pos = cursor.x + 1;
-                let line = &lines[cursor.y];
-                let chars: Vec<char> = line.chars().collect();
-                while x_pos < line.len() {
+                let line = buffer.line(cursor.y);
+                let chars = graphemes(&line);
+                while x_pos < chars.len() {
                     if ch == chars[x_pos] {
-                        return (Position(cursor.x, cursor.y), Position(x_pos, cursor.y));
+                        return Some((Position(cursor.x, cursor.y), Position(x_pos, cursor.y)));
                     }
                     x_pos += 1;
                 }
 
                 // If we reach this, it means there was no match 
-                return (Position(cursor.x, cursor.y), Position(cursor.x, cursor.y));
+                None
             }
             Backward { ch } => {
-                let ch = char::from_u32(ch).unwrap();
-                let mut x_pos = cursor.x - 1;
-                let line = &lines[cursor.y];
-                let chars: Vec<char> = line.chars().collect();
+                let ch = ch.to_string();
+                let line = buffer.line(cursor.y);
+                let chars = graphemes(&line);
+                let mut x_pos = cursor.x.min(chars.len());
                 while x_pos > 0 {
+                    x_pos -= 1;
                     if ch == chars[x_pos] {
-                        return (Position(cursor.x, cursor.y), Position(x_pos, cursor.y));
+                        return Some((Position(cursor.x, cursor.y), Position(x_pos, cursor.y)));
                     }
-                    x_pos -= 1;
                 }
                 // If we reach this, it means there was no match 
-                return (Position(cursor.x, cursor.y), Position(cursor.x, cursor.y));
+                None
+            }
+            Chars { n } => {
+                let n_chars = graphemes(&buffer.line(cursor.y)).len() as i32;
+                let x_pos = (cursor.x as i32 + n).clamp(0, n_chars.max(0)) as usize;
+                Some((Position(cursor.x, cursor.y), Position(x_pos, cursor.y)))
+            }
+            Lines { n } => {
+                let last_line = buffer.len_lines() as i32 - 1;
+                let y_pos = (cursor.y as i32 + n).clamp(0, last_line) as usize;
+                let x_pos = cursor.x.min(graphemes(&buffer.line(y_pos)).len());
+                Some((Position(cursor.x, cursor.y), Position(x_pos, y_pos)))
             }
             Words { n_words } => {
                 // Get the lines
-                let n_lines = lines.len();
+                let n_lines = buffer.len_lines();
                 let mut x_pos = cursor.x;
                 let mut y_pos = cursor.y;
 
-                let line = &lines[cursor.y];
-                let chars: Vec<char> = line.chars().collect();
+                let line = buffer.line(cursor.y);
+                let chars = graphemes(&line);
 
                 let mut to_process = n_words.abs();
 
                 // If you're going backward at the first character before you
                 // is a separator, then you will have to skip it
-                if n_words < 0 && x_pos > 0 && chars[x_pos - 1] == ' ' {
+                if n_words < 0 && x_pos > 0 && chars[x_pos - 1] == " " {
                     to_process += 1;
                 }
 
@@ -87,16 +191,18 @@ impl Motion {
                 while to_process > 0 {
 
                     // Necessary to update the array chars
-                    let line = &lines[y_pos];
-                    let chars: Vec<char> = line.chars().collect();
+                    let line = buffer.line(y_pos);
+                    let chars = graphemes(&line);
 
                     if n_words > 0 { // going forward
                         if chars.len() == 0 {
-                            y_pos += 1;
+                            if y_pos < n_lines - 1 {
+                                y_pos += 1;
+                            }
                             to_process -= 1;
                         } else if x_pos < chars.len() - 1 {
                             x_pos += 1;
-                            if chars[x_pos] == ' ' {
+                            if chars[x_pos] == " " {
                                 to_process -= 1;
                                 x_pos += 1;
                             }
@@ -111,15 +217,16 @@ impl Motion {
                         eprintln!("{x_pos}, {y_pos}, {chars:?}");
                         if x_pos > 0 {
                             x_pos -= 1;
-                            if chars[x_pos] == ' ' || x_pos == 0 {
+                            if chars[x_pos] == " " || x_pos == 0 {
                                 to_process -= 1;
                             }
                         } else if x_pos == 0 {
                             if y_pos > 0 {
                                 // move one line up
                                 y_pos -= 1;
-                                if lines[y_pos].len() > 0 {
-                                    x_pos = lines[y_pos].len() - 1;
+                                let n = graphemes(&buffer.line(y_pos)).len();
+                                if n > 0 {
+                                    x_pos = n - 1;
                                 } else {
                                     x_pos = 0;
                                 }
@@ -135,14 +242,754 @@ impl Motion {
                     x_pos += 1;
                 }
 
-                return (Position(cursor.x, cursor.y), Position(x_pos, y_pos));
+                Some((Position(cursor.x, cursor.y), Position(x_pos, y_pos)))
+            }
+            TillForward { ch } => {
+                let (start, end) = Forward { ch }.apply_from(model, cursor)?;
+                Some((start, Position(end.x() - 1, end.y())))
+            }
+            TillBackward { ch } => {
+                let (start, end) = Backward { ch }.apply_from(model, cursor)?;
+                Some((start, Position(end.x() + 1, end.y())))
+            }
+            LineStart => Some((Position(cursor.x, cursor.y), Position(0, cursor.y))),
+            FirstNonBlank => {
+                let x_pos = first_non_blank(&buffer.line(cursor.y));
+                Some((Position(cursor.x, cursor.y), Position(x_pos, cursor.y)))
+            }
+            LineEnd => {
+                let x_pos = graphemes(&buffer.line(cursor.y)).len().max(1) - 1;
+                Some((Position(cursor.x, cursor.y), Position(x_pos, cursor.y)))
+            }
+            BigWords { n_words } => {
+                let chars = BufferChars::new(buffer);
+                let mut i = buffer.byte_to_char(cursor_offset(buffer, cursor.x, cursor.y));
+                for _ in 0..n_words.abs() {
+                    i = if n_words > 0 {
+                        next_word_start(&chars, i)
+                    } else {
+                        previous_word_start(&chars, i)
+                    };
+                }
+                Some((Position(cursor.x, cursor.y), char_to_position(buffer, i)))
+            }
+            WordEnds { n_words, big } => {
+                let chars = BufferChars::new(buffer);
+                let mut i = buffer.byte_to_char(cursor_offset(buffer, cursor.x, cursor.y));
+                for _ in 0..n_words.abs() {
+                    i = if n_words > 0 {
+                        next_word_end(&chars, i, big)
+                    } else {
+                        previous_word_end(&chars, i, big)
+                    };
+                }
+                Some((Position(cursor.x, cursor.y), char_to_position(buffer, i)))
+            }
+            GotoLine { line } => {
+                let last_line = buffer.len_lines() - 1;
+                let y_pos = line.map_or(last_line, |line| line.max(1) - 1).min(last_line);
+                let x_pos = first_non_blank(&buffer.line(y_pos));
+                Some((Position(cursor.x, cursor.y), Position(x_pos, y_pos)))
+            }
+            MatchingBracket => {
+                let chars = BufferChars::new(buffer);
+                let start = buffer.byte_to_char(cursor_offset(buffer, cursor.x, cursor.y));
+                // The bracket under the cursor, or the next one on the line
+                let i = (start..chars.len())
+                    .take_while(|i| chars.at(*i) != '\n')
+                    .find(|i| "()[]{}".contains(chars.at(*i)))?;
+                let i = matching_bracket(&chars, i)?;
+                Some((Position(cursor.x, cursor.y), char_to_position(buffer, i)))
+            }
+            ScreenTop | ScreenMiddle | ScreenBottom => {
+                let screen = model.get_screen_info();
+                let last_line = buffer.len_lines() - 1;
+                let top = (screen.top.max(0) as usize).min(last_line);
+                let bottom = if screen.h > 0 {
+                    (top + screen.h as usize - 1).min(last_line)
+                } else {
+                    last_line
+                };
+                let y_pos = match *self {
+                    ScreenTop => top,
+                    ScreenMiddle => (top + bottom) / 2,
+                    _ => bottom,
+                };
+                let x_pos = first_non_blank(&buffer.line(y_pos));
+                Some((Position(cursor.x, cursor.y), Position(x_pos, y_pos)))
+            }
+            Repeat { ref motion, count } => {
+                // A till motion stops next to the character: it is repeated as the motion that
+                // reaches the character, otherwise it would find the same character again
+                let (motion, shift) = match **motion {
+                    TillForward { ch } => (Forward { ch }, -1),
+                    TillBackward { ch } => (Backward { ch }, 1),
+                    ref motion => (motion.clone(), 0),
+                };
+                let mut position = *cursor;
+                for _ in 0..count {
+                    let (_, end) = motion.apply_from(model, &position)?;
+                    position = Cursor { x: end.x(), y: end.y() };
+                }
+                let x_pos = (position.x as i32 + shift) as usize;
+                Some((Position(cursor.x, cursor.y), Position(x_pos, position.y)))
+            }
+            SearchNext { reverse } => {
+                let (pattern, backward) = model.get_search()?;
+                let (end, _) = find_next(buffer, pattern, cursor, backward != reverse)?;
+                Some((Position(cursor.x, cursor.y), Position(end.x, end.y)))
+            }
+            TextObject { object } => {
+                let at = cursor_offset(buffer, cursor.x, cursor.y);
+                let range = object.range(buffer, at)?;
+                Some((offset_to_position(buffer, range.start), offset_to_position(buffer, range.end)))
+            }
+        }
+    }
+}
+
+/// Returns the byte offset in the buffer of the grapheme `x` of the line `y`
+fn cursor_offset(buffer: &TextBuffer, x: usize, y: usize) -> usize {
+    buffer.line_to_byte(y) + grapheme_to_byte(&buffer.line(y), x)
+}
+
+/// Converts the index of a char of the buffer into a position
+fn char_to_position(buffer: &TextBuffer, i: usize) -> Position {
+    offset_to_position(buffer, buffer.char_to_byte(i))
+}
+
+/// Returns the index of the first non-blank grapheme of the line
+pub fn first_non_blank(line: &str) -> usize {
+    graphemes(line)
+        .iter()
+        .position(|grapheme| !grapheme.trim().is_empty())
+        .unwrap_or(0)
+}
+
+/// Converts a byte offset of the buffer into a position
+fn offset_to_position(buffer: &TextBuffer, offset: usize) -> Position {
+    let y = buffer.byte_to_line(offset);
+    let line = buffer.line(y);
+    Position(byte_to_grapheme(&line, offset - buffer.line_to_byte(y)), y)
+}
+
+/// The chars of the buffer, indexed like in the whole buffer, but read line by line the first
+/// time they are needed: the motions and text objects only look at the text around the cursor.
+struct BufferChars<'a> {
+    buffer: &'a TextBuffer,
+    read: RefCell<ReadLines>,
+}
+
+/// The lines read by `BufferChars`, with their line breaks
+struct ReadLines {
+    lines: Range<usize>,
+    /// Index of the first char of the lines in the buffer
+    start: usize,
+    chars: VecDeque<char>,
+}
+
+impl<'a> BufferChars<'a> {
+    fn new(buffer: &'a TextBuffer) -> Self {
+        let read = ReadLines { lines: 0..0, start: 0, chars: VecDeque::new() };
+        Self { buffer, read: RefCell::new(read) }
+    }
+
+    fn len(&self) -> usize {
+        self.buffer.len_chars()
+    }
+
+    /// Returns the char at the index `i` of the buffer, reading the lines up to its line
+    fn at(&self, i: usize) -> char {
+        assert!(i < self.len(), "char index out of bounds");
+        let mut read = self.read.borrow_mut();
+        if read.lines.is_empty() {
+            let y = self.buffer.byte_to_line(self.buffer.char_to_byte(i));
+            read.lines = y..y;
+            read.start = self.buffer.byte_to_char(self.buffer.line_to_byte(y));
+        }
+        while i < read.start {
+            let y = read.lines.start - 1;
+            for ch in self.line(y).chars().rev() {
+                read.chars.push_front(ch);
+                read.start -= 1;
+            }
+            read.lines.start = y;
+        }
+        while i >= read.start + read.chars.len() {
+            let y = read.lines.end;
+            read.chars.extend(self.line(y).chars());
+            read.lines.end = y + 1;
+        }
+        read.chars[i - read.start]
+    }
+
+    /// Returns the content of the line `y`, with its line break
+    fn line(&self, y: usize) -> String {
+        let mut line = self.buffer.line(y);
+        if y + 1 < self.buffer.len_lines() {
+            line.push('\n');
+        }
+        line
+    }
+}
+
+/// The kinds of text objects
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextObjectKind {
+    /// A sequence of letters, digits and underscores, or a sequence of other non-blank characters
+    Word,
+    /// A sequence of non-blank characters
+    BigWord,
+    /// Text ending with '.', '!' or '?' followed by a blank
+    Sentence,
+    /// Lines between blank lines
+    Paragraph,
+    /// Text between a pair of brackets, which can be nested
+    Block { open: char, close: char },
+    /// Text between two quotes on the same line
+    Quote { quote: char },
+    /// Text between an XML/HTML opening tag and its closing tag
+    Tag,
+}
+
+/// A text object is a piece of text around the cursor, selected after an operator or in visual
+/// mode: `diw` deletes a word, `ci"` changes the text between quotes, `va(` selects parentheses.
+///
+/// The inner object (`i`) is the object itself, or the content between the delimiters. The
+/// object with `a` also includes the surrounding white space, or the delimiters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextObject {
+    pub kind: TextObjectKind,
+    pub inner: bool,
+}
+
+impl TextObject {
+    /// Returns the text object typed after `i` (if `inner`) or `a`, such as `w` or `(`
+    pub fn from_keys(inner: bool, ch: char) -> Option<TextObject> {
+        use TextObjectKind::*;
+        let kind = match ch {
+            'w' => Word,
+            'W' => BigWord,
+            's' => Sentence,
+            'p' => Paragraph,
+            '(' | ')' | 'b' => Block { open: '(', close: ')' },
+            '{' | '}' | 'B' => Block { open: '{', close: '}' },
+            '[' | ']' => Block { open: '[', close: ']' },
+            '<' | '>' => Block { open: '<', close: '>' },
+            '"' | '\'' | '`' => Quote { quote: ch },
+            't' => Tag,
+            _ => return None,
+        };
+        Some(TextObject { kind, inner })
+    }
+
+    /// Returns true if the object is made of whole lines
+    pub fn is_linewise(&self) -> bool {
+        self.kind == TextObjectKind::Paragraph
+    }
+
+    /// Returns the byte range of the text covered by the object around the byte offset `at` of
+    /// the buffer, or None if there is no such object.
+    pub fn range(&self, buffer: &TextBuffer, at: usize) -> Option<Range<usize>> {
+        let chars = BufferChars::new(buffer);
+        if chars.len() == 0 {
+            return None;
+        }
+        let i = buffer.byte_to_char(at).min(chars.len() - 1);
+
+        let range = match self.kind {
+            TextObjectKind::Word => word_object(&chars, i, self.inner, false)?,
+            TextObjectKind::BigWord => word_object(&chars, i, self.inner, true)?,
+            TextObjectKind::Sentence => sentence_object(&chars, i, self.inner),
+            TextObjectKind::Paragraph => return Some(paragraph_object(buffer, buffer.byte_to_line(at), self.inner)),
+            TextObjectKind::Block { open, close } => {
+                block_object(&chars, i, open, close, self.inner)?
+            }
+            TextObjectKind::Quote { quote } => quote_object(&chars, i, quote, self.inner)?,
+            TextObjectKind::Tag => tag_object(&chars, i, self.inner)?,
+        };
+
+        // Convert the char indices into byte offsets
+        Some(buffer.char_to_byte(range.start)..buffer.char_to_byte(range.end))
+    }
+}
+
+// The functions below work on the chars of the buffer, and return ranges of char indices.
+
+/// Class of a character for the word objects: characters of the same class form a word
+fn word_class(ch: char, big: bool) -> u8 {
+    if ch == '\n' {
+        0
+    } else if ch.is_whitespace() {
+        1
+    } else if big || ch.is_alphanumeric() || ch == '_' {
+        2
+    } else {
+        3
+    }
+}
+
+/// Returns the range of consecutive characters of the same class as the one at `i`
+fn word_run(chars: &BufferChars, i: usize, big: bool) -> Range<usize> {
+    let class = word_class(chars.at(i), big);
+    let mut start = i;
+    while start > 0 && word_class(chars.at(start - 1), big) == class {
+        start -= 1;
+    }
+    let mut end = i + 1;
+    while end < chars.len() && word_class(chars.at(end), big) == class {
+        end += 1;
+    }
+    start..end
+}
+
+fn word_object(chars: &BufferChars, i: usize, inner: bool, big: bool) -> Option<Range<usize>> {
+    let class = word_class(chars.at(i), big);
+    if class == 0 {
+        return None;
+    }
+    let mut range = word_run(chars, i, big);
+    if inner {
+        return Some(range);
+    }
+
+    let is_blank = |index: usize| word_class(chars.at(index), big) == 1;
+    if class == 1 {
+        // On blanks, the word after them is included
+        if range.end < chars.len() && word_class(chars.at(range.end), big) >= 2 {
+            range.end = word_run(chars, range.end, big).end;
+        }
+    } else if range.end < chars.len() && is_blank(range.end) {
+        range.end = word_run(chars, range.end, big).end;
+    } else if range.start > 0 && is_blank(range.start - 1) {
+        range.start = word_run(chars, range.start - 1, big).start;
+    }
+    Some(range)
+}
+
+/// Returns true if the char at `i` is the line break of an empty line, which counts as a word
+fn is_empty_line(chars: &BufferChars, i: usize) -> bool {
+    chars.at(i) == '\n' && (i == 0 || chars.at(i - 1) == '\n')
+}
+
+/// Returns the start of the next WORD after the char `i`
+fn next_word_start(chars: &BufferChars, i: usize) -> usize {
+    let n = chars.len();
+    let mut j = i;
+    if j < n && word_class(chars.at(j), true) == 2 {
+        j = word_run(chars, j, true).end;
+    }
+    while j < n && word_class(chars.at(j), true) < 2 && !(j > i && is_empty_line(chars, j)) {
+        j += 1;
+    }
+    j
+}
+
+/// Returns the start of the WORD before the char `i`
+fn previous_word_start(chars: &BufferChars, i: usize) -> usize {
+    let mut j = i.min(chars.len());
+    while j > 0 {
+        j -= 1;
+        if word_class(chars.at(j), true) == 2 {
+            return word_run(chars, j, true).start;
+        }
+        if is_empty_line(chars, j) {
+            return j;
+        }
+    }
+    0
+}
+
+/// Returns the end of the next word after the char `i`
+fn next_word_end(chars: &BufferChars, i: usize, big: bool) -> usize {
+    let n = chars.len();
+    let mut j = i + 1;
+    while j < n && word_class(chars.at(j), big) < 2 {
+        j += 1;
+    }
+    if j >= n {
+        return i;
+    }
+    word_run(chars, j, big).end - 1
+}
+
+/// Returns the end of the word before the char `i`
+fn previous_word_end(chars: &BufferChars, i: usize, big: bool) -> usize {
+    let mut j = i.min(chars.len());
+    if j < chars.len() && word_class(chars.at(j), big) >= 2 {
+        j = word_run(chars, j, big).start;
+    }
+    while j > 0 {
+        j -= 1;
+        if word_class(chars.at(j), big) >= 2 || is_empty_line(chars, j) {
+            return j;
+        }
+    }
+    0
+}
+
+/// Returns the index of the bracket matching the one at `i`, taking nesting into account
+fn matching_bracket(chars: &BufferChars, i: usize) -> Option<usize> {
+    let (open, close, forward) = match chars.at(i) {
+        '(' => ('(', ')', true),
+        '[' => ('[', ']', true),
+        '{' => ('{', '}', true),
+        ')' => ('(', ')', false),
+        ']' => ('[', ']', false),
+        '}' => ('{', '}', false),
+        _ => return None,
+    };
+    if forward {
+        block_object(chars, i, open, close, false).map(|range| range.end - 1)
+    } else {
+        block_object(chars, i, open, close, false).map(|range| range.start)
+    }
+}
+
+/// Returns true if there is an empty line starting at the line break `i`
+fn is_paragraph_break(chars: &BufferChars, i: usize) -> bool {
+    chars.at(i) == '\n' && i + 1 < chars.len() && chars.at(i + 1) == '\n'
+}
+
+/// Returns true if the character at `i` ends a sentence: a '.', '!' or '?', possibly followed by
+/// closing parentheses or quotes
+fn ends_sentence(chars: &BufferChars, i: usize) -> bool {
+    let mut j = i;
+    while j > 0 && ")]\"'".contains(chars.at(j)) {
+        j -= 1;
+    }
+    ".!?".contains(chars.at(j))
+}
+
+fn sentence_object(chars: &BufferChars, i: usize, inner: bool) -> Range<usize> {
+    let n = chars.len();
+    let is_blank = |index: usize| chars.at(index).is_whitespace();
+
+    // Go back to the end of the previous sentence, then skip the blanks after it
+    let mut start = i;
+    while start > 0 {
+        let after_paragraph = start >= 2 && chars.at(start - 1) == '\n' && chars.at(start - 2) == '\n';
+        let after_sentence = is_blank(start) && ends_sentence(chars, start - 1);
+        if after_paragraph || after_sentence {
+            break;
+        }
+        start -= 1;
+    }
+    while start < n && is_blank(start) {
+        start += 1;
+    }
+
+    let mut end = start;
+    while end < n {
+        if is_paragraph_break(chars, end) || (end > 0 && is_blank(end) && ends_sentence(chars, end - 1)) {
+            break;
+        }
+        end += 1;
+    }
+    if inner {
+        return start..end;
+    }
+
+    // Include the blanks after the sentence, or before it if there are none
+    let mut outer_end = end;
+    while outer_end < n && is_blank(outer_end) && !is_paragraph_break(chars, outer_end) {
+        outer_end += 1;
+    }
+    let mut outer_start = start;
+    if outer_end == end {
+        while outer_start > 0 && chars.at(outer_start - 1) != '\n' && is_blank(outer_start - 1) {
+            outer_start -= 1;
+        }
+    }
+    outer_start..outer_end
+}
+
+/// Returns the byte range of the paragraph around the line `y`, without the last line break
+fn paragraph_object(buffer: &TextBuffer, y: usize, inner: bool) -> Range<usize> {
+    let n_lines = buffer.len_lines();
+    let is_blank = |line: usize| buffer.line(line).trim().is_empty();
+    // Returns the range of lines with the same blankness as the provided one
+    let run = |line: usize| {
+        let blank = is_blank(line);
+        let mut first = line;
+        while first > 0 && is_blank(first - 1) == blank {
+            first -= 1;
+        }
+        let mut last = line;
+        while last + 1 < n_lines && is_blank(last + 1) == blank {
+            last += 1;
+        }
+        (first, last)
+    };
+
+    let (mut first, mut last) = run(y);
+    if !inner {
+        if last + 1 < n_lines {
+            last = run(last + 1).1;
+        } else if first > 0 && !is_blank(y) {
+            first = run(first - 1).0;
+        }
+    }
+    buffer.line_to_byte(first)..buffer.line_range(last).end
+}
+
+fn block_object(chars: &BufferChars, i: usize, open: char, close: char, inner: bool) -> Option<Range<usize>> {
+    // Find the opening bracket, skipping the pairs that are closed before the cursor
+    let mut start = None;
+    let mut depth = 0;
+    let mut index = if chars.at(i) == close { i.checked_sub(1)? } else { i };
+    loop {
+        if chars.at(index) == open {
+            if depth == 0 {
+                start = Some(index);
+                break;
+            }
+            depth -= 1;
+        } else if chars.at(index) == close {
+            depth += 1;
+        }
+        if index == 0 {
+            break;
+        }
+        index -= 1;
+    }
+    let start = start?;
+
+    // Find the matching closing bracket
+    let mut depth = 0;
+    let end = (start + 1..chars.len()).find(|index| {
+        if chars.at(*index) == open {
+            depth += 1;
+        } else if chars.at(*index) == close {
+            if depth == 0 {
+                return true;
+            }
+            depth -= 1;
+        }
+        false
+    })?;
+
+    if inner {
+        Some(start + 1..end)
+    } else {
+        Some(start..end + 1)
+    }
+}
+
+fn quote_object(chars: &BufferChars, i: usize, quote: char, inner: bool) -> Option<Range<usize>> {
+    // Quotes only work on the current line
+    let line_start = (0..i).rposition(|index| chars.at(index) == '\n').map_or(0, |p| p + 1);
+    let line_end = (i..chars.len()).find(|index| chars.at(*index) == '\n').unwrap_or(chars.len());
+    let quotes: Vec<usize> = (line_start..line_end)
+        .filter(|index| chars.at(*index) == quote && (*index == 0 || chars.at(index - 1) != '\\'))
+        .collect();
+
+    // The quotes are paired from the start of the line
+    let pairs: Vec<(usize, usize)> = quotes.chunks_exact(2).map(|pair| (pair[0], pair[1])).collect();
+    let (start, end) = pairs
+        .iter()
+        .find(|(start, end)| *start <= i && i <= *end)
+        .or_else(|| pairs.iter().find(|(start, _)| *start > i))
+        .copied()?;
+
+    if inner {
+        return Some(start + 1..end);
+    }
+    // Include the blanks after the closing quote, or before the opening one if there are none
+    let is_space = |index: usize| chars.at(index) == ' ' || chars.at(index) == '\t';
+    let mut outer_end = end + 1;
+    while outer_end < line_end && is_space(outer_end) {
+        outer_end += 1;
+    }
+    let mut outer_start = start;
+    if outer_end == end + 1 {
+        while outer_start > line_start && is_space(outer_start - 1) {
+            outer_start -= 1;
+        }
+    }
+    Some(outer_start..outer_end)
+}
+
+/// An XML/HTML tag, with the name of the element it opens or closes
+struct Tag {
+    range: Range<usize>,
+    kind: TagKind,
+}
+
+enum TagKind {
+    Opening(String),
+    Closing(String),
+    /// A self-closing tag, a comment or a declaration
+    Other,
+}
+
+/// Returns the tag starting with the '<' at `index`, or None if it is never closed
+fn tag_at(chars: &BufferChars, index: usize) -> Option<Tag> {
+    let end = (index + 1..chars.len()).find(|j| chars.at(*j) == '>')?;
+    let content: String = (index + 1..end).map(|j| chars.at(j)).collect();
+    let kind = if let Some(name) = content.strip_prefix('/') {
+        TagKind::Closing(name.trim().to_string())
+    } else if !content.ends_with('/') && !content.starts_with(['!', '?']) {
+        TagKind::Opening(content.split_whitespace().next().unwrap_or("").to_string())
+    } else {
+        TagKind::Other
+    };
+    Some(Tag { range: index..end + 1, kind })
+}
+
+/// Returns the range of the tag closing the element `name` opened before `from`, skipping the
+/// nested elements of the same name
+fn closing_tag(chars: &BufferChars, name: &str, from: usize) -> Option<Range<usize>> {
+    let mut depth = 0;
+    let mut index = from;
+    while index < chars.len() {
+        if chars.at(index) != '<' {
+            index += 1;
+            continue;
+        }
+        let tag = tag_at(chars, index)?;
+        match &tag.kind {
+            TagKind::Opening(opened) if opened == name => depth += 1,
+            TagKind::Closing(closed) if closed == name => {
+                if depth == 0 {
+                    return Some(tag.range);
+                }
+                depth -= 1;
+            }
+            _ => {}
+        }
+        index = tag.range.end;
+    }
+    None
+}
+
+fn tag_object(chars: &BufferChars, i: usize, inner: bool) -> Option<Range<usize>> {
+    // Go back from the cursor to the innermost opening tag whose element contains it. The
+    // closing tags met on the way are matched with the opening tags before them.
+    let mut closed: Vec<(String, Range<usize>)> = Vec::new();
+    let mut index = i + 1;
+    let (opening, closing) = loop {
+        if index == 0 {
+            return None;
+        }
+        index -= 1;
+        if chars.at(index) != '<' {
+            continue;
+        }
+        let Some(tag) = tag_at(chars, index) else {
+            continue;
+        };
+        match tag.kind {
+            TagKind::Closing(name) => closed.push((name, tag.range)),
+            TagKind::Opening(name) => {
+                let closing = match closed.iter().rposition(|(closed_name, _)| *closed_name == name) {
+                    Some(position) => {
+                        let (_, closing) = closed.remove(position);
+                        closed.truncate(position);
+                        Some(closing)
+                    }
+                    None => closing_tag(chars, &name, tag.range.end),
+                };
+                // The element may end before the cursor, unless the cursor is on its closing tag
+                if let Some(closing) = closing.filter(|closing| closing.end > i) {
+                    break (tag.range, closing);
+                }
             }
+            TagKind::Other => {}
         }
+    };
+    if inner {
+        Some(opening.end..closing.start)
+    } else {
+        Some(opening.start..closing.end)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::motion::*;
+
+    /// Returns the text covered by the object typed with the provided keys, around the first
+    /// occurrence of `at` in the text
+    fn object_text<'a>(text: &'a str, keys: &str, at: &str) -> Option<&'a str> {
+        let mut keys = keys.chars();
+        let inner = keys.next() == Some('i');
+        let object = TextObject::from_keys(inner, keys.next().unwrap()).unwrap();
+        let range = object.range(&TextBuffer::from(text), text.find(at).unwrap())?;
+        Some(&text[range])
+    }
+
+    #[test]
+    fn test_buffer_chars() {
+        let buffer = TextBuffer::from("one\ntwo\nthree\nfour");
+        let chars = BufferChars::new(&buffer);
+        assert_eq!(chars.len(), 18);
+        // Only the lines around the requested chars are read
+        assert_eq!(chars.at(5), 'w');
+        assert_eq!(chars.read.borrow().lines, 1..2);
+        assert_eq!(chars.at(3), '\n');
+        assert_eq!(chars.read.borrow().lines, 0..2);
+        assert_eq!(chars.at(17), 'r');
+        assert_eq!(chars.read.borrow().lines, 0..4);
+    }
+
+    #[test]
+    fn test_word_objects() {
+        let text = "let foo_bar = baz.qux;";
+        assert_eq!(object_text(text, "iw", "oo_"), Some("foo_bar"));
+        assert_eq!(object_text(text, "aw", "oo_"), Some("foo_bar "));
+        assert_eq!(object_text(text, "iw", "qux"), Some("qux"));
+        assert_eq!(object_text(text, "iW", "qux"), Some("baz.qux;"));
+        // No blank after the last WORD: the blank before is included
+        assert_eq!(object_text(text, "aW", "qux"), Some(" baz.qux;"));
+        assert_eq!(object_text(text, "aw", " ="), Some(" ="));
+    }
+
+    #[test]
+    fn test_sentence_and_paragraph_objects() {
+        let text = "First one. Second (yes) one! Third\non two lines.\n\nNext paragraph.";
+        assert_eq!(object_text(text, "is", "yes"), Some("Second (yes) one!"));
+        assert_eq!(object_text(text, "as", "yes"), Some("Second (yes) one! "));
+        assert_eq!(object_text(text, "is", "two"), Some("Third\non two lines."));
+        assert_eq!(object_text(text, "ip", "two"), Some("First one. Second (yes) one! Third\non two lines."));
+        assert_eq!(
+            object_text(text, "ap", "yes"),
+            Some("First one. Second (yes) one! Third\non two lines.\n")
+        );
+        assert_eq!(object_text(text, "ap", "Next"), Some("\nNext paragraph."));
+    }
+
+    #[test]
+    fn test_block_objects() {
+        let text = "f(a, (b + c), d) { x[0] }";
+        assert_eq!(object_text(text, "i(", "a,"), Some("a, (b + c), d"));
+        assert_eq!(object_text(text, "a(", "b +"), Some("(b + c)"));
+        assert_eq!(object_text(text, "ib", "(b"), Some("b + c"));
+        assert_eq!(object_text(text, "i)", "), d"), Some("b + c"));
+        assert_eq!(object_text(text, "a)", ", d"), Some("(a, (b + c), d)"));
+        assert_eq!(object_text(text, "iB", "x["), Some(" x[0] "));
+        assert_eq!(object_text(text, "a[", "0"), Some("[0]"));
+        assert_eq!(object_text(text, "i(", "{"), None);
+    }
+
+    #[test]
+    fn test_quote_and_tag_objects() {
+        let text = "say \"hello \\\"you\\\"\" and 'bye'\n<div><p>Some <b>bold</b> text</p></div>";
+        assert_eq!(object_text(text, "i\"", "hello"), Some("hello \\\"you\\\""));
+        assert_eq!(object_text(text, "a\"", "hello"), Some("\"hello \\\"you\\\"\" "));
+        // Before the quotes, the next pair is used
+        assert_eq!(object_text(text, "i'", "and"), Some("bye"));
+        assert_eq!(object_text(text, "it", "bold"), Some("bold"));
+        assert_eq!(object_text(text, "at", "bold"), Some("<b>bold</b>"));
+        assert_eq!(object_text(text, "it", "Some"), Some("Some <b>bold</b> text"));
+        assert_eq!(object_text(text, "at", "<p>"), Some("<p>Some <b>bold</b> text</p>"));
+        assert_eq!(object_text(text, "at", "/b>"), Some("<b>bold</b>"));
+        assert_eq!(object_text(text, "it", "say"), None);
+        // Nested elements of the same name
+        let text = "<div><div>a</div>b</div>";
+        assert_eq!(object_text(text, "it", "b<"), Some("<div>a</div>b"));
+        assert_eq!(object_text(text, "it", "a<"), Some("a"));
+    }
 
     #[test]
     fn basic_char_iterator() {
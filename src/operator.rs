@@ -0,0 +1,78 @@
+use crate::key::Key;
+
+/// An operator acts on the text covered by a motion.
+///
+/// In normal mode, typing an operator key waits for a motion: `dw` deletes up to the next word,
+/// `cfx` changes the text up to the next 'x', and `y3b` yanks the three previous words. Typing the
+/// operator key twice (`dd`, `cc`, `yy`) operates on whole lines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operator {
+    /// Removes the text and keeps it in the yanker
+    Delete,
+    /// Removes the text and switches to insert mode
+    Change,
+    /// Copies the text in the yanker
+    Yank,
+    /// Shifts the lines to the right
+    Indent,
+    /// Shifts the lines to the left
+    Outdent,
+    /// Switches the case of the letters
+    ToggleCase,
+    /// Makes the letters lowercase
+    Lowercase,
+    /// Makes the letters uppercase
+    Uppercase,
+}
+
+impl Operator {
+    /// Returns the operator started by the provided key, if any
+    pub fn from_key(key: Key) -> Option<Operator> {
+        match key {
+            Key::Char('d') => Some(Operator::Delete),
+            Key::Char('c') => Some(Operator::Change),
+            Key::Char('y') => Some(Operator::Yank),
+            Key::Char('>') => Some(Operator::Indent),
+            Key::Char('<') => Some(Operator::Outdent),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the operator acts on whole lines, whatever the motion, like `>w`
+    pub fn is_linewise(&self) -> bool {
+        matches!(self, Operator::Indent | Operator::Outdent)
+    }
+
+    /// Returns the text changed by a case operator, or None for the other operators
+    pub fn transform(&self, text: &str) -> Option<String> {
+        match self {
+            Operator::ToggleCase => Some(
+                text.chars()
+                    .flat_map(|c| {
+                        if c.is_uppercase() {
+                            c.to_lowercase().collect::<Vec<char>>()
+                        } else {
+                            c.to_uppercase().collect::<Vec<char>>()
+                        }
+                    })
+                    .collect(),
+            ),
+            Operator::Lowercase => Some(text.to_lowercase()),
+            Operator::Uppercase => Some(text.to_uppercase()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transform() {
+        assert_eq!(Operator::ToggleCase.transform("Hello World!"), Some("hELLO wORLD!".to_string()));
+        assert_eq!(Operator::Lowercase.transform("ÉtÉ"), Some("été".to_string()));
+        assert_eq!(Operator::Uppercase.transform("straße"), Some("STRASSE".to_string()));
+        assert_eq!(Operator::Delete.transform("text"), None);
+    }
+}
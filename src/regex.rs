@@ -0,0 +1,969 @@
+use std::collections::HashSet;
+
+/// The text a regex searches in, made of lines separated by line breaks.
+///
+/// The lines are asked one by one, so that a search does not need a copy of the whole text.
+pub trait Lines {
+    fn len_lines(&self) -> usize;
+    fn line(&self, y: usize) -> &str;
+}
+
+impl<S: AsRef<str>> Lines for [S] {
+    fn len_lines(&self) -> usize {
+        self.len()
+    }
+
+    fn line(&self, y: usize) -> &str {
+        self[y].as_ref()
+    }
+}
+
+/// A position in the searched text: a line, and a byte offset in this line
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Pos {
+    pub line: usize,
+    pub offset: usize,
+}
+
+/// A match of a regex, with the text matched by its groups `\(...\)`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Match {
+    pub start: Pos,
+    pub end: Pos,
+    /// Start and end of the groups 1 to 9, None if the group did not take part in the match
+    groups: Vec<Option<(Pos, Pos)>>,
+}
+
+impl Match {
+    /// Returns the start and the end of a group, the group 0 being the whole match
+    pub fn group(&self, i: usize) -> Option<(Pos, Pos)> {
+        match i {
+            0 => Some((self.start, self.end)),
+            i => *self.groups.get(i - 1)?,
+        }
+    }
+}
+
+/// Returns the text between two positions, with '\n' between the lines
+pub fn text_between<L: Lines + ?Sized>(lines: &L, start: Pos, end: Pos) -> String {
+    if start.line == end.line {
+        return lines.line(start.line)[start.offset..end.offset.max(start.offset)].to_string();
+    }
+    let mut text = lines.line(start.line)[start.offset..].to_string();
+    for y in start.line + 1..end.line {
+        text.push('\n');
+        text.push_str(lines.line(y));
+    }
+    text.push('\n');
+    text.push_str(&lines.line(end.line)[..end.offset]);
+    text
+}
+
+/// Returns true if the character is part of a word, for `\<`, `\>` and `\w`
+pub fn is_keyword_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// A class of characters, such as `[a-z]` or `\s`
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct CharClass {
+    negated: bool,
+    /// Set if the class also matches a line break, like `\_s` or `\_[a-z]`
+    newline: bool,
+    items: Vec<ClassItem>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ClassItem {
+    Range(char, char),
+    Named(NamedClass),
+}
+
+/// The classes of `\s`, `\d`, `[:alpha:]`, etc
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum NamedClass {
+    Space,
+    Digit,
+    Word,
+    Head,
+    Alpha,
+    Alnum,
+    Lower,
+    Upper,
+    Hex,
+    Octal,
+    Punct,
+}
+
+impl NamedClass {
+    /// Returns the class of an escape such as `\s`, and true if the escape is negated (`\S`)
+    fn from_escape(c: char) -> Option<(NamedClass, bool)> {
+        let class = match c.to_ascii_lowercase() {
+            's' => NamedClass::Space,
+            'd' => NamedClass::Digit,
+            'w' => NamedClass::Word,
+            'h' => NamedClass::Head,
+            'a' => NamedClass::Alpha,
+            'l' => NamedClass::Lower,
+            'u' => NamedClass::Upper,
+            'x' => NamedClass::Hex,
+            'o' => NamedClass::Octal,
+            _ => return None,
+        };
+        Some((class, c.is_ascii_uppercase()))
+    }
+
+    /// Returns the class of a bracket expression such as `[:alpha:]`
+    fn from_name(name: &str) -> Option<NamedClass> {
+        Some(match name {
+            "space" | "blank" => NamedClass::Space,
+            "digit" => NamedClass::Digit,
+            "alpha" => NamedClass::Alpha,
+            "alnum" => NamedClass::Alnum,
+            "lower" => NamedClass::Lower,
+            "upper" => NamedClass::Upper,
+            "xdigit" => NamedClass::Hex,
+            "punct" => NamedClass::Punct,
+            _ => return None,
+        })
+    }
+
+    fn contains(&self, c: char) -> bool {
+        match self {
+            NamedClass::Space => c == ' ' || c == '\t',
+            NamedClass::Digit => c.is_ascii_digit(),
+            NamedClass::Word => c.is_ascii_alphanumeric() || c == '_',
+            NamedClass::Head => c.is_ascii_alphabetic() || c == '_',
+            NamedClass::Alpha => c.is_alphabetic(),
+            NamedClass::Alnum => c.is_alphanumeric(),
+            NamedClass::Lower => c.is_lowercase(),
+            NamedClass::Upper => c.is_uppercase(),
+            NamedClass::Hex => c.is_ascii_hexdigit(),
+            NamedClass::Octal => ('0'..='7').contains(&c),
+            NamedClass::Punct => c.is_ascii_punctuation(),
+        }
+    }
+}
+
+impl CharClass {
+    fn named(class: NamedClass, negated: bool) -> Self {
+        Self { negated, newline: false, items: vec![ClassItem::Named(class)] }
+    }
+
+    fn contains(&self, c: char, ignore_case: bool) -> bool {
+        let contains = |c: char| {
+            self.items.iter().any(|item| match *item {
+                ClassItem::Range(first, last) => (first..=last).contains(&c),
+                ClassItem::Named(class) => class.contains(c),
+            })
+        };
+        let found = contains(c) || (ignore_case && (contains(fold_case(c)) || c.to_uppercase().any(contains)));
+        found != self.negated
+    }
+}
+
+/// How many characters have a special meaning without a backslash, changed by `\v`, `\m`, `\M`
+/// and `\V`, like in Vim
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Magic {
+    /// `\v`
+    Very,
+    /// `\m`, the default
+    Normal,
+    /// `\M`
+    No,
+    /// `\V`
+    VeryNo,
+}
+
+impl Magic {
+    /// Returns true if the operator character is special, depending on the backslash before it
+    fn is_special(self, c: char, escaped: bool) -> bool {
+        match self {
+            Magic::Very => !escaped,
+            Magic::Normal if "^$.*[~".contains(c) => !escaped,
+            Magic::No if "^$".contains(c) => !escaped,
+            Magic::VeryNo => escaped,
+            _ => escaped,
+        }
+    }
+}
+
+/// Characters which may be operators, depending on the magic level
+const OPERATOR_CHARS: &str = "^$.*[~+?={}()|<>@%";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Literal(char),
+    Any { newline: bool },
+    Class(CharClass),
+    Newline,
+    /// `^`, which is an anchor at the start of a branch only
+    Caret,
+    /// `$`, which is an anchor at the end of a branch only
+    Dollar,
+    Star,
+    Plus,
+    Question,
+    Brace { min: usize, max: Option<usize>, greedy: bool },
+    Open { capture: bool },
+    Close,
+    Alt,
+    WordStart,
+    WordEnd,
+    MatchStart,
+    MatchEnd,
+}
+
+/// The syntax tree of a regex
+#[derive(Clone, Debug)]
+enum Node {
+    Empty,
+    Char(char),
+    Any { newline: bool },
+    Class(CharClass),
+    Newline,
+    Assert(Assertion),
+    /// `\zs`
+    MatchStart,
+    /// `\ze`
+    MatchEnd,
+    Group { node: Box<Node>, index: Option<usize> },
+    Concat(Vec<Node>),
+    Alternation(Vec<Node>),
+    Repeat { node: Box<Node>, min: usize, max: Option<usize>, greedy: bool },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Assertion {
+    LineStart,
+    LineEnd,
+    WordStart,
+    WordEnd,
+}
+
+/// An instruction of a compiled regex
+#[derive(Clone, Debug)]
+enum Inst {
+    Char(char),
+    Any { newline: bool },
+    Class(CharClass),
+    Newline,
+    Assert(Assertion),
+    /// Saves the current position in a slot: the start and end of the match, then of each group
+    Save(usize),
+    /// Tries the first branch, then the second one if the first fails
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+/// Largest count accepted in `\{n,m}`
+const MAX_REPEAT: usize = 1000;
+
+/// Number of groups `\(...\)`, like in Vim
+const MAX_GROUPS: usize = 9;
+
+/// Largest number of instructions of a compiled pattern: the counted repeats are unrolled, so
+/// nested ones could otherwise grow the program without bounds
+const MAX_PROGRAM_SIZE: usize = 100_000;
+
+/// A regex with the syntax of Vim, compiled once and used for every search.
+///
+/// The supported syntax is:
+/// - `.`, `[abc]`, `[^a-z]`, `[[:alpha:]]`, and the classes `\s`, `\d`, `\w`, `\a`, `\l`, `\u`,
+///   `\x`, `\h`, `\o` (uppercase for the opposite)
+/// - `*`, `\+`, `\?` or `\=`, `\{n,m}` and its lazy form `\{-n,m}`
+/// - `\(...\)` groups, `\%(...\)` groups without capture, and `\|` alternatives
+/// - `^`, `$`, `\<` and `\>` anchors
+/// - `\n` for a line break, `\_.` for any character or a line break, `\_s` or `\_[...]` to add
+///   the line break to a class
+/// - `\zs` and `\ze` to set where the match starts and ends
+/// - `\c` to ignore the case, `\C` to match it
+/// - `\v` (very magic) where `(`, `)`, `|`, `+`, `?`, `{`, `<`, `>` are special without
+///   backslash, `\m` to go back to the default, `\M` and `\V` where fewer characters are special
+pub struct Regex {
+    program: Vec<Inst>,
+    n_groups: usize,
+    ignore_case: bool,
+}
+
+impl Regex {
+    /// Compiles a pattern. The error is the message shown to the user.
+    pub fn new(pattern: &str) -> Result<Regex, String> {
+        let (tokens, ignore_case) = tokenize(pattern)?;
+        let mut parser = Parser { tokens: &tokens, at: 0, n_groups: 0 };
+        let node = parser.parse_alternation()?;
+        if parser.at < tokens.len() {
+            return Err("E16: Unmatched \\)".to_string());
+        }
+        let mut program = vec![Inst::Save(0)];
+        compile(&node, &mut program)?;
+        program.push(Inst::Match);
+        Ok(Regex { program, n_groups: parser.n_groups, ignore_case })
+    }
+
+    /// Returns the first match starting at or after the byte `from` of the line `y`. The match may
+    /// continue on the next lines.
+    pub fn find_at<L: Lines + ?Sized>(&self, lines: &L, y: usize, from: usize) -> Option<Match> {
+        let line = lines.line(y);
+        let mut at = from;
+        while at <= line.len() {
+            if let Some(found) = self.match_at(lines, Pos { line: y, offset: at }) {
+                return Some(found);
+            }
+            at += line[at..].chars().next().map_or(1, char::len_utf8);
+        }
+        None
+    }
+
+    /// Returns the matches found in the line `y`, from left to right. The matches do not overlap,
+    /// and an empty match is not found right after another match.
+    pub fn find_in_line<L: Lines + ?Sized>(&self, lines: &L, y: usize) -> Vec<Match> {
+        let line = lines.line(y);
+        let mut matches: Vec<Match> = Vec::new();
+        let mut at = 0;
+        while at <= line.len() {
+            let found = match self.find_at(lines, y, at) {
+                Some(found) => found,
+                None => break,
+            };
+            let next_char = |offset: usize| offset + line[offset..].chars().next().map_or(1, char::len_utf8);
+            let previous_end = matches.last().map(|previous| previous.end);
+            let end = found.end;
+            if end.line > y {
+                matches.push(found);
+                break;
+            }
+            if found.start == end && previous_end == Some(end) {
+                at = next_char(end.offset);
+                continue;
+            }
+            at = if end.offset > at { end.offset } else { next_char(at) };
+            matches.push(found);
+        }
+        matches
+    }
+
+    /// Runs the program from a position of the text, and returns the match if it succeeds.
+    ///
+    /// This is a backtracking search: the threads that are not followed yet are kept in a stack,
+    /// with the slots to restore when going back to them. A position of the program is never
+    /// explored twice, which keeps the search linear in the size of the text.
+    fn match_at<L: Lines + ?Sized>(&self, lines: &L, start: Pos) -> Option<Match> {
+        enum Job {
+            Thread { pc: usize, pos: Pos },
+            Restore { slot: usize, value: Option<Pos> },
+        }
+
+        let mut slots: Vec<Option<Pos>> = vec![None; 2 * (self.n_groups + 1)];
+        let mut visited: HashSet<(usize, Pos)> = HashSet::new();
+        let mut jobs = vec![Job::Thread { pc: 0, pos: start }];
+        let mut match_end = None;
+        while let Some(job) = jobs.pop() {
+            let (mut pc, mut pos) = match job {
+                Job::Thread { pc, pos } => (pc, pos),
+                Job::Restore { slot, value } => {
+                    slots[slot] = value;
+                    continue;
+                }
+            };
+            loop {
+                if !visited.insert((pc, pos)) {
+                    break;
+                }
+                match &self.program[pc] {
+                    Inst::Char(c) => match next_char(lines, pos) {
+                        Some((ch, next)) if ch == *c || (self.ignore_case && fold_case(ch) == fold_case(*c)) => {
+                            pos = next;
+                            pc += 1;
+                        }
+                        _ => break,
+                    },
+                    Inst::Any { newline } => match next_char(lines, pos) {
+                        Some((_, next)) => {
+                            pos = next;
+                            pc += 1;
+                        }
+                        None if *newline && pos.line + 1 < lines.len_lines() => {
+                            pos = Pos { line: pos.line + 1, offset: 0 };
+                            pc += 1;
+                        }
+                        None => break,
+                    },
+                    Inst::Class(class) => match next_char(lines, pos) {
+                        Some((ch, next)) if class.contains(ch, self.ignore_case) => {
+                            pos = next;
+                            pc += 1;
+                        }
+                        None if class.newline && pos.line + 1 < lines.len_lines() => {
+                            pos = Pos { line: pos.line + 1, offset: 0 };
+                            pc += 1;
+                        }
+                        _ => break,
+                    },
+                    Inst::Newline => {
+                        if pos.offset < lines.line(pos.line).len() || pos.line + 1 >= lines.len_lines() {
+                            break;
+                        }
+                        pos = Pos { line: pos.line + 1, offset: 0 };
+                        pc += 1;
+                    }
+                    Inst::Assert(assertion) => {
+                        if !check(*assertion, lines.line(pos.line), pos.offset) {
+                            break;
+                        }
+                        pc += 1;
+                    }
+                    Inst::Save(slot) => {
+                        jobs.push(Job::Restore { slot: *slot, value: slots[*slot] });
+                        slots[*slot] = Some(pos);
+                        pc += 1;
+                    }
+                    Inst::Split(first, second) => {
+                        jobs.push(Job::Thread { pc: *second, pos });
+                        pc = *first;
+                    }
+                    Inst::Jump(target) => pc = *target,
+                    Inst::Match => {
+                        match_end = Some(pos);
+                        break;
+                    }
+                }
+            }
+            if match_end.is_some() {
+                break;
+            }
+        }
+
+        let end = match_end?;
+        let start = slots[0].unwrap_or(start);
+        // `\ze` ends the match before the end of the text matched
+        let end = slots[1].unwrap_or(end).max(start);
+        let groups = (1..=self.n_groups)
+            .map(|i| Some((slots[2 * i]?, slots[2 * i + 1]?)))
+            .collect();
+        Some(Match { start, end, groups })
+    }
+}
+
+/// Returns the character at a position and the position after it, None at the end of a line
+fn next_char<L: Lines + ?Sized>(lines: &L, pos: Pos) -> Option<(char, Pos)> {
+    let c = lines.line(pos.line)[pos.offset..].chars().next()?;
+    Some((c, Pos { line: pos.line, offset: pos.offset + c.len_utf8() }))
+}
+
+fn fold_case(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+fn check(assertion: Assertion, line: &str, at: usize) -> bool {
+    let keyword_before = line[..at].chars().next_back().is_some_and(is_keyword_char);
+    let keyword_after = line[at..].chars().next().is_some_and(is_keyword_char);
+    match assertion {
+        Assertion::LineStart => at == 0,
+        Assertion::LineEnd => at == line.len(),
+        Assertion::WordStart => keyword_after && !keyword_before,
+        Assertion::WordEnd => keyword_before && !keyword_after,
+    }
+}
+
+/// Splits a pattern into tokens, following the magic level. Also returns whether the case is
+/// ignored, set by `\c`.
+fn tokenize(pattern: &str) -> Result<(Vec<Token>, bool), String> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut tokens = Vec::new();
+    let mut magic = Magic::Normal;
+    let mut ignore_case = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let escaped = chars[i] == '\\' && i + 1 < chars.len();
+        if escaped {
+            i += 1;
+        }
+        let c = chars[i];
+        i += 1;
+
+        if escaped && (c.is_ascii_alphanumeric() || c == '_') {
+            let token = match c {
+                'v' => {
+                    magic = Magic::Very;
+                    continue;
+                }
+                'm' => {
+                    magic = Magic::Normal;
+                    continue;
+                }
+                'M' => {
+                    magic = Magic::No;
+                    continue;
+                }
+                'V' => {
+                    magic = Magic::VeryNo;
+                    continue;
+                }
+                'c' => {
+                    ignore_case = true;
+                    continue;
+                }
+                'C' => continue,
+                'n' => Token::Newline,
+                't' => Token::Literal('\t'),
+                'e' => Token::Literal('\x1b'),
+                'r' => Token::Literal('\r'),
+                'z' => {
+                    i += 1;
+                    match chars.get(i - 1) {
+                        Some('s') => Token::MatchStart,
+                        Some('e') => Token::MatchEnd,
+                        _ => return Err("E68: Invalid character after \\z".to_string()),
+                    }
+                }
+                '_' => {
+                    i += 1;
+                    match chars.get(i - 1) {
+                        Some('.') => Token::Any { newline: true },
+                        Some('^') => Token::Caret,
+                        Some('$') => Token::Dollar,
+                        Some('[') => match parse_bracket(&chars, &mut i) {
+                            Some(mut class) => {
+                                class.newline = true;
+                                Token::Class(class)
+                            }
+                            None => return Err("E769: Missing ] after \\_[".to_string()),
+                        },
+                        Some(&c) => match NamedClass::from_escape(c) {
+                            Some((class, negated)) => {
+                                let mut class = CharClass::named(class, negated);
+                                class.newline = true;
+                                Token::Class(class)
+                            }
+                            None => return Err("E63: Invalid use of \\_".to_string()),
+                        },
+                        None => return Err("E63: Invalid use of \\_".to_string()),
+                    }
+                }
+                '1'..='9' => return Err("E65: Illegal back reference".to_string()),
+                c => match NamedClass::from_escape(c) {
+                    Some((class, negated)) => Token::Class(CharClass::named(class, negated)),
+                    None => Token::Literal(c),
+                },
+            };
+            tokens.push(token);
+            continue;
+        }
+
+        if !OPERATOR_CHARS.contains(c) || !magic.is_special(c, escaped) {
+            tokens.push(Token::Literal(c));
+            continue;
+        }
+        let token = match c {
+            '^' => Token::Caret,
+            '$' => Token::Dollar,
+            '.' => Token::Any { newline: false },
+            '*' => Token::Star,
+            '+' => Token::Plus,
+            '?' | '=' => Token::Question,
+            '(' => Token::Open { capture: true },
+            ')' => Token::Close,
+            '|' => Token::Alt,
+            '<' => Token::WordStart,
+            '>' => Token::WordEnd,
+            '[' => match parse_bracket(&chars, &mut i) {
+                Some(class) => Token::Class(class),
+                // Without the closing bracket, '[' is a character
+                None => Token::Literal('['),
+            },
+            '{' => parse_brace(&chars, &mut i)?,
+            // `\%(` in magic mode, `%(` in very magic mode
+            '%' if chars.get(i) == Some(&'(') => {
+                i += 1;
+                Token::Open { capture: false }
+            }
+            c => Token::Literal(c),
+        };
+        tokens.push(token);
+    }
+    Ok((tokens, ignore_case))
+}
+
+/// Parses the count of `\{n,m}`, after the opening brace. The closing brace may be preceded by
+/// a backslash.
+fn parse_brace(chars: &[char], i: &mut usize) -> Result<Token, String> {
+    let error = || "E554: Syntax error in \\{...}".to_string();
+    let close = chars[*i..].iter().position(|c| *c == '}').ok_or_else(error)? + *i;
+    let mut content: String = chars[*i..close].iter().collect();
+    *i = close + 1;
+    if content.ends_with('\\') {
+        content.pop();
+    }
+    let greedy = !content.starts_with('-');
+    let content = content.trim_start_matches('-');
+    let number = |text: &str| -> Result<Option<usize>, String> {
+        if text.is_empty() {
+            Ok(None)
+        } else {
+            text.parse().map(Some).map_err(|_| error())
+        }
+    };
+    let (min, max) = match content.split_once(',') {
+        Some((min, max)) => (number(min)?.unwrap_or(0), number(max)?),
+        None => {
+            let n = number(content)?;
+            (n.unwrap_or(0), n)
+        }
+    };
+    if min > MAX_REPEAT || max.is_some_and(|max| max > MAX_REPEAT) {
+        return Err("E60: Too many complex \\{...}(s)".to_string());
+    }
+    // Like in Vim, "\{3,1}" is the same as "\{1,3}"
+    let (min, max) = match max {
+        Some(max) if max < min => (max, Some(min)),
+        max => (min, max),
+    };
+    Ok(Token::Brace { min, max, greedy })
+}
+
+/// Parses a bracket expression such as `[a-z_]`, after the opening bracket. Returns None if the
+/// closing bracket is missing.
+fn parse_bracket(chars: &[char], i: &mut usize) -> Option<CharClass> {
+    let mut at = *i;
+    let negated = chars.get(at) == Some(&'^');
+    if negated {
+        at += 1;
+    }
+    let mut class = CharClass { negated, newline: false, items: Vec::new() };
+    let mut first = true;
+    loop {
+        let mut c = *chars.get(at)?;
+        at += 1;
+        if c == ']' && !first {
+            break;
+        }
+        first = false;
+        if c == '[' && chars.get(at) == Some(&':') {
+            let rest: String = chars[at + 1..].iter().collect();
+            if let Some(end) = rest.find(":]") {
+                if let Some(named) = NamedClass::from_name(&rest[..end]) {
+                    class.items.push(ClassItem::Named(named));
+                    at += 1 + rest[..end].chars().count() + 2;
+                    continue;
+                }
+            }
+        }
+        if c == '\\' {
+            match chars.get(at) {
+                Some('n') => {
+                    class.newline = true;
+                    at += 1;
+                    continue;
+                }
+                Some(&escaped @ ('\\' | ']' | '^' | '-' | 't' | 'e' | 'r')) => {
+                    c = match escaped {
+                        't' => '\t',
+                        'e' => '\x1b',
+                        'r' => '\r',
+                        escaped => escaped,
+                    };
+                    at += 1;
+                }
+                _ => {}
+            }
+        }
+        if chars.get(at) == Some(&'-') && chars.get(at + 1).is_some_and(|c| *c != ']') {
+            let last = chars[at + 1];
+            at += 2;
+            class.items.push(ClassItem::Range(c, last));
+        } else {
+            class.items.push(ClassItem::Range(c, c));
+        }
+    }
+    *i = at;
+    Some(class)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    at: usize,
+    n_groups: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.at)
+    }
+
+    /// Parses branches separated by `\|`
+    fn parse_alternation(&mut self) -> Result<Node, String> {
+        let mut branches = vec![self.parse_concat()?];
+        while self.peek() == Some(&Token::Alt) {
+            self.at += 1;
+            branches.push(self.parse_concat()?);
+        }
+        Ok(if branches.len() == 1 { branches.pop().unwrap_or(Node::Empty) } else { Node::Alternation(branches) })
+    }
+
+    /// Parses a branch: atoms, each followed by its multis
+    fn parse_concat(&mut self) -> Result<Node, String> {
+        let mut nodes = Vec::new();
+        while let Some(token) = self.peek() {
+            let token = token.clone();
+            self.at += 1;
+            let node = match token {
+                Token::Alt | Token::Close => {
+                    self.at -= 1;
+                    break;
+                }
+                Token::Literal(c) => Node::Char(c),
+                Token::Any { newline } => Node::Any { newline },
+                Token::Class(class) => Node::Class(class),
+                Token::Newline => Node::Newline,
+                Token::Caret if nodes.is_empty() => Node::Assert(Assertion::LineStart),
+                Token::Caret => Node::Char('^'),
+                Token::Dollar if matches!(self.peek(), None | Some(Token::Alt) | Some(Token::Close)) => {
+                    Node::Assert(Assertion::LineEnd)
+                }
+                Token::Dollar => Node::Char('$'),
+                Token::WordStart => Node::Assert(Assertion::WordStart),
+                Token::WordEnd => Node::Assert(Assertion::WordEnd),
+                Token::MatchStart => Node::MatchStart,
+                Token::MatchEnd => Node::MatchEnd,
+                // A star with nothing before it is a character, like in Vim
+                Token::Star => Node::Char('*'),
+                Token::Plus | Token::Question | Token::Brace { .. } => {
+                    return Err("E64: Nothing to repeat".to_string());
+                }
+                Token::Open { capture } => {
+                    let index = if capture {
+                        self.n_groups += 1;
+                        if self.n_groups > MAX_GROUPS {
+                            return Err("E51: Too many \\(".to_string());
+                        }
+                        Some(self.n_groups)
+                    } else {
+                        None
+                    };
+                    let node = self.parse_alternation()?;
+                    if self.peek() != Some(&Token::Close) {
+                        return Err("E54: Unmatched \\(".to_string());
+                    }
+                    self.at += 1;
+                    Node::Group { node: Box::new(node), index }
+                }
+            };
+            nodes.push(self.parse_multis(node)?);
+        }
+        Ok(match nodes.len() {
+            0 => Node::Empty,
+            1 => nodes.pop().unwrap_or(Node::Empty),
+            _ => Node::Concat(nodes),
+        })
+    }
+
+    /// Applies the multi that follows an atom, such as `*` or `\{2,3}`. Like in Vim, a multi
+    /// cannot follow another one.
+    fn parse_multis(&mut self, node: Node) -> Result<Node, String> {
+        let (min, max, greedy) = match self.peek() {
+            Some(Token::Star) => (0, None, true),
+            Some(Token::Plus) => (1, None, true),
+            Some(Token::Question) => (0, Some(1), true),
+            Some(Token::Brace { min, max, greedy }) => (*min, *max, *greedy),
+            _ => return Ok(node),
+        };
+        self.at += 1;
+        match self.peek() {
+            Some(Token::Star) => Err("E61: Nested *".to_string()),
+            Some(Token::Plus) => Err("E62: Nested \\+".to_string()),
+            Some(Token::Question) => Err("E62: Nested \\=".to_string()),
+            Some(Token::Brace { .. }) => Err("E62: Nested \\{".to_string()),
+            _ => Ok(Node::Repeat { node: Box::new(node), min, max, greedy }),
+        }
+    }
+}
+
+/// Appends the instructions of a node to the program
+fn compile(node: &Node, program: &mut Vec<Inst>) -> Result<(), String> {
+    compile_node(node, program)?;
+    if program.len() > MAX_PROGRAM_SIZE {
+        return Err("E339: Pattern too long".to_string());
+    }
+    Ok(())
+}
+
+fn compile_node(node: &Node, program: &mut Vec<Inst>) -> Result<(), String> {
+    match node {
+        Node::Empty => {}
+        Node::Char(c) => program.push(Inst::Char(*c)),
+        Node::Any { newline } => program.push(Inst::Any { newline: *newline }),
+        Node::Class(class) => program.push(Inst::Class(class.clone())),
+        Node::Newline => program.push(Inst::Newline),
+        Node::Assert(assertion) => program.push(Inst::Assert(*assertion)),
+        Node::MatchStart => program.push(Inst::Save(0)),
+        Node::MatchEnd => program.push(Inst::Save(1)),
+        Node::Group { node, index: Some(index) } => {
+            program.push(Inst::Save(2 * index));
+            compile(node, program)?;
+            program.push(Inst::Save(2 * index + 1));
+        }
+        Node::Group { node, index: None } => compile(node, program)?,
+        Node::Concat(nodes) => {
+            for node in nodes {
+                compile(node, program)?;
+            }
+        }
+        Node::Alternation(branches) => {
+            let mut jumps = Vec::new();
+            for (i, branch) in branches.iter().enumerate() {
+                if i + 1 < branches.len() {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    compile(branch, program)?;
+                    jumps.push(program.len());
+                    program.push(Inst::Jump(0));
+                    let next = program.len();
+                    program[split] = Inst::Split(split + 1, next);
+                } else {
+                    compile(branch, program)?;
+                }
+            }
+            let end = program.len();
+            for jump in jumps {
+                program[jump] = Inst::Jump(end);
+            }
+        }
+        Node::Repeat { node, min, max, greedy } => {
+            for _ in 0..*min {
+                compile(node, program)?;
+            }
+            let split = |body: usize, exit: usize| if *greedy { Inst::Split(body, exit) } else { Inst::Split(exit, body) };
+            match max {
+                None => {
+                    let start = program.len();
+                    program.push(Inst::Split(0, 0));
+                    compile(node, program)?;
+                    program.push(Inst::Jump(start));
+                    program[start] = split(start + 1, program.len());
+                }
+                Some(max) => {
+                    // Each optional copy is skipped to the end
+                    let mut splits = Vec::new();
+                    for _ in *min..*max {
+                        splits.push(program.len());
+                        program.push(Inst::Split(0, 0));
+                        compile(node, program)?;
+                    }
+                    let end = program.len();
+                    for at in splits {
+                        program[at] = split(at + 1, end);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the text of the matches found in the lines, skipping the matches that start inside
+    /// a previous match spanning several lines
+    fn find_all(pattern: &str, lines: &[&str]) -> Vec<String> {
+        let regex = Regex::new(pattern).unwrap();
+        let mut end = Pos { line: 0, offset: 0 };
+        let mut matches = Vec::new();
+        for y in 0..lines.len() {
+            for found in regex.find_in_line(lines, y) {
+                if found.start >= end {
+                    matches.push(text_between(lines, found.start, found.end));
+                    end = found.end;
+                }
+            }
+        }
+        matches
+    }
+
+    #[test]
+    fn test_basic_syntax() {
+        assert_eq!(find_all("a.c", &["abc adc a-", "c"]), ["abc", "adc"]);
+        assert_eq!(find_all("ab*", &["a ab abbb"]), ["a", "ab", "abbb"]);
+        assert_eq!(find_all("ab\\+", &["a ab abbb"]), ["ab", "abbb"]);
+        assert_eq!(find_all("ab\\=c", &["ac abc abbc"]), ["ac", "abc"]);
+        assert_eq!(find_all("a\\{2,3}", &["a aa aaaa"]), ["aa", "aaa"]);
+        assert_eq!(find_all("a\\{-1,}", &["aaa"]), ["a", "a", "a"]);
+        assert_eq!(find_all("[a-c]\\+", &["abcd cab"]), ["abc", "cab"]);
+        assert_eq!(find_all("[^ ]\\+", &["one  two"]), ["one", "two"]);
+        assert_eq!(find_all("[[:digit:]]\\+", &["a12b3"]), ["12", "3"]);
+        assert_eq!(find_all("\\d\\+\\s\\w", &["12 a 3 b"]), ["12 a", "3 b"]);
+        assert_eq!(find_all("one\\|two", &["one two three"]), ["one", "two"]);
+        assert_eq!(find_all("\\(ab\\)\\+", &["ababa"]), ["abab"]);
+        assert_eq!(find_all("^a", &["aaa", "ba"]), ["a"]);
+        assert_eq!(find_all("a$", &["aaa", "ab"]), ["a"]);
+        assert_eq!(find_all("a.b$c", &["a.b$c"]), ["a.b$c"]);
+        assert_eq!(find_all("a\\.b", &["axb a.b"]), ["a.b"]);
+        assert_eq!(find_all("[ab", &["[ab"]), ["[ab"]);
+        assert_eq!(find_all("*a", &["**a"]), ["*a"]);
+        assert_eq!(find_all("été", &["l'été"]), ["été"]);
+    }
+
+    #[test]
+    fn test_vim_syntax() {
+        // Words
+        assert_eq!(find_all("\\<the\\>", &["the other theme, the"]), ["the", "the"]);
+        // Very magic
+        assert_eq!(find_all("\\v(ab)+|c{2}", &["ababcc c"]), ["abab", "cc"]);
+        assert_eq!(find_all("\\v<\\w+>", &["one, two"]), ["one", "two"]);
+        // Very nomagic
+        assert_eq!(find_all("\\Va.*", &["abc a.*"]), ["a.*"]);
+        // Case
+        assert_eq!(find_all("\\cHello", &["hello HELLO Hello"]), ["hello", "HELLO", "Hello"]);
+        assert_eq!(find_all("[a-z]\\+\\c", &["ABC def"]), ["ABC", "def"]);
+        // Start and end of the match
+        assert_eq!(find_all("foo\\zsbar", &["foobar bar"]), ["bar"]);
+        assert_eq!(find_all("foo\\zebar", &["foobar foo"]), ["foo"]);
+        assert_eq!(find_all("\\%(a\\|b\\)c", &["ac bc cc"]), ["ac", "bc"]);
+    }
+
+    #[test]
+    fn test_multi_line_matches() {
+        let lines = ["one", "two", "", "three"];
+        assert_eq!(find_all("one\\ntwo", &lines), ["one\ntwo"]);
+        assert_eq!(find_all("e\\_s*t", &["one", "", "  two"]), ["e\n\n  t"]);
+        assert_eq!(find_all("o\\_.*h", &lines), ["one\ntwo\n\nth"]);
+        assert_eq!(find_all("^\\n", &lines), ["\n"]);
+        // The last line has no line break
+        assert_eq!(find_all("e\\n", &lines), ["e\n"]);
+
+        let regex = Regex::new("\\(t\\)\\(w\\)\\?\\(x\\)\\?").unwrap();
+        let found = regex.find_at(&lines[..], 1, 0).unwrap();
+        assert_eq!(found.start, Pos { line: 1, offset: 0 });
+        assert_eq!(found.group(1), Some((Pos { line: 1, offset: 0 }, Pos { line: 1, offset: 1 })));
+        assert_eq!(found.group(2), Some((Pos { line: 1, offset: 1 }, Pos { line: 1, offset: 2 })));
+        assert_eq!(found.group(3), None);
+    }
+
+    #[test]
+    fn test_empty_matches() {
+        assert_eq!(find_all("x*", &["xxa"]), ["xx", ""]);
+        assert_eq!(find_all("\\<", &["a bc"]), ["", ""]);
+        // A pattern that can loop on nothing ends
+        assert_eq!(find_all("\\(a*\\)*b", &["aab"]), ["aab"]);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(Regex::new("\\(a").is_err());
+        assert!(Regex::new("a\\)").is_err());
+        assert!(Regex::new("\\+").is_err());
+        assert!(Regex::new("a\\{x}").is_err());
+        assert!(Regex::new("\\zx").is_err());
+        assert_eq!(Regex::new("a**").err(), Some("E61: Nested *".to_string()));
+        assert_eq!(Regex::new("a\\{1000}\\{1000}").err(), Some("E62: Nested \\{".to_string()));
+        assert_eq!(
+            Regex::new("\\(\\(a\\{1000}\\)\\{1000}\\)\\{1000}").err(),
+            Some("E339: Pattern too long".to_string())
+        );
+        assert!(Regex::new("\\(a\\{10}\\)\\{10}").is_ok());
+    }
+}
@@ -1,5 +1,46 @@
 use std::ops::Range;
 
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::graphemes::display_width;
+
+/// How a part of the text is highlighted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Highlight {
+    /// The visual selection
+    Selection,
+    /// A match of the last search
+    Match,
+    /// The match the search being typed goes to
+    CurrentMatch,
+}
+
+/// Splits a byte range of a line, such as a subline, into segments with the highlight covering
+/// each of them. When highlights overlap, the last one in the list is shown.
+pub fn highlight_segments(range: Range<usize>, highlights: &[(Range<usize>, Highlight)]) -> Vec<(Range<usize>, Option<Highlight>)> {
+    let mut bounds = vec![range.start, range.end];
+    for (highlight, _) in highlights {
+        bounds.push(highlight.start.clamp(range.start, range.end));
+        bounds.push(highlight.end.clamp(range.start, range.end));
+    }
+    bounds.sort_unstable();
+    bounds.dedup();
+
+    let mut segments: Vec<(Range<usize>, Option<Highlight>)> = Vec::new();
+    for bound in bounds.windows(2) {
+        let kind = highlights
+            .iter()
+            .rev()
+            .find(|(highlight, _)| highlight.start <= bound[0] && bound[1] <= highlight.end)
+            .map(|(_, kind)| *kind);
+        match segments.last_mut() {
+            Some((last, last_kind)) if *last_kind == kind => last.end = bound[1],
+            _ => segments.push((bound[0]..bound[1], kind)),
+        }
+    }
+    segments
+}
+
 /// Helper class to hold information about the usable screen
 #[derive(Debug)]
 pub struct Screen {
@@ -15,20 +56,27 @@ impl Screen {
         self.h + self.top
     }
 
-    /// Returns the indices where to split the provided line so that it fits on &self
-    pub fn split_line(&self, line: &String) -> Vec<Range<usize>> {
-        let n = line.len();
+    /// Returns the byte ranges where to split the provided line so that it fits on &self
+    ///
+    /// The width of the screen is counted in terminal cells: wide characters take two cells, and
+    /// a grapheme is never split between two sublines. An empty line has a single empty range.
+    pub fn split_line(&self, line: &str) -> Vec<Range<usize>> {
+        let w = self.w.max(1) as usize;
         let mut idx = Vec::new();
 
-        let mut i: usize = 0;
-        while i < n {
-            let j = i + self.w as usize;
-            if j > n {
-                idx.push(i..n);
-            } else {
-                idx.push(i..j);
+        let mut start: usize = 0;
+        let mut width: usize = 0;
+        for (i, grapheme) in line.grapheme_indices(true) {
+            let grapheme_width = display_width(grapheme);
+            if width + grapheme_width > w && i > start {
+                idx.push(start..i);
+                start = i;
+                width = 0;
             }
-            i += self.w as usize;
+            width += grapheme_width;
+        }
+        if start < line.len() || idx.is_empty() {
+            idx.push(start..line.len());
         }
         idx
     }
@@ -62,4 +110,45 @@ mod tests {
         let ranges = screen.split_line(&line);
         assert_eq!(ranges.len(), 1);
     }
+
+    #[test]
+    fn screen_split_lines_wide_chars() {
+        let screen = Screen {top:0, h:100, w: 5};
+        // Each ideograph takes two cells, so only two of them fit in a subline
+        let line = "日本語のテキスト".to_string();
+        let ranges = screen.split_line(&line);
+        assert_eq!(ranges.len(), 4);
+        assert_eq!("日本", &line[ranges[0].clone()]);
+        assert_eq!("語の", &line[ranges[1].clone()]);
+
+        let line = "ab日本".to_string();
+        let ranges = screen.split_line(&line);
+        assert_eq!(ranges.len(), 2);
+        assert_eq!("ab日", &line[ranges[0].clone()]);
+        assert_eq!("本", &line[ranges[1].clone()]);
+    }
+
+    #[test]
+    fn test_highlight_segments() {
+        let highlights = [(2..6, Highlight::Match), (4..8, Highlight::Selection)];
+        assert_eq!(
+            highlight_segments(0..10, &highlights),
+            vec![
+                (0..2, None),
+                (2..4, Some(Highlight::Match)),
+                (4..8, Some(Highlight::Selection)),
+                (8..10, None),
+            ]
+        );
+        // A subline only shows its part of the highlights
+        assert_eq!(highlight_segments(5..7, &highlights), vec![(5..7, Some(Highlight::Selection))]);
+        assert_eq!(highlight_segments(0..4, &[]), vec![(0..4, None)]);
+        assert_eq!(highlight_segments(3..3, &highlights), vec![]);
+    }
+
+    #[test]
+    fn screen_split_empty_line() {
+        let screen = Screen {top:0, h:100, w: 10};
+        assert_eq!(screen.split_line(""), vec![0..0]);
+    }
 }
@@ -0,0 +1,213 @@
+use crate::cursor::Cursor;
+use crate::graphemes::{byte_to_grapheme, grapheme_to_byte};
+use crate::regex::{is_keyword_char, Lines, Match, Pos, Regex};
+use crate::text_buffer::TextBuffer;
+use std::cell::OnceCell;
+use std::ops::Range;
+
+/// A pattern searched with `/`, `?`, `*` and `#`, compiled once for all the searches.
+/// See [`Regex`] for its syntax.
+pub struct Pattern {
+    source: String,
+    regex: Regex,
+}
+
+impl Pattern {
+    /// Compiles the pattern. The error is the message shown to the user.
+    pub fn new(source: &str) -> Result<Self, String> {
+        Ok(Self { source: source.to_string(), regex: Regex::new(source)? })
+    }
+
+    /// Returns the pattern as it was typed
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Returns the matches starting in the line `y` of the buffer. They may continue on the next
+    /// lines.
+    pub fn find_in_line(&self, lines: &BufferLines, y: usize) -> Vec<Match> {
+        self.regex.find_in_line(lines, y)
+    }
+}
+
+/// Number of children of each node of the cache of `BufferLines`
+const CACHE_FANOUT: usize = 16;
+
+/// A node of the cache of `BufferLines`, covering a range of lines. Its children are only
+/// created when a line under them is read, so the cache only grows with the lines a search reads,
+/// and not with the size of the buffer.
+enum LineCache {
+    Lines(Box<[OnceCell<String>; CACHE_FANOUT]>),
+    Nodes([OnceCell<Box<LineCache>>; CACHE_FANOUT]),
+}
+
+impl LineCache {
+    /// Returns an empty node covering `span` lines
+    fn new(span: usize) -> Self {
+        if span <= CACHE_FANOUT {
+            LineCache::Lines(Box::new(std::array::from_fn(|_| OnceCell::new())))
+        } else {
+            LineCache::Nodes(std::array::from_fn(|_| OnceCell::new()))
+        }
+    }
+}
+
+/// The lines of the buffer, read from the buffer the first time they are needed by a search
+pub struct BufferLines<'a> {
+    buffer: &'a TextBuffer,
+    len_lines: usize,
+    /// Number of lines covered by the root of the cache, a power of `CACHE_FANOUT`
+    span: usize,
+    cache: LineCache,
+}
+
+impl<'a> BufferLines<'a> {
+    pub fn new(buffer: &'a TextBuffer) -> Self {
+        let len_lines = buffer.len_lines();
+        let mut span = CACHE_FANOUT;
+        while span < len_lines {
+            span *= CACHE_FANOUT;
+        }
+        Self { buffer, len_lines, span, cache: LineCache::new(span) }
+    }
+}
+
+impl Lines for BufferLines<'_> {
+    fn len_lines(&self) -> usize {
+        self.len_lines
+    }
+
+    fn line(&self, y: usize) -> &str {
+        let mut node = &self.cache;
+        let mut span = self.span;
+        loop {
+            // Number of lines covered by each child of the node
+            span /= CACHE_FANOUT;
+            let child = y / span % CACHE_FANOUT;
+            match node {
+                LineCache::Lines(lines) => return lines[child].get_or_init(|| self.buffer.line(y)),
+                LineCache::Nodes(nodes) => node = nodes[child].get_or_init(|| Box::new(LineCache::new(span))),
+            }
+        }
+    }
+}
+
+/// Returns the byte range of the word under the grapheme `x` of the line, or of the first word
+/// after it, for `*` and `#`
+pub fn word_at(line: &str, x: usize) -> Option<Range<usize>> {
+    let at = grapheme_to_byte(line, x);
+    let start = at + line[at..].find(is_keyword_char)?;
+    let start = line[..start]
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_keyword_char(*c))
+        .last()
+        .map_or(start, |(i, _)| i);
+    let end = line[start..]
+        .find(|c| !is_keyword_char(c))
+        .map_or(line.len(), |n| start + n);
+    Some(start..end)
+}
+
+/// Finds the first match after the cursor, or the last one before it if `backward` is set.
+///
+/// The search wraps around the end of the buffer: the second element of the result is true
+/// when it did.
+pub fn find_next(buffer: &TextBuffer, pattern: &Pattern, from: &Cursor, backward: bool) -> Option<(Cursor, bool)> {
+    let lines = BufferLines::new(buffer);
+    let n_lines = lines.len_lines();
+    let at = Pos { line: from.y, offset: grapheme_to_byte(lines.line(from.y), from.x) };
+    let matches = pattern.find_in_line(&lines, from.y);
+    let found = if backward {
+        matches.iter().rev().find(|found| found.start < at)
+    } else {
+        matches.iter().find(|found| found.start > at)
+    };
+    if let Some(found) = found {
+        return Some((to_cursor(&lines, found.start), false));
+    }
+
+    // The other lines, then the line of the cursor again after wrapping around
+    for i in 1..=n_lines {
+        let (y, wrapped) = if backward {
+            ((from.y + n_lines - i) % n_lines, i > from.y)
+        } else {
+            ((from.y + i) % n_lines, from.y + i >= n_lines)
+        };
+        let matches = pattern.find_in_line(&lines, y);
+        let found = if backward { matches.last() } else { matches.first() };
+        if let Some(found) = found {
+            return Some((to_cursor(&lines, found.start), wrapped));
+        }
+    }
+    None
+}
+
+/// Returns the first line after the line `from` with a match, or the last one before it if
+/// `backward` is set, for the addresses `/pattern/` and `?pattern?`. The search wraps around the
+/// end of the buffer.
+pub fn find_line(buffer: &TextBuffer, pattern: &Pattern, from: usize, backward: bool) -> Option<usize> {
+    let lines = BufferLines::new(buffer);
+    let n_lines = lines.len_lines();
+    (1..=n_lines)
+        .map(|i| if backward { (from + n_lines - i) % n_lines } else { (from + i) % n_lines })
+        .find(|y| !pattern.find_in_line(&lines, *y).is_empty())
+}
+
+/// Converts a position found by a search into a cursor
+fn to_cursor(lines: &BufferLines, pos: Pos) -> Cursor {
+    Cursor { x: byte_to_grapheme(lines.line(pos.line), pos.offset), y: pos.line }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_next() {
+        let mut buffer = TextBuffer::new();
+        buffer.insert(0, "one two\nthree\ntwo one");
+        let pattern = Pattern::new("\\<t\\w*").unwrap();
+        let from = Cursor { x: 0, y: 0 };
+        assert_eq!(find_next(&buffer, &pattern, &from, false), Some((Cursor { x: 4, y: 0 }, false)));
+        assert_eq!(find_next(&buffer, &pattern, &from, true), Some((Cursor { x: 0, y: 2 }, true)));
+        let from = Cursor { x: 0, y: 2 };
+        assert_eq!(find_next(&buffer, &pattern, &from, false), Some((Cursor { x: 4, y: 0 }, true)));
+
+        // A match spanning two lines
+        let pattern = Pattern::new("e\\ntwo").unwrap();
+        assert_eq!(find_next(&buffer, &pattern, &from, false), Some((Cursor { x: 4, y: 1 }, true)));
+        assert!(Pattern::new("\\(").is_err());
+    }
+
+    #[test]
+    fn test_find_line() {
+        let mut buffer = TextBuffer::new();
+        buffer.insert(0, "foo\nbar\nfoo bar\nbaz");
+        let pattern = Pattern::new("foo").unwrap();
+        assert_eq!(find_line(&buffer, &pattern, 0, false), Some(2));
+        assert_eq!(find_line(&buffer, &pattern, 2, false), Some(0));
+        assert_eq!(find_line(&buffer, &pattern, 2, true), Some(0));
+        assert_eq!(find_line(&buffer, &pattern, 0, true), Some(2));
+        assert_eq!(find_line(&buffer, &Pattern::new("qux").unwrap(), 0, false), None);
+    }
+
+    #[test]
+    fn test_buffer_lines() {
+        let text: Vec<String> = (0..1000).map(|i| format!("line {i}")).collect();
+        let buffer = TextBuffer::from(text.join("\n").as_str());
+        let lines = BufferLines::new(&buffer);
+        assert_eq!(lines.len_lines(), 1000);
+        for y in [0, 15, 16, 255, 256, 999, 16] {
+            assert_eq!(lines.line(y), text[y]);
+        }
+    }
+
+    #[test]
+    fn test_word_at() {
+        assert_eq!(word_at("foo bar_baz", 5), Some(4..11));
+        assert_eq!(word_at("foo bar", 3), Some(4..7));
+        assert_eq!(word_at("foo bar", 0), Some(0..3));
+        assert_eq!(word_at("foo ...", 3), None);
+    }
+}
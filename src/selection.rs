@@ -1,20 +1,41 @@
 use crate::cursor::Cursor;
+use std::ops::Range;
+
+/// What is selected between the start and the end of a selection
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SelectionKind {
+    /// All the characters from the start to the end, made with 'v'
+    Charwise,
+    /// All the lines from the start to the end, made with 'V'
+    Linewise,
+    /// The rectangle whose corners are the start and the end, made with Ctrl-V
+    Blockwise,
+}
 
 /// A selection is a tuple from an end to a start
 #[derive(Clone, Copy, Debug)]
 pub struct Selection {
     start: Cursor,
-    end: Cursor
+    end: Cursor,
+    kind: SelectionKind,
 }
 
 impl Selection {
-    pub fn new(start: Cursor, end: Cursor) -> Self {
-        Self {start, end}
+    pub fn new(start: Cursor, end: Cursor, kind: SelectionKind) -> Self {
+        Self {start, end, kind}
+    }
+
+    pub fn kind(&self) -> SelectionKind {
+        self.kind
+    }
+
+    pub fn set_kind(&mut self, kind: SelectionKind) {
+        self.kind = kind;
     }
 
     /// Returns true if the selection contains the given line
     pub fn contains_line(&self, line_number: usize) -> bool {
-        self.start.y <= line_number && self.end.y >= line_number
+        self.start().y <= line_number && self.end().y >= line_number
     }
 
     /// Sets the new end for this selection
@@ -39,6 +60,42 @@ impl Selection {
         }
     }
 
+    /// Returns the end of the selection which moves with the cursor
+    pub fn cursor(&self) -> Cursor {
+        self.end
+    }
+
+    /// Swaps the fixed end of the selection with the one which moves with the cursor
+    pub fn swap_ends(&mut self) {
+        std::mem::swap(&mut self.start, &mut self.end);
+    }
+
+    /// Returns the first and the last columns of a block selection (both included)
+    pub fn block_columns(&self) -> (usize, usize) {
+        (self.start.x.min(self.end.x), self.start.x.max(self.end.x))
+    }
+
+    /// Returns the range of graphemes selected in the given line, whose length is `line_len`.
+    /// The range is empty if nothing is selected in the line.
+    pub fn line_range(&self, line_number: usize, line_len: usize) -> Range<usize> {
+        if !self.contains_line(line_number) {
+            return 0..0;
+        }
+        let range = match self.kind {
+            SelectionKind::Charwise => {
+                let start = if self.start().y == line_number { self.start().x } else { 0 };
+                let end = if self.end().y == line_number { self.end().x + 1 } else { line_len };
+                start..end
+            }
+            SelectionKind::Linewise => 0..line_len,
+            SelectionKind::Blockwise => {
+                let (left, right) = self.block_columns();
+                left..right + 1
+            }
+        };
+        range.start.min(line_len)..range.end.min(line_len)
+    }
+
 }
 
 #[cfg(test)]
@@ -49,7 +106,8 @@ mod tests {
 
         let s = Selection {
             start: Cursor { x: 0, y: 0 },
-            end: Cursor { x: 0, y: 0 }
+            end: Cursor { x: 0, y: 0 },
+            kind: SelectionKind::Charwise,
         };
         //s.set_new_end(Cursor { x: 1, y: 0 });
         //assert_eq!(s.end.x, 1);
@@ -63,7 +121,8 @@ mod tests {
     fn test_backward_selection() {
         let mut s = Selection {
             start: Cursor { x: 10, y: 0 },
-            end: Cursor { x: 11, y: 0 }
+            end: Cursor { x: 11, y: 0 },
+            kind: SelectionKind::Charwise,
         };
 
         s.set_new_end(Cursor { x: 9, y: 0 });
@@ -74,5 +133,23 @@ mod tests {
         assert_eq!(s.end().x, 10);
     }
 
-}
+    #[test]
+    fn test_line_range() {
+        let mut s = Selection::new(Cursor { x: 4, y: 1 }, Cursor { x: 2, y: 3 }, SelectionKind::Charwise);
+        assert_eq!(s.line_range(0, 10), 0..0);
+        assert_eq!(s.line_range(1, 10), 4..10);
+        assert_eq!(s.line_range(2, 10), 0..10);
+        assert_eq!(s.line_range(3, 10), 0..3);
+
+        s.set_kind(SelectionKind::Linewise);
+        assert_eq!(s.line_range(1, 10), 0..10);
+        assert_eq!(s.line_range(3, 10), 0..10);
 
+        // A block covers the same columns on every line, but short lines
+        s.set_kind(SelectionKind::Blockwise);
+        assert_eq!(s.block_columns(), (2, 4));
+        assert_eq!(s.line_range(1, 10), 2..5);
+        assert_eq!(s.line_range(2, 3), 2..3);
+        assert_eq!(s.line_range(3, 1), 1..1);
+    }
+}
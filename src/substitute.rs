@@ -0,0 +1,197 @@
+/// The flags after the replacement of `:s`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SubstituteFlags {
+    /// `g`: replace all the matches of each line, instead of the first one
+    pub global: bool,
+    /// `c`: ask before each replacement
+    pub confirm: bool,
+    /// `i`: ignore the case of the pattern. `I` does not.
+    pub ignore_case: bool,
+    /// `n`: only count the matches, without replacing them
+    pub count_only: bool,
+}
+
+/// The arguments of `:s/pattern/replacement/flags`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Substitute {
+    /// The pattern, which is the last search pattern if it is empty
+    pub pattern: String,
+    /// The replacement, as typed. See [`expand_replacement`].
+    pub replacement: String,
+    pub flags: SubstituteFlags,
+}
+
+/// The answer to the question asked before each replacement of `:s///c`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfirmAnswer {
+    /// `y`: replace this match
+    Yes,
+    /// `n`: skip this match
+    No,
+    /// `a`: replace this match and all the next ones
+    All,
+    /// `l`: replace this match, and stop there
+    Last,
+    /// `q` or Escape: stop without replacing this match
+    Quit,
+}
+
+impl Substitute {
+    /// Parses the arguments of `:s`, for instance `/foo/bar/g`.
+    ///
+    /// The first character is the delimiter, which may be escaped with a backslash in the pattern
+    /// and in the replacement. The trailing delimiter may be omitted, like in `:s/foo/bar`.
+    pub fn parse(args: &str) -> Result<Self, String> {
+        let mut chars = args.chars();
+        let delimiter = match chars.next() {
+            None => return Err("E35: No previous regular expression".to_string()),
+            Some(c) if c.is_alphanumeric() || matches!(c, '\\' | '"' | '|' | ' ') => {
+                return Err("E146: Regular expressions can't be delimited by letters".to_string())
+            }
+            Some(c) => c,
+        };
+        let (pattern, rest) = split_at_delimiter(chars.as_str(), delimiter);
+        let (replacement, rest) = split_at_delimiter(rest.unwrap_or_default(), delimiter);
+
+        let mut flags = SubstituteFlags::default();
+        for flag in rest.unwrap_or_default().trim_end().chars() {
+            match flag {
+                'g' => flags.global = true,
+                'c' => flags.confirm = true,
+                'i' => flags.ignore_case = true,
+                'I' => flags.ignore_case = false,
+                'n' => flags.count_only = true,
+                _ => return Err(format!("E488: Trailing characters: {flag}")),
+            }
+        }
+        Ok(Self { pattern, replacement, flags })
+    }
+}
+
+/// Returns the text before the first unescaped delimiter, in which the escaped delimiters are
+/// unescaped, and the text after the delimiter if there is one
+fn split_at_delimiter(text: &str, delimiter: char) -> (String, Option<&str>) {
+    let mut part = String::new();
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == delimiter {
+            return (part, Some(&text[i + c.len_utf8()..]));
+        }
+        if c == '\\' {
+            match chars.next() {
+                Some((_, next)) if next == delimiter => part.push(next),
+                Some((_, next)) => {
+                    part.push('\\');
+                    part.push(next);
+                }
+                None => part.push('\\'),
+            }
+        } else {
+            part.push(c);
+        }
+    }
+    (part, None)
+}
+
+/// A change of case requested in a replacement
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Case {
+    Upper,
+    Lower,
+}
+
+/// Builds the text replacing a match of `:s`, from the replacement typed by the user.
+/// `group` returns the text of a group of the match, the group 0 being the whole match.
+///
+/// The replacement may contain:
+/// - `&` or `\0` for the whole match, and `\1` to `\9` for the groups,
+/// - `\r` or `\n` for a line break, and `\t` for a tab,
+/// - `\u` and `\l` to make the next character uppercase or lowercase,
+/// - `\U` and `\L` to make the next characters uppercase or lowercase, until `\E` or `\e`.
+///
+/// Any other escaped character is inserted as is, like `\&` or `\\`.
+pub fn expand_replacement(replacement: &str, group: impl Fn(usize) -> String) -> String {
+    let mut text = String::new();
+    let mut next_case: Option<Case> = None;
+    let mut case: Option<Case> = None;
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        let inserted = match c {
+            '&' => group(0),
+            '\\' => match chars.next() {
+                Some(digit @ '0'..='9') => group(digit as usize - '0' as usize),
+                Some('n' | 'r') => "\n".to_string(),
+                Some('t') => "\t".to_string(),
+                Some('u') => {
+                    next_case = Some(Case::Upper);
+                    continue;
+                }
+                Some('l') => {
+                    next_case = Some(Case::Lower);
+                    continue;
+                }
+                Some('U') => {
+                    case = Some(Case::Upper);
+                    continue;
+                }
+                Some('L') => {
+                    case = Some(Case::Lower);
+                    continue;
+                }
+                Some('e' | 'E') => {
+                    case = None;
+                    continue;
+                }
+                Some(c) => c.to_string(),
+                None => "\\".to_string(),
+            },
+            c => c.to_string(),
+        };
+        for c in inserted.chars() {
+            match next_case.take().or(case) {
+                Some(Case::Upper) => text.extend(c.to_uppercase()),
+                Some(Case::Lower) => text.extend(c.to_lowercase()),
+                None => text.push(c),
+            }
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_substitute() {
+        let substitute = Substitute::parse("/a\\/b/c/gi").unwrap();
+        assert_eq!(substitute.pattern, "a/b");
+        assert_eq!(substitute.replacement, "c");
+        assert_eq!(
+            substitute.flags,
+            SubstituteFlags { global: true, ignore_case: true, ..Default::default() }
+        );
+
+        // Other delimiters, and the trailing parts omitted
+        let substitute = Substitute::parse("#a\\.b#c\\#").unwrap();
+        assert_eq!(substitute.pattern, "a\\.b");
+        assert_eq!(substitute.replacement, "c#");
+        assert_eq!(substitute.flags, SubstituteFlags::default());
+        assert_eq!(Substitute::parse("/a").unwrap().replacement, "");
+
+        assert!(Substitute::parse("").is_err());
+        assert!(Substitute::parse("xaxbx").is_err());
+        assert_eq!(Substitute::parse("/a/b/z"), Err("E488: Trailing characters: z".to_string()));
+    }
+
+    #[test]
+    fn test_expand_replacement() {
+        let groups = ["foo bar", "foo", "bar"];
+        let expand = |replacement| expand_replacement(replacement, |i| groups.get(i).unwrap_or(&"").to_string());
+        assert_eq!(expand("\\2 \\1"), "bar foo");
+        assert_eq!(expand("[&] \\& \\\\"), "[foo bar] & \\");
+        assert_eq!(expand("\\u\\1\\r\\U\\2!\\Ex"), "Foo\nBAR!x");
+        assert_eq!(expand("\\L\\uFOO \\0"), "Foo foo bar");
+        assert_eq!(expand("\\5."), ".");
+    }
+}
@@ -2,6 +2,10 @@
 mod tests {
     use crate::editor_model::*;
     use crate::cursor::Cursor;
+    use crate::key::Key;
+    use crate::yanker::RegisterKind;
+    use crate::clipboard::Clipboard;
+    use crate::screen::Highlight;
 
     fn setup_empty_model() -> EditorModel {
         let text = "
@@ -37,46 +41,1150 @@ And a last sentence
     fn test_undo_redo() {
         let mut model = setup_empty_model();
         model.force_insert_mode();
-        model.key_tapped('a' as u32);
-        model.key_tapped('b' as u32);
-        model.key_tapped('c' as u32);
-        assert_eq!(model.get_lines()[0], "abc");
+        model.key_tapped('a'.into());
+        model.key_tapped('b'.into());
+        model.key_tapped('c'.into());
+        assert_eq!(model.get_buffer().line(0), "abc");
         model.force_normal_mode();
-        model.key_tapped('u' as u32);
-        assert_eq!(model.get_lines()[0], "ab");
-        model.key_tapped('u' as u32);
-        assert_eq!(model.get_lines()[0], "a");
 
+        // Everything typed during the insert session is undone at once
+        model.key_tapped('u'.into());
+        assert_eq!(model.get_buffer().line(0), "");
+        model.key_tapped(Key::Ctrl('r'));
+        assert_eq!(model.get_buffer().line(0), "abc");
+
+        // Each insert session is its own undo step
+        model.key_tapped('i'.into());
+        model.key_tapped('d'.into());
+        model.key_tapped(Key::Enter);
+        model.key_tapped('e'.into());
+        model.key_tapped(Key::Escape);
+        model.key_tapped('i'.into());
+        model.key_tapped('f'.into());
+        model.key_tapped(Key::Escape);
+        assert_eq!(model.get_buffer().line(1), "ef");
+        model.key_tapped('u'.into());
+        assert_eq!(model.get_buffer().line(1), "e");
+        model.key_tapped('u'.into());
+        assert_eq!(model.get_buffer().len_lines(), 1);
+        assert_eq!(model.get_buffer().line(0), "abc");
+        assert_cursor_at(model.get_cursor(), 3, 0);
+
+    }
+
+    #[test]
+    fn test_undo_redo_every_action() {
+        let mut model = setup_model();
+        model.force_normal_mode();
+
+        // x, then undo and redo
+        model.key_tapped('x'.into());
+        assert_eq!(model.get_buffer().line(0), "ello world");
+        model.key_tapped('u'.into());
+        assert_eq!(model.get_buffer().line(0), "Hello world");
+        assert_cursor_at(model.get_cursor(), 0, 0);
+        model.key_tapped(Key::Ctrl('r'));
+        assert_eq!(model.get_buffer().line(0), "ello world");
+        model.key_tapped('u'.into());
+
+        // r
+        model.key_tapped('r'.into());
+        model.key_tapped('J'.into());
+        assert_eq!(model.get_buffer().line(0), "Jello world");
+        model.key_tapped('u'.into());
+        assert_eq!(model.get_buffer().line(0), "Hello world");
+
+        // o adds a line
+        model.key_tapped('o'.into());
+        model.key_tapped(Key::Escape);
+        assert_eq!(model.get_buffer().len_lines(), 5);
+        model.key_tapped('u'.into());
+        assert_eq!(model.get_buffer().len_lines(), 4);
+        model.key_tapped(Key::Ctrl('r'));
+        assert_eq!(model.get_buffer().len_lines(), 5);
+        assert_eq!(model.get_buffer().line(1), "");
+        model.key_tapped('u'.into());
+
+        // Joining lines with backspace
+        model.key_tapped(Key::Down);
+        model.force_insert_mode();
+        model.key_tapped(Key::Backspace);
+        assert_eq!(model.get_buffer().line(0), "Hello worldanother sentence");
+        model.force_normal_mode();
+        model.key_tapped('u'.into());
+        assert_eq!(model.get_buffer().line(0), "Hello world");
+        assert_eq!(model.get_buffer().line(1), "another sentence");
+        assert_cursor_at(model.get_cursor(), 0, 1);
+
+        // Put
+        model.key_tapped('v'.into());
+        model.key_tapped('l'.into());
+        model.key_tapped('y'.into());
+        model.key_tapped('p'.into());
+        assert_eq!(model.get_buffer().line(1), "aannother sentence");
+        model.key_tapped('u'.into());
+        assert_eq!(model.get_buffer().line(1), "another sentence");
+
+        // Typing after an undo clears the redo history
+        model.key_tapped('x'.into());
+        model.key_tapped('u'.into());
+        model.key_tapped('x'.into());
+        model.key_tapped('u'.into());
+        model.key_tapped('u'.into());
+        model.key_tapped(Key::Ctrl('r'));
+        model.key_tapped(Key::Ctrl('r'));
+        assert_eq!(model.get_buffer().line(1), "nother sentence");
+    }
+
+    fn type_text(model: &mut EditorModel, text: &str) {
+        for ch in text.chars() {
+            model.key_tapped(ch.into());
+        }
+    }
+
+    #[test]
+    fn test_undo_tree() {
+        let mut model = setup_simple_model();
+        model.force_normal_mode();
+        model.key_tapped('x'.into());
+        model.key_tapped('x'.into());
+        assert_eq!(model.get_buffer().line(0), "llo world");
+
+        // Undo, then make another change: the previous state is kept in another branch
+        model.key_tapped('u'.into());
+        model.key_tapped('r'.into());
+        model.key_tapped('J'.into());
+        assert_eq!(model.get_buffer().line(0), "Jllo world");
+
+        model.key_tapped('g'.into());
+        model.key_tapped('-'.into());
+        assert_eq!(model.get_buffer().line(0), "llo world");
+        model.key_tapped('g'.into());
+        model.key_tapped('-'.into());
+        assert_eq!(model.get_buffer().line(0), "ello world");
+        model.key_tapped('g'.into());
+        model.key_tapped('+'.into());
+        model.key_tapped('g'.into());
+        model.key_tapped('+'.into());
+        assert_eq!(model.get_buffer().line(0), "Jllo world");
+
+        // The same travel with commands
+        type_text(&mut model, ":earlier 3");
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_buffer().line(0), "Hello world");
+        type_text(&mut model, ":later 2");
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_buffer().line(0), "llo world");
+        type_text(&mut model, ":later 1h");
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_buffer().line(0), "Jllo world");
+    }
+
+    #[test]
+    fn test_operators() {
+        let mut model = setup_model();
+        model.force_normal_mode();
+
+        // Delete a word, then the character searched with 'f'
+        type_text(&mut model, "dw");
+        assert_eq!(model.get_buffer().line(0), "world");
+        type_text(&mut model, "dfr");
+        assert_eq!(model.get_buffer().line(0), "ld");
+        assert_eq!(model.get_yanker_content(), Some("wor".to_string()));
+        model.key_tapped('u'.into());
+        assert_eq!(model.get_buffer().line(0), "world");
+
+        // Repeat the last search with ';'
+        type_text(&mut model, "jjjfed;");
+        assert_eq!(model.get_buffer().line(3), "And a last snce");
+        model.key_tapped('u'.into());
+
+        // Change a word, which is a single undo step
+        model.reset_cursor();
+        model.key_tapped(Key::Down);
+        type_text(&mut model, "cwone");
+        model.key_tapped(Key::Escape);
+        assert_eq!(model.get_buffer().line(1), "one sentence");
+        model.key_tapped('u'.into());
+        assert_eq!(model.get_buffer().line(1), "another sentence");
+
+        // Yank words backward
+        for _ in 0..20 {
+            model.key_tapped(Key::Right);
+        }
+        type_text(&mut model, "y2b");
+        assert_eq!(model.get_yanker_content(), Some("another sentence".to_string()));
+        assert_cursor_at(model.get_cursor(), 0, 1);
+    }
+
+    #[test]
+    fn test_linewise_operators() {
+        let mut model = setup_model();
+        model.force_normal_mode();
+
+        type_text(&mut model, "yy");
+        assert_eq!(model.get_yanker_content(), Some("Hello world\n".to_string()));
+
+        type_text(&mut model, "2dd");
+        assert_eq!(model.get_buffer().len_lines(), 2);
+        assert_eq!(model.get_buffer().line(1), "And a last sentence");
+        assert_eq!(model.get_yanker_content(), Some("Hello world\nanother sentence\n".to_string()));
+
+        // Deleting the last line removes the line break before it
+        type_text(&mut model, "jdk");
+        assert_eq!(model.get_buffer().to_string(), "");
+
+        model.key_tapped('u'.into());
+        model.key_tapped('u'.into());
+        assert_eq!(model.get_buffer().len_lines(), 4);
+        type_text(&mut model, "ccnew");
+        model.key_tapped(Key::Escape);
+        assert_eq!(model.get_buffer().line(0), "new");
+        assert_eq!(model.get_buffer().line(1), "another sentence");
+    }
+
+    #[test]
+    fn test_text_objects() {
+        let mut model = EditorModel::new();
+        model.set_text("call(a, \"some text\", b)\nlast line".to_string());
+        model.force_normal_mode();
+
+        type_text(&mut model, "fmci\"new");
+        model.key_tapped(Key::Escape);
+        assert_eq!(model.get_buffer().line(0), "call(a, \"new\", b)");
+
+        type_text(&mut model, "Fndaw");
+        assert_eq!(model.get_buffer().line(0), "call(a, \"\", b)");
+        model.key_tapped('u'.into());
+
+        // Visual selection of the parentheses, then yank
+        type_text(&mut model, "va(y");
+        assert_eq!(model.get_yanker_content(), Some("(a, \"new\", b)".to_string()));
+        assert!(model.get_selection().is_none());
+
+        type_text(&mut model, "di(");
+        assert_eq!(model.get_buffer().line(0), "call()");
+        type_text(&mut model, "dip");
+        assert_eq!(model.get_buffer().len_lines(), 1);
+        assert_eq!(model.get_buffer().line(0), "");
+    }
+
+    #[test]
+    fn test_motions() {
+        let mut model = EditorModel::new();
+        model.set_text("  fn main(a: u8) {\n    let x = (a + 1);\n}\nfoo.bar baz".to_string());
+        model.force_normal_mode();
+
+        type_text(&mut model, "$");
+        assert_cursor_at(model.get_cursor(), 17, 0);
+        type_text(&mut model, "^");
+        assert_cursor_at(model.get_cursor(), 2, 0);
+        type_text(&mut model, "0");
+        assert_cursor_at(model.get_cursor(), 0, 0);
+        type_text(&mut model, "G");
+        assert_cursor_at(model.get_cursor(), 0, 3);
+        type_text(&mut model, "W");
+        assert_cursor_at(model.get_cursor(), 8, 3);
+        type_text(&mut model, "B");
+        assert_cursor_at(model.get_cursor(), 0, 3);
+        type_text(&mut model, "e");
+        assert_cursor_at(model.get_cursor(), 2, 3);
+        type_text(&mut model, "E");
+        assert_cursor_at(model.get_cursor(), 6, 3);
+        type_text(&mut model, "ge");
+        assert_cursor_at(model.get_cursor(), 3, 3);
+        type_text(&mut model, "2G");
+        assert_cursor_at(model.get_cursor(), 4, 1);
+        type_text(&mut model, "t+");
+        assert_cursor_at(model.get_cursor(), 14, 1);
+        type_text(&mut model, "T(");
+        assert_cursor_at(model.get_cursor(), 13, 1);
+        type_text(&mut model, "%");
+        assert_cursor_at(model.get_cursor(), 12, 1);
+        type_text(&mut model, "gg");
+        assert_cursor_at(model.get_cursor(), 2, 0);
+        type_text(&mut model, "%");
+        assert_cursor_at(model.get_cursor(), 15, 0);
+        type_text(&mut model, "L");
+        assert_cursor_at(model.get_cursor(), 0, 3);
+        type_text(&mut model, "H");
+        assert_cursor_at(model.get_cursor(), 2, 0);
+
+        // The motions compose with operators
+        type_text(&mut model, "fmd$");
+        assert_eq!(model.get_buffer().line(0), "  fn ");
+        type_text(&mut model, "u0d^");
+        assert_eq!(model.get_buffer().line(0), "fn main(a: u8) {");
+        type_text(&mut model, "uf{d%");
+        assert_eq!(model.get_buffer().len_lines(), 2);
+        assert_eq!(model.get_buffer().line(0), "  fn main(a: u8) ");
+        type_text(&mut model, "udG");
+        assert_eq!(model.get_buffer().to_string(), "");
+        type_text(&mut model, "u2Gdgg");
+        assert_eq!(model.get_buffer().line(0), "}");
+        type_text(&mut model, "uGce");
+        model.key_tapped(Key::Escape);
+        assert_eq!(model.get_buffer().line(3), ".bar baz");
+        type_text(&mut model, "udW");
+        assert_eq!(model.get_buffer().line(3), "baz");
+        type_text(&mut model, "u$dT.");
+        assert_eq!(model.get_buffer().line(3), "foo.z");
+    }
+
+    #[test]
+    fn test_count_prefix() {
+        let mut model = EditorModel::new();
+        model.set_text("one two three four\na-b-c-d\nline 3\nline 4\nline 5".to_string());
+        model.force_normal_mode();
+
+        type_text(&mut model, "3w");
+        assert_cursor_at(model.get_cursor(), 14, 0);
+        type_text(&mut model, "5x");
+        assert_eq!(model.get_buffer().line(0), "one two three ");
+        assert_eq!(model.get_yanker_content(), Some("four".to_string()));
+        type_text(&mut model, "u0");
+
+        type_text(&mut model, "j3f-");
+        assert_cursor_at(model.get_cursor(), 5, 1);
+        type_text(&mut model, "02;");
+        assert_cursor_at(model.get_cursor(), 3, 1);
+        type_text(&mut model, "0d2t-");
+        assert_eq!(model.get_buffer().line(1), "-c-d");
+        type_text(&mut model, "3rx");
+        assert_eq!(model.get_buffer().line(1), "xxxd");
+        assert_cursor_at(model.get_cursor(), 2, 1);
+
+        // Both changes are undone
+        type_text(&mut model, "2u");
+        assert_eq!(model.get_buffer().line(1), "a-b-c-d");
+
+        type_text(&mut model, "10j");
+        assert_cursor_at(model.get_cursor(), 0, 4);
+        type_text(&mut model, "2k2dd");
+        assert_eq!(model.get_buffer().len_lines(), 3);
+        assert_eq!(model.get_buffer().line(2), "line 5");
+        type_text(&mut model, "gg3yl4P");
+        assert_eq!(model.get_buffer().line(0), "oneoneoneoneone two three four");
+
+        // A counted motion stops at the end of the buffer
+        let mut end_model = EditorModel::new();
+        end_model.set_text("a b\n\n".to_string());
+        end_model.force_normal_mode();
+        type_text(&mut end_model, "9w");
+        assert_eq!(end_model.get_buffer().len_lines(), 2);
+        assert_cursor_at(end_model.get_cursor(), 0, 1);
+        type_text(&mut end_model, "9wk");
+        assert_cursor_at(end_model.get_cursor(), 0, 0);
+
+        // Huge counts are capped
+        type_text(&mut model, "0w99999999999999999999x");
+        assert_eq!(model.get_buffer().line(0), "oneoneoneoneone ");
+        type_text(&mut model, "2\"a99999999999999999999dd");
+        assert_eq!(model.get_buffer().to_string(), "");
+    }
+
+    #[test]
+    fn test_dot_repeat() {
+        let mut model = EditorModel::new();
+        model.set_text("one two three four five\nsix seven".to_string());
+        model.force_normal_mode();
+
+        type_text(&mut model, "dw.");
+        assert_eq!(model.get_buffer().line(0), "three four five");
+
+        // Moves and undos are not changes
+        type_text(&mut model, "wu");
+        assert_eq!(model.get_buffer().line(0), "two three four five");
+        type_text(&mut model, ".");
+        assert_eq!(model.get_buffer().line(0), "three four five");
+
+        // A new count replaces the count of the change
+        type_text(&mut model, "2x3.");
+        assert_eq!(model.get_buffer().line(0), " four five");
+        type_text(&mut model, ".");
+        assert_eq!(model.get_buffer().line(0), "ur five");
+
+        // The text typed in an insert session is repeated
+        type_text(&mut model, "cwthe");
+        model.key_tapped(Key::Escape);
+        type_text(&mut model, "W.");
+        assert_eq!(model.get_buffer().line(0), "the the");
+
+        // The repeated change is a single undo step
+        model.key_tapped('u'.into());
+        assert_eq!(model.get_buffer().line(0), "the five");
+
+        // Commands of the command line are not repeated
+        type_text(&mut model, ":earlier 1");
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_buffer().line(0), "ur five");
+        type_text(&mut model, "j0.");
+        assert_eq!(model.get_buffer().line(1), "the seven");
+    }
+
+    #[test]
+    fn test_macros() {
+        let mut model = EditorModel::new();
+        model.set_text("one\ntwo q\nthree\nfour\nfive".to_string());
+        model.force_normal_mode();
+
+        // Record a macro that prefixes a line and goes to the next one
+        type_text(&mut model, "qa0i- ");
+        model.key_tapped(Key::Escape);
+        assert!(model.get_status_message().contains("recording @a"));
+        type_text(&mut model, "jq");
+        assert!(!model.get_status_message().contains("recording"));
+        assert_eq!(model.get_buffer().line(0), "- one");
+
+        type_text(&mut model, "@a");
+        assert_eq!(model.get_buffer().line(1), "- two q");
+        type_text(&mut model, "@@");
+        assert_eq!(model.get_buffer().line(2), "- three");
+
+        // The playback stops when 'j' fails on the last line
+        type_text(&mut model, "10@a");
+        assert_eq!(model.get_buffer().line(3), "- four");
+        assert_eq!(model.get_buffer().line(4), "- five");
+
+        // All the changes of a playback are undone at once
+        model.key_tapped('u'.into());
+        assert_eq!(model.get_buffer().line(3), "four");
+        assert_eq!(model.get_buffer().line(4), "five");
+
+        // A 'q' which is part of a command does not stop the recording
+        type_text(&mut model, "ggjqb0fqxq");
+        assert_eq!(model.get_buffer().line(1), "- two ");
+        type_text(&mut model, "u@b");
+        assert_eq!(model.get_buffer().line(1), "- two ");
+    }
+
+    #[test]
+    fn test_recursive_macro() {
+        let mut model = EditorModel::new();
+        model.set_text("1a\n2b\n3c\n4d".to_string());
+        model.force_normal_mode();
+
+        type_text(&mut model, "qbxjq");
+        assert_eq!(model.get_buffer().line(0), "a");
+
+        // Append a call to itself to the macro: it then runs until the last line
+        type_text(&mut model, "qB@bq");
+        assert_eq!(model.get_buffer().line(1), "b");
+        type_text(&mut model, "@b");
+        assert_eq!(model.get_buffer().to_string(), "a\nb\nc\nd");
+
+        // A macro which plays itself without failing is stopped too
+        type_text(&mut model, "qc@cq@c");
+        assert_eq!(model.get_buffer().to_string(), "a\nb\nc\nd");
+    }
+
+    #[test]
+    fn test_undo_in_macro() {
+        let mut model = EditorModel::new();
+        model.set_text("abcdef".to_string());
+        model.force_normal_mode();
+
+        // The undo of the macro reverts the change the macro has just made
+        type_text(&mut model, "x$qaxuq");
+        assert_eq!(model.get_buffer().to_string(), "bcdef");
+        type_text(&mut model, "@a");
+        assert_eq!(model.get_buffer().to_string(), "bcdef");
+        type_text(&mut model, "u");
+        assert_eq!(model.get_buffer().to_string(), "abcdef");
+        model.key_tapped(Key::Ctrl('r'));
+        assert_eq!(model.get_buffer().to_string(), "bcdef");
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut model = EditorModel::new();
+        model.set_text("one two\nthree".to_string());
+        model.force_normal_mode();
+
+        // Named registers, an uppercase one appends
+        type_text(&mut model, "\"ayw");
+        type_text(&mut model, "w\"Ayw");
+        assert_eq!(model.get_register('a').unwrap().content, "one two");
+        type_text(&mut model, "0\"aP");
+        assert_eq!(model.get_buffer().line(0), "one twoone two");
+        model.key_tapped('u'.into());
+
+        // Deletes inside a line go to "-, deletes of lines to "1
+        type_text(&mut model, "dw");
+        assert_eq!(model.get_register('-').unwrap().content, "one ");
+        type_text(&mut model, "dd");
+        let register = model.get_register('1').unwrap();
+        assert_eq!(register.content, "two\n");
+        assert_eq!(register.kind, RegisterKind::Linewise);
+        type_text(&mut model, "yw");
+        assert_eq!(model.get_register('0').unwrap().content, "three");
+        assert_eq!(model.get_register('1').unwrap().content, "two\n");
+
+        // A count can be typed before and after the register
+        type_text(&mut model, "2\"b2P");
+        assert_eq!(model.get_buffer().line(0), "three");
+        type_text(&mut model, "2\"a2P");
+        assert_eq!(model.get_buffer().line(0), "one twoone twoone twoone twothree");
+
+        // Read-only registers
+        type_text(&mut model, "ihey");
+        model.key_tapped(Key::Escape);
+        assert_eq!(model.get_register('.').unwrap().content, "hey");
+        type_text(&mut model, ":later 1");
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_register(':').unwrap().content, "later 1");
+        type_text(&mut model, "\".yw");
+        assert_eq!(model.get_register('.').unwrap().content, "hey");
+
+        type_text(&mut model, ":registers");
+        model.key_tapped(Key::Enter);
+        assert!(model.get_message().contains(&"  l  \"1   two^J".to_string()));
+        assert!(model.get_message().contains(&"  c  \"a   one two".to_string()));
+        model.key_tapped('l'.into());
+        assert!(model.get_message().is_empty());
+    }
+
+    #[test]
+    fn test_open_line() {
+        let mut model = setup_simple_model();
+        model.force_normal_mode();
+        type_text(&mut model, "jwonew");
+        model.key_tapped(Key::Escape);
+        assert_eq!(model.get_buffer().line(2), "new");
+        assert_eq!(model.get_buffer().line(1), "another sentence");
+
+        type_text(&mut model, "kwOtop");
+        model.key_tapped(Key::Escape);
+        assert_eq!(model.get_buffer().line(1), "top");
+        assert_eq!(model.get_buffer().line(2), "another sentence");
+    }
+
+    #[test]
+    fn test_persistent_undo() {
+        let dir = std::env::temp_dir().join(format!("red-undo-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("notes.txt");
+        std::fs::write(&file, "Hello world\n").unwrap();
+        let filename = file.to_string_lossy().to_string();
+
+        let mut model = EditorModel::from_file(filename.clone(), true, Clipboard::Osc52);
+        model.key_tapped('x'.into());
+        model.key_tapped('x'.into());
+        type_text(&mut model, ":w");
+        model.key_tapped(Key::Enter);
+        assert!(dir.join(".notes.txt.un~").exists());
+
+        // After a restart, the changes can still be undone
+        let mut model = EditorModel::from_file(filename.clone(), true, Clipboard::Osc52);
+        assert_eq!(model.get_buffer().line(0), "llo world");
+        model.key_tapped('u'.into());
+        assert_eq!(model.get_buffer().line(0), "ello world");
+        model.key_tapped('u'.into());
+        assert_eq!(model.get_buffer().line(0), "Hello world");
+
+        // Without the option, the undo file is ignored
+        let mut model = EditorModel::from_file(filename.clone(), false, Clipboard::Osc52);
+        model.key_tapped('u'.into());
+        assert_eq!(model.get_buffer().line(0), "llo world");
+
+        // The undo file is ignored if the file changed in the meantime
+        std::fs::write(&file, "Another text\n").unwrap();
+        let mut model = EditorModel::from_file(filename, true, Clipboard::Osc52);
+        model.key_tapped('u'.into());
+        assert_eq!(model.get_buffer().line(0), "Another text");
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
     fn test_visual_yanking_pasting() {
         let mut model = setup_model();
         model.force_normal_mode();
-        model.key_tapped('j' as u32);
-        model.key_tapped('v' as u32);
-        model.key_tapped('l' as u32);
-        model.key_tapped('l' as u32);
-        model.key_tapped('l' as u32);
-        model.key_tapped('l' as u32);
-        model.key_tapped('y' as u32);
-        model.key_tapped('j' as u32);
-        model.key_tapped('p' as u32);
-        assert_eq!(model.get_lines()[2], "anoth");
+        model.key_tapped('j'.into());
+        model.key_tapped('v'.into());
+        model.key_tapped('l'.into());
+        model.key_tapped('l'.into());
+        model.key_tapped('l'.into());
+        model.key_tapped('l'.into());
+        model.key_tapped('y'.into());
+        model.key_tapped('j'.into());
+        model.key_tapped('p'.into());
+        assert_eq!(model.get_buffer().line(2), "anoth");
     }
 
 
+    #[test]
+    fn test_linewise_put() {
+        let mut model = EditorModel::new();
+        model.set_text("one\ntwo\nthree".to_string());
+        model.force_normal_mode();
+
+        // Lines are put below or above the line of the cursor
+        type_text(&mut model, "yyp");
+        assert_eq!(model.get_buffer().to_string(), "one\none\ntwo\nthree");
+        assert_cursor_at(model.get_cursor(), 0, 1);
+        type_text(&mut model, "ujyyP");
+        assert_eq!(model.get_buffer().to_string(), "one\ntwo\ntwo\nthree");
+        assert_cursor_at(model.get_cursor(), 0, 1);
+        type_text(&mut model, "uGyy2p");
+        assert_eq!(model.get_buffer().to_string(), "one\ntwo\nthree\nthree\nthree");
+        model.key_tapped('u'.into());
+
+        // Swap two lines
+        type_text(&mut model, "ggddp");
+        assert_eq!(model.get_buffer().to_string(), "two\none\nthree");
+        model.key_tapped('u'.into());
+        model.key_tapped('u'.into());
+
+        // A visual selection over several lines keeps its line breaks
+        type_text(&mut model, "ggvjy");
+        assert_eq!(model.get_yanker_content(), Some("one\nt".to_string()));
+        assert_cursor_at(model.get_cursor(), 0, 0);
+        type_text(&mut model, "G$p");
+        assert_eq!(model.get_buffer().to_string(), "one\ntwo\nthreeone\nt");
+        type_text(&mut model, "uggP");
+        assert_eq!(model.get_buffer().to_string(), "one\ntone\ntwo\nthree");
+    }
+
+    #[test]
+    fn test_visual_line_mode() {
+        let mut model = EditorModel::new();
+        model.set_text("abcd\nefgh\nijkl\nmnop".to_string());
+        model.force_normal_mode();
+
+        type_text(&mut model, "lVjy");
+        let register = model.get_register('"').unwrap();
+        assert_eq!(register.content, "abcd\nefgh\n");
+        assert_eq!(register.kind, RegisterKind::Linewise);
+        assert_cursor_at(model.get_cursor(), 1, 0);
+
+        type_text(&mut model, "jVjd");
+        assert_eq!(model.get_buffer().to_string(), "abcd\nmnop");
+        type_text(&mut model, "P");
+        assert_eq!(model.get_buffer().to_string(), "abcd\nefgh\nijkl\nmnop");
+
+        // 'v' turns a line selection into a character selection
+        type_text(&mut model, "ggVvjd");
+        assert_eq!(model.get_buffer().to_string(), "fgh\nijkl\nmnop");
+        model.key_tapped('u'.into());
+
+        type_text(&mut model, "ggVjcnew");
+        model.key_tapped(Key::Escape);
+        assert_eq!(model.get_buffer().to_string(), "new\nijkl\nmnop");
+    }
+
+    #[test]
+    fn test_visual_block_mode() {
+        let mut model = EditorModel::new();
+        model.set_text("abcd\nefgh\nijkl\nmnop".to_string());
+        model.force_normal_mode();
+
+        type_text(&mut model, "l");
+        model.key_tapped(Key::Ctrl('v'));
+        type_text(&mut model, "ljjy");
+        let register = model.get_register('"').unwrap();
+        assert_eq!(register.content, "bc\nfg\njk");
+        assert_eq!(register.kind, RegisterKind::Blockwise);
+        assert_cursor_at(model.get_cursor(), 1, 0);
+
+        // Delete the block, and put it back
+        model.key_tapped(Key::Ctrl('v'));
+        type_text(&mut model, "ljjd");
+        assert_eq!(model.get_buffer().to_string(), "ad\neh\nil\nmnop");
+        type_text(&mut model, "P");
+        assert_eq!(model.get_buffer().to_string(), "abcd\nefgh\nijkl\nmnop");
+
+        // Put a block after the end of lines, then over the end of the text
+        type_text(&mut model, "u$p");
+        assert_eq!(model.get_buffer().to_string(), "adbc\nehfg\niljk\nmnop");
+        type_text(&mut model, "uGp");
+        assert_eq!(model.get_buffer().to_string(), "ad\neh\nil\nmbcnop\n fg\n jk");
+        model.key_tapped('u'.into());
+        model.key_tapped('u'.into());
+
+        // Insert before the block, as a single undo step
+        type_text(&mut model, "gg0");
+        model.key_tapped(Key::Ctrl('v'));
+        type_text(&mut model, "jjI# ");
+        model.key_tapped(Key::Escape);
+        assert_eq!(model.get_buffer().to_string(), "# abcd\n# efgh\n# ijkl\nmnop");
+        model.key_tapped('u'.into());
+        assert_eq!(model.get_buffer().to_string(), "abcd\nefgh\nijkl\nmnop");
+
+        // Change the block
+        type_text(&mut model, "gg0");
+        model.key_tapped(Key::Ctrl('v'));
+        type_text(&mut model, "jlcX");
+        model.key_tapped(Key::Escape);
+        assert_eq!(model.get_buffer().to_string(), "Xcd\nXgh\nijkl\nmnop");
+
+        // Append after the block, short lines are padded
+        let mut model = EditorModel::new();
+        model.set_text("abcd\nef\nghij".to_string());
+        model.force_normal_mode();
+        type_text(&mut model, "$");
+        model.key_tapped(Key::Ctrl('v'));
+        type_text(&mut model, "jjA;");
+        model.key_tapped(Key::Escape);
+        assert_eq!(model.get_buffer().to_string(), "abcd;\nef  ;\nghij;");
+    }
+
+    #[test]
+    fn test_visual_operators() {
+        let mut model = EditorModel::new();
+        model.set_text("one two\n  three\nfour".to_string());
+        model.force_normal_mode();
+
+        type_text(&mut model, "vex");
+        assert_eq!(model.get_buffer().to_string(), " two\n  three\nfour");
+        model.key_tapped('u'.into());
+
+        // Shift lines, then select them again with gv
+        type_text(&mut model, "Vj>");
+        assert_eq!(model.get_buffer().to_string(), "    one two\n      three\nfour");
+        assert_cursor_at(model.get_cursor(), 4, 0);
+        type_text(&mut model, "gv<");
+        assert_eq!(model.get_buffer().to_string(), "one two\n  three\nfour");
+        type_text(&mut model, "j>>");
+        assert_eq!(model.get_buffer().to_string(), "one two\n      three\nfour");
+        model.key_tapped('u'.into());
+
+        // Case operators
+        type_text(&mut model, "ggwvjU");
+        assert_eq!(model.get_buffer().to_string(), "one TWO\n  THRee\nfour");
+        type_text(&mut model, "ggV~");
+        assert_eq!(model.get_buffer().to_string(), "ONE two\n  THRee\nfour");
+        type_text(&mut model, "Vju");
+        assert_eq!(model.get_buffer().to_string(), "one two\n  three\nfour");
+
+        // Join lines, a single line is joined with the next one
+        type_text(&mut model, "Vjj");
+        type_text(&mut model, "J");
+        assert_eq!(model.get_buffer().to_string(), "one two three four");
+        model.key_tapped('u'.into());
+        type_text(&mut model, "ggvJ");
+        assert_eq!(model.get_buffer().to_string(), "one two three\nfour");
+        model.key_tapped('u'.into());
+
+        // Replace the selected characters
+        type_text(&mut model, "ggvlr-");
+        assert_eq!(model.get_buffer().to_string(), "--e two\n  three\nfour");
+        model.key_tapped('u'.into());
+
+        // 'o' moves the cursor to the other end of the selection
+        type_text(&mut model, "ggwvllohd");
+        assert_eq!(model.get_buffer().to_string(), "one\n  three\nfour");
+    }
+
+    #[test]
+    fn test_search() {
+        let mut model = EditorModel::new();
+        model.set_text("one two\nthree one\ntwo-one".to_string());
+        model.force_normal_mode();
+
+        type_text(&mut model, "/one");
+        assert!(model.get_status_message().contains("/one"));
+        model.key_tapped(Key::Enter);
+        assert_cursor_at(model.get_cursor(), 6, 1);
+        assert_eq!(model.get_register('/').unwrap().content, "one");
+
+        // n and N, with wrap-around
+        type_text(&mut model, "n");
+        assert_cursor_at(model.get_cursor(), 4, 2);
+        type_text(&mut model, "n");
+        assert_cursor_at(model.get_cursor(), 0, 0);
+        assert!(model.get_status_message().contains("search hit BOTTOM"));
+        type_text(&mut model, "N");
+        assert_cursor_at(model.get_cursor(), 4, 2);
+        type_text(&mut model, "2N");
+        assert_cursor_at(model.get_cursor(), 0, 0);
+
+        // ? searches backward, and n follows its direction
+        type_text(&mut model, "?two");
+        model.key_tapped(Key::Enter);
+        assert_cursor_at(model.get_cursor(), 0, 2);
+        type_text(&mut model, "n");
+        assert_cursor_at(model.get_cursor(), 4, 0);
+
+        // * and # search the word under the cursor, as a whole word
+        model.set_text("foo foobar\nbar foo\nfoo".to_string());
+        model.reset_cursor();
+        type_text(&mut model, "*");
+        assert_cursor_at(model.get_cursor(), 4, 1);
+        assert_eq!(model.get_register('/').unwrap().content, "\\<foo\\>");
+        type_text(&mut model, "#");
+        assert_cursor_at(model.get_cursor(), 0, 0);
+
+        // A search operates with an operator, like a motion
+        type_text(&mut model, "/bar");
+        model.key_tapped(Key::Enter);
+        type_text(&mut model, "0dn");
+        assert_eq!(model.get_buffer().line(0), "bar");
+
+        // A failed search does not move the cursor
+        type_text(&mut model, "gg/nothing");
+        model.key_tapped(Key::Enter);
+        assert_cursor_at(model.get_cursor(), 0, 0);
+        assert!(model.get_status_message().contains("Pattern not found: nothing"));
+        model.key_tapped(Key::Escape);
+        assert!(!model.get_status_message().contains("Pattern not found"));
+
+        // Patterns are regexes, which can match line breaks
+        model.set_text("one two\nthree".to_string());
+        model.reset_cursor();
+        type_text(&mut model, "/\\vt(wo|hr)");
+        model.key_tapped(Key::Enter);
+        assert_cursor_at(model.get_cursor(), 4, 0);
+        type_text(&mut model, "n");
+        assert_cursor_at(model.get_cursor(), 0, 1);
+        type_text(&mut model, "/o\\n\\zst");
+        model.key_tapped(Key::Enter);
+        assert_cursor_at(model.get_cursor(), 0, 1);
+        type_text(&mut model, "/\\(");
+        model.key_tapped(Key::Enter);
+        assert!(model.get_status_message().contains("E54: Unmatched \\("));
+    }
+
+    #[test]
+    fn test_search_highlights() {
+        let mut model = EditorModel::new();
+        model.set_text("one two\ntwo\nthree two".to_string());
+        model.force_normal_mode();
+        assert_eq!(model.get_highlights(0..3), vec![vec![], vec![], vec![]]);
+
+        // While the pattern is typed, its matches are shown, and the one the search goes to
+        type_text(&mut model, "/tw");
+        assert_cursor_at(model.get_cursor(), 0, 0);
+        assert_eq!(
+            model.get_highlights(0..2),
+            vec![vec![(4..6, Highlight::CurrentMatch)], vec![(0..2, Highlight::Match)]]
+        );
+        // A pattern which is not complete is not shown
+        type_text(&mut model, "\\(");
+        assert_eq!(model.get_highlights(0..1), vec![vec![]]);
+        model.key_tapped(Key::Escape);
+        assert_eq!(model.get_highlights(0..1), vec![vec![]]);
+
+        // After the search, all the matches are highlighted, even across lines
+        type_text(&mut model, "/o\\ntw");
+        model.key_tapped(Key::Enter);
+        assert_eq!(
+            model.get_highlights(0..3),
+            vec![vec![(6..7, Highlight::Match)], vec![(0..2, Highlight::Match)], vec![]]
+        );
+        type_text(&mut model, "/two");
+        model.key_tapped(Key::Enter);
+        type_text(&mut model, "ggwvl");
+        assert_eq!(
+            model.get_highlights(0..1),
+            vec![vec![(4..7, Highlight::Match), (4..6, Highlight::Selection)]]
+        );
+        model.key_tapped(Key::Escape);
+
+        // :nohlsearch hides them until the next search
+        type_text(&mut model, ":noh");
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_highlights(2..3), vec![vec![]]);
+        type_text(&mut model, "n");
+        assert_eq!(model.get_highlights(2..3), vec![vec![(6..9, Highlight::Match)]]);
+    }
+
+    #[test]
+    fn test_substitute() {
+        let mut model = EditorModel::new();
+        model.set_text("foo bar foo\nbar foo\nfoo".to_string());
+        model.force_normal_mode();
+
+        // The current line, first match only
+        type_text(&mut model, ":s/foo/baz/");
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_buffer().to_string(), "baz bar foo\nbar foo\nfoo");
+        assert!(model.get_status_message().contains("1 substitution on 1 line"));
+
+        // All the lines, all the matches, as a single undo step
+        type_text(&mut model, "u:%s/foo/X/g");
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_buffer().to_string(), "X bar X\nbar X\nX");
+        assert!(model.get_status_message().contains("4 substitutions on 3 lines"));
+        assert_cursor_at(model.get_cursor(), 0, 2);
+        type_text(&mut model, "u");
+        assert_eq!(model.get_buffer().to_string(), "foo bar foo\nbar foo\nfoo");
+
+        // Groups, case modifiers and line breaks
+        type_text(&mut model, ":1,2s/\\(\\w\\+\\) \\(\\w\\+\\)/\\u\\2 \\U\\1\\r[&]/");
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_buffer().to_string(), "Bar FOO\n[FOO BAR] foo\nFoo BAR\n[BAR FOO]\nfoo");
+
+        // Errors are shown in the status bar
+        type_text(&mut model, ":s/nothing/x/");
+        model.key_tapped(Key::Enter);
+        assert!(model.get_status_message().contains("E486: Pattern not found: nothing"));
+        type_text(&mut model, ":s/a/b/z");
+        model.key_tapped(Key::Enter);
+        assert!(model.get_status_message().contains("E488"));
+        type_text(&mut model, ":%s/foo//n");
+        model.key_tapped(Key::Enter);
+        assert!(model.get_status_message().contains("2 matches on 2 lines"));
+        assert_eq!(model.get_buffer().line(4), "foo");
+
+        // The selection gives its lines, and an empty pattern is the last search
+        model.set_text("a a\na a\na a".to_string());
+        model.reset_cursor();
+        type_text(&mut model, "/a");
+        model.key_tapped(Key::Enter);
+        type_text(&mut model, "Vj:");
+        assert!(model.get_status_message().contains("'<,'>"));
+        type_text(&mut model, "s//b/g");
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_buffer().to_string(), "b b\nb b\na a");
+    }
+
+    #[test]
+    fn test_ex_addresses() {
+        let mut model = EditorModel::new();
+        model.set_text("one\n  two\nthree\nfour\nfive".to_string());
+        model.force_normal_mode();
+
+        // A range alone goes to its last line
+        type_text(&mut model, ":2");
+        model.key_tapped(Key::Enter);
+        assert_cursor_at(model.get_cursor(), 2, 1);
+        type_text(&mut model, ":$-1");
+        model.key_tapped(Key::Enter);
+        assert_cursor_at(model.get_cursor(), 0, 3);
+
+        // Searches start after the current line, and ';' makes the next address relative
+        type_text(&mut model, ":1");
+        model.key_tapped(Key::Enter);
+        type_text(&mut model, ":/f/;+1s/^/-/");
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_buffer().to_string(), "one\n  two\nthree\n-four\n-five");
+        type_text(&mut model, ":?one?,.s/o/0/g");
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_buffer().to_string(), "0ne\n  tw0\nthree\n-f0ur\n-five");
+
+        // Marks follow their line when lines are added above it
+        type_text(&mut model, "ggjjmaggyyP:'a,'a+1s/$/!/");
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_buffer().line(3), "three!");
+        assert_eq!(model.get_buffer().line(4), "-f0ur!");
+
+        // Errors are shown in the status bar
+        type_text(&mut model, ":'bs/a/b/");
+        model.key_tapped(Key::Enter);
+        assert!(model.get_status_message().contains("E20: Mark not set"));
+        type_text(&mut model, ":/nothing/");
+        model.key_tapped(Key::Enter);
+        assert!(model.get_status_message().contains("E486: Pattern not found: nothing"));
+        type_text(&mut model, ":9");
+        model.key_tapped(Key::Enter);
+        assert!(model.get_status_message().contains("E16: Invalid range"));
+        type_text(&mut model, ":$+9223372036854775807");
+        model.key_tapped(Key::Enter);
+        assert!(model.get_status_message().contains("E16: Invalid range"));
+        type_text(&mut model, ":18446744073709551615+5");
+        model.key_tapped(Key::Enter);
+        assert!(model.get_status_message().contains("E16: Invalid range"));
+        type_text(&mut model, ":frobnicate");
+        model.key_tapped(Key::Enter);
+        assert!(model.get_status_message().contains("E492: Not an editor command: frobnicate"));
+        assert!(model.get_status_message().contains("Normal Mode"));
+    }
+
+    #[test]
+    fn test_ex_line_commands() {
+        let mut model = EditorModel::new();
+        model.set_text("a\nb\nc\nd".to_string());
+        model.force_normal_mode();
+
+        type_text(&mut model, ":2,3d x");
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_buffer().to_string(), "a\nd");
+        assert_eq!(model.get_register('x').unwrap().content, "b\nc\n");
+        type_text(&mut model, "\"xp:1t$");
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_buffer().to_string(), "a\nd\nb\nc\na");
+        assert_cursor_at(model.get_cursor(), 0, 4);
+        type_text(&mut model, ":1,2m$");
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_buffer().to_string(), "b\nc\na\na\nd");
+        type_text(&mut model, ":m0");
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_buffer().to_string(), "d\nb\nc\na\na");
+        type_text(&mut model, ":1,3m2");
+        model.key_tapped(Key::Enter);
+        assert!(model.get_status_message().contains("E134"));
+        type_text(&mut model, ":%norm ix");
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_buffer().to_string(), "xd\nxb\nxc\nxa\nxa");
+        assert!(model.get_status_message().contains("Normal Mode"));
+        type_text(&mut model, ":2,3p");
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_message(), ["xb", "xc"]);
+        // A count deletes lines from the last line of the range
+        type_text(&mut model, ":1,2d b 2");
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_buffer().to_string(), "xd\nxa\nxa");
+        assert_eq!(model.get_register('b').unwrap().content, "xb\nxc\n");
+    }
+
+    #[test]
+    fn test_undo_in_normal_command() {
+        let mut model = EditorModel::new();
+        model.set_text("abc\nxyz".to_string());
+        model.force_normal_mode();
+
+        // Each line reverts its own change, then the undo reverts the change before `:normal`
+        type_text(&mut model, "x:%norm xu");
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_buffer().to_string(), "bc\nxyz");
+        model.key_tapped('u'.into());
+        assert_eq!(model.get_buffer().to_string(), "abc\nxyz");
+    }
+
+    #[test]
+    fn test_global() {
+        let mut model = EditorModel::new();
+        model.set_text("INFO start\nDEBUG x\nDEBUG y\nINFO done\nERROR z".to_string());
+        model.force_normal_mode();
+
+        // Consecutive matching lines are all deleted, and in a single undo step
+        type_text(&mut model, ":g/DEBUG/d");
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_buffer().to_string(), "INFO start\nINFO done\nERROR z");
+        type_text(&mut model, "u");
+        assert_eq!(model.get_buffer().to_string(), "INFO start\nDEBUG x\nDEBUG y\nINFO done\nERROR z");
+
+        // :v and :g! act on the lines which do not match, the command may have its own range
+        type_text(&mut model, ":v/INFO/s/\\w\\+/[&]/");
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_buffer().to_string(), "INFO start\n[DEBUG] x\n[DEBUG] y\nINFO done\n[ERROR] z");
+        type_text(&mut model, "u:g!/DEBUG/.,+1s/$/;/");
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_buffer().to_string(), "INFO start;\nDEBUG x;\nDEBUG y\nINFO done;\nERROR z;");
+
+        // :s in :g does not fail on the lines without a match
+        model.set_text("a1\nb\na2".to_string());
+        type_text(&mut model, ":g/./s/a/A/");
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_buffer().to_string(), "A1\nb\nA2");
+
+        // :g/^/m0 reverses the lines, :t copies and :normal types keys
+        type_text(&mut model, ":g/^/m0");
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_buffer().to_string(), "A2\nb\nA1");
+        type_text(&mut model, ":g/A/t.");
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_buffer().to_string(), "A2\nA2\nb\nA1\nA1");
+        type_text(&mut model, ":2,$g/A/normal ddp");
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_buffer().to_string(), "A2\nb\nA2\nA1\nA1");
+        type_text(&mut model, "u");
+        assert_eq!(model.get_buffer().to_string(), "A2\nA2\nb\nA1\nA1");
+
+        // Without command, the matching lines are shown
+        type_text(&mut model, ":g/1");
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_message(), ["A1", "A1"]);
+        type_text(&mut model, ":g/x/d");
+        model.key_tapped(Key::Enter);
+        assert!(model.get_status_message().contains("Pattern not found: x"));
+        type_text(&mut model, ":v/./d");
+        model.key_tapped(Key::Enter);
+        assert!(model.get_status_message().contains("Pattern found in every line: ."));
+        type_text(&mut model, ":g/A/g/1/d");
+        model.key_tapped(Key::Enter);
+        assert!(model.get_status_message().contains("E147"));
+    }
+
+    #[test]
+    fn test_ex_write_and_quit() {
+        let dir = std::env::temp_dir().join(format!("red-ex-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("notes.txt");
+        std::fs::write(&file, "Hello world\n").unwrap();
+        let mut model = EditorModel::from_file(file.to_string_lossy().to_string(), false, Clipboard::Osc52);
+
+        // :q refuses to lose changes, unless forced
+        type_text(&mut model, "x:q");
+        model.key_tapped(Key::Enter);
+        assert!(!model.is_exit_requested());
+        assert!(model.get_status_message().contains("E37"));
+
+        // :w writes to another file, without overwriting it unless forced
+        let other = dir.join("other.txt");
+        type_text(&mut model, &format!(":wr {}", other.display()));
+        model.key_tapped(Key::Enter);
+        assert_eq!(std::fs::read_to_string(&other).unwrap(), "ello world");
+        type_text(&mut model, &format!(":w {}", other.display()));
+        model.key_tapped(Key::Enter);
+        assert!(model.get_status_message().contains("E13"));
+        type_text(&mut model, &format!("x:w! {}", other.display()));
+        model.key_tapped(Key::Enter);
+        assert_eq!(std::fs::read_to_string(&other).unwrap(), "llo world");
+
+        // Writing another file does not write the changes of the file
+        type_text(&mut model, ":q");
+        model.key_tapped(Key::Enter);
+        assert!(!model.is_exit_requested());
+        type_text(&mut model, ":x");
+        model.key_tapped(Key::Enter);
+        assert!(model.is_exit_requested());
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "llo world");
+
+        let mut model = EditorModel::from_file(file.to_string_lossy().to_string(), false, Clipboard::Osc52);
+        type_text(&mut model, "x:q!");
+        model.key_tapped(Key::Enter);
+        assert!(model.is_exit_requested());
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "llo world");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_substitute_confirm() {
+        let mut model = EditorModel::new();
+        model.set_text("a a\na\na".to_string());
+        model.force_normal_mode();
+
+        type_text(&mut model, ":%s/a/b/gc");
+        model.key_tapped(Key::Enter);
+        assert!(model.get_status_message().contains("replace with b"));
+        assert_eq!(
+            model.get_highlights(0..1)[0],
+            vec![(0..1, Highlight::Match), (2..3, Highlight::Match), (0..1, Highlight::CurrentMatch)]
+        );
+        type_text(&mut model, "yn");
+        assert_cursor_at(model.get_cursor(), 0, 1);
+        assert_eq!(model.get_buffer().to_string(), "b a\na\na");
+        type_text(&mut model, "l");
+        assert_eq!(model.get_buffer().to_string(), "b a\nb\na");
+        assert!(model.get_status_message().contains("2 substitutions on 2 lines"));
+
+        // The confirmed replacements are undone together, and the keys are back to normal mode
+        type_text(&mut model, "u");
+        assert_eq!(model.get_buffer().to_string(), "a a\na\na");
+        type_text(&mut model, ":%s/a/b/gc");
+        model.key_tapped(Key::Enter);
+        type_text(&mut model, "na");
+        assert_eq!(model.get_buffer().to_string(), "a b\nb\nb");
+        type_text(&mut model, ":%s/b/c/c");
+        model.key_tapped(Key::Enter);
+        model.key_tapped(Key::Escape);
+        assert_eq!(model.get_buffer().to_string(), "a b\nb\nb");
+        assert!(!model.get_status_message().contains("replace with"));
+    }
+
     #[test]
     fn test_insertion_at_end_of_line() {
         let mut model = setup_simple_model();
         model.force_insert_mode();
         // Make sure we spam the right key
         for _ in 0..20 {
-            model.key_tapped(RIGHT.unwrap() as u32);
+            model.key_tapped(Key::Right);
         }
         // Tap a key and validate that it inserted the key after the line
-        model.key_tapped('f' as u32);
-        assert_eq!(model.get_lines()[0], "Hello worldf");
+        model.key_tapped('f'.into());
+        assert_eq!(model.get_buffer().line(0), "Hello worldf");
     }
 
     #[test]
@@ -84,12 +1192,12 @@ And a last sentence
         let mut model = setup_simple_model();
         model.reset_cursor();
         model.force_normal_mode();
-        model.key_tapped('f' as u32);
-        model.key_tapped('o' as u32);
+        model.key_tapped('f'.into());
+        model.key_tapped('o'.into());
         assert_cursor_at(model.get_cursor(), 4, 0);
         // 
-        model.key_tapped('f' as u32);
-        model.key_tapped('Z' as u32);
+        model.key_tapped('f'.into());
+        model.key_tapped('Z'.into());
         assert_cursor_at(model.get_cursor(), 4, 0);
     }
 
@@ -98,12 +1206,12 @@ And a last sentence
         let mut model = setup_simple_model();
         model.reset_cursor();
         model.force_normal_mode();
-        model.key_tapped('r' as u32);
-        model.key_tapped('a' as u32);
-        assert_eq!(model.get_lines()[0], "aello world");
-        model.key_tapped('r' as u32);
-        model.key_tapped('H' as u32);
-        assert_eq!(model.get_lines()[0], "Hello world");
+        model.key_tapped('r'.into());
+        model.key_tapped('a'.into());
+        assert_eq!(model.get_buffer().line(0), "aello world");
+        model.key_tapped('r'.into());
+        model.key_tapped('H'.into());
+        assert_eq!(model.get_buffer().line(0), "Hello world");
     }
 
     #[test]
@@ -111,17 +1219,17 @@ And a last sentence
         let mut model = setup_simple_model();
 
         model.reset_cursor();
-        model.key_tapped(DOWN.unwrap() as u32);
-        model.key_tapped(DOWN.unwrap() as u32);
+        model.key_tapped(Key::Down);
+        model.key_tapped(Key::Down);
 
         // Make sure we spam the right key
         for i in 0..20 {
-            model.key_tapped(RIGHT.unwrap() as u32);
+            model.key_tapped(Key::Right);
         }
         assert_cursor_at(model.get_cursor(), 16, 1);
 
         // Pres the up key, the cursor must go to the left
-        model.key_tapped(UP.unwrap() as u32);
+        model.key_tapped(Key::Up);
         assert_cursor_at(model.get_cursor(), 10, 0);
     }
 
@@ -129,50 +1237,100 @@ And a last sentence
     fn test_basic_functionalities_insert_mode() {
         // Setup model
         let mut model = setup_simple_model();
-        assert_eq!(model.get_lines().len(), 2);
+        assert_eq!(model.get_buffer().len_lines(), 2);
 
         // Make sure we are in insert mode
         model.force_insert_mode();
 
         // Type some letters and press enter
         model.reset_cursor();
-        model.key_tapped('a' as u32);
-        model.key_tapped('a' as u32);
-        model.key_tapped('a' as u32);
-        model.key_tapped(ENTER.unwrap() as u32);
-        println!("Model = {:?}", model.get_lines());
-        assert_eq!(model.get_lines().len(), 3);
-        assert_eq!(model.get_lines()[0], "aaa");
+        model.key_tapped('a'.into());
+        model.key_tapped('a'.into());
+        model.key_tapped('a'.into());
+        model.key_tapped(Key::Enter);
+        println!("Model = {:?}", model.get_buffer().to_string());
+        assert_eq!(model.get_buffer().len_lines(), 3);
+        assert_eq!(model.get_buffer().line(0), "aaa");
 
         // Move the cursor and press enter
-        model.key_tapped(RIGHT.unwrap() as u32);
-        model.key_tapped(RIGHT.unwrap() as u32);
-        model.key_tapped(RIGHT.unwrap() as u32);
-        model.key_tapped(RIGHT.unwrap() as u32);
-        model.key_tapped(RIGHT.unwrap() as u32);
-        model.key_tapped(ENTER.unwrap() as u32);
-        assert_eq!(model.get_lines().len(), 4);
-        assert_eq!(model.get_lines()[1], "Hello");
-        assert_eq!(model.get_lines()[2], " world");
+        model.key_tapped(Key::Right);
+        model.key_tapped(Key::Right);
+        model.key_tapped(Key::Right);
+        model.key_tapped(Key::Right);
+        model.key_tapped(Key::Right);
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_buffer().len_lines(), 4);
+        assert_eq!(model.get_buffer().line(1), "Hello");
+        assert_eq!(model.get_buffer().line(2), " world");
 
         // Press enter several times
-        model.key_tapped(ENTER.unwrap() as u32);
-        model.key_tapped(ENTER.unwrap() as u32);
-        model.key_tapped(ENTER.unwrap() as u32);
-        assert_eq!(model.get_lines().len(), 7);
-        assert_eq!(model.get_lines()[5], " world");
-        assert_eq!(model.get_lines()[6], "another sentence");
+        model.key_tapped(Key::Enter);
+        model.key_tapped(Key::Enter);
+        model.key_tapped(Key::Enter);
+        assert_eq!(model.get_buffer().len_lines(), 7);
+        assert_eq!(model.get_buffer().line(5), " world");
+        assert_eq!(model.get_buffer().line(6), "another sentence");
 
         // Move line, cursor, and delete words
-        model.key_tapped(DOWN.unwrap() as u32);
-        model.key_tapped(RIGHT.unwrap() as u32);
-        model.key_tapped(RIGHT.unwrap() as u32);
-        model.key_tapped(RIGHT.unwrap() as u32);
-        model.key_tapped(RIGHT.unwrap() as u32);
-        model.key_tapped(RIGHT.unwrap() as u32);
-        model.key_tapped(BACKSPACE.unwrap() as u32);
-        model.key_tapped(BACKSPACE.unwrap() as u32);
-        assert_eq!(model.get_lines()[6], "anoer sentence");
+        model.key_tapped(Key::Down);
+        model.key_tapped(Key::Right);
+        model.key_tapped(Key::Right);
+        model.key_tapped(Key::Right);
+        model.key_tapped(Key::Right);
+        model.key_tapped(Key::Right);
+        model.key_tapped(Key::Backspace);
+        model.key_tapped(Key::Backspace);
+        assert_eq!(model.get_buffer().line(6), "anoer sentence");
+    }
+
+    #[test]
+    fn test_unicode_text() {
+        let mut model = EditorModel::new();
+        model.set_text("Ça coûte 日本円\nnaïve".to_string());
+        model.force_normal_mode();
+
+        // The cursor moves by graphemes, not by bytes
+        model.key_tapped('f'.into());
+        model.key_tapped('û'.into());
+        assert_cursor_at(model.get_cursor(), 5, 0);
+        model.key_tapped('f'.into());
+        model.key_tapped('円'.into());
+        assert_cursor_at(model.get_cursor(), 11, 0);
+        model.key_tapped('x'.into());
+        assert_eq!(model.get_buffer().line(0), "Ça coûte 日本");
+
+        // Replace a character with a multi-byte one
+        model.key_tapped('F'.into());
+        model.key_tapped('Ç'.into());
+        model.key_tapped('r'.into());
+        model.key_tapped('É'.into());
+        assert_eq!(model.get_buffer().line(0), "Éa coûte 日本");
+
+        // A combining accent is merged with the previous letter
+        model.key_tapped(Key::Down);
+        model.force_insert_mode();
+        model.key_tapped('e'.into());
+        model.key_tapped('\u{301}'.into());
+        assert_cursor_at(model.get_cursor(), 1, 1);
+        model.key_tapped(Key::Backspace);
+        assert_eq!(model.get_buffer().line(1), "naïve");
+    }
+
+    #[test]
+    fn test_ctrl_keys() {
+        let mut model = setup_model();
+        model.force_normal_mode();
+        model.set_screen_h(4);
+        model.key_tapped(Key::Ctrl('d'));
+        assert_cursor_at(model.get_cursor(), 0, 2);
+        model.key_tapped(Key::Ctrl('u'));
+        assert_cursor_at(model.get_cursor(), 0, 0);
+
+        // Keys with modifiers are not inserted as text
+        model.force_insert_mode();
+        model.key_tapped(Key::Alt('j'));
+        model.key_tapped(Key::Ctrl('x'));
+        assert_eq!(model.get_buffer().line(0), "Hello world");
     }
 
     fn assert_cursor_at(cursor: &Cursor, x: usize, y: usize) {
@@ -189,16 +1347,16 @@ And a last sentence
 
         assert_cursor_at(model.get_cursor(), 0, 0);
         for i in 0..10 {
-            model.key_tapped(RIGHT.unwrap() as u32);
+            model.key_tapped(Key::Right);
             assert_cursor_at(model.get_cursor(), i + 1, 0);
         }
 
         // Validate that if you keep spamming right arrow, the cursor does not move
-        model.key_tapped(RIGHT.unwrap() as u32);
-        model.key_tapped(RIGHT.unwrap() as u32);
-        model.key_tapped(RIGHT.unwrap() as u32);
-        model.key_tapped(RIGHT.unwrap() as u32);
-        model.key_tapped(RIGHT.unwrap() as u32);
+        model.key_tapped(Key::Right);
+        model.key_tapped(Key::Right);
+        model.key_tapped(Key::Right);
+        model.key_tapped(Key::Right);
+        model.key_tapped(Key::Right);
         assert_cursor_at(model.get_cursor(), 11, 0);
     }
 
@@ -212,14 +1370,14 @@ another sentence"
         model.set_text(text);
 
         // Initially the model is in insert
-        model.key_tapped(ESCAPE.unwrap() as u32);
+        model.key_tapped(Key::Escape);
         assert_cursor_at(model.get_cursor(), 0, 0);
 
         // Tap on word
-        model.key_tapped('w' as u32);
+        model.key_tapped('w'.into());
         assert_cursor_at(model.get_cursor(), 6, 0);
-        model.key_tapped('w' as u32);
-        model.key_tapped('w' as u32);
+        model.key_tapped('w'.into());
+        model.key_tapped('w'.into());
     }
 
     #[test]
@@ -230,21 +1388,21 @@ another sentence"
         model.set_text(text);
 
         // Initially the model is in insert
-        model.key_tapped(ESCAPE.unwrap() as u32);
+        model.key_tapped(Key::Escape);
         assert_cursor_at(model.get_cursor(), 0, 0);
 
         // Tap on word
-        model.key_tapped('w' as u32);
+        model.key_tapped('w'.into());
         assert_cursor_at(model.get_cursor(), 6, 0);
-        model.key_tapped('w' as u32);
+        model.key_tapped('w'.into());
         assert_cursor_at(model.get_cursor(), 10, 0);
 
         // Assert that the position of the cursor does not move
-        model.key_tapped('w' as u32);
-        model.key_tapped('w' as u32);
-        model.key_tapped('w' as u32);
-        model.key_tapped('w' as u32);
-        model.key_tapped('w' as u32);
+        model.key_tapped('w'.into());
+        model.key_tapped('w'.into());
+        model.key_tapped('w'.into());
+        model.key_tapped('w'.into());
+        model.key_tapped('w'.into());
         assert_cursor_at(model.get_cursor(), 10, 0);
     }
 }
@@ -0,0 +1,520 @@
+use std::fmt;
+use std::ops::Range;
+
+/// Maximum size (in bytes) of the text held by a single leaf of the rope.
+const MAX_LEAF_SIZE: usize = 1024;
+
+/// When the depth of the tree goes above this value, the rope is rebuilt from its leaves.
+/// A balanced rope with leaves of `MAX_LEAF_SIZE / 2` bytes would need several terabytes of text
+/// to reach it, so this only happens after many localized edits.
+const MAX_DEPTH: usize = 64;
+
+/// Summary of a piece of text.
+/// Every node of the rope caches the summary of the text it holds, which makes all the lookups
+/// logarithmic.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct TextInfo {
+    bytes: usize,
+    chars: usize,
+    newlines: usize,
+}
+
+impl TextInfo {
+    fn of(text: &str) -> Self {
+        Self {
+            bytes: text.len(),
+            chars: text.chars().count(),
+            newlines: text.bytes().filter(|b| *b == b'\n').count(),
+        }
+    }
+
+    fn combine(&self, other: &TextInfo) -> Self {
+        Self {
+            bytes: self.bytes + other.bytes,
+            chars: self.chars + other.chars,
+            newlines: self.newlines + other.newlines,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Leaf {
+        text: String,
+        info: TextInfo,
+    },
+    Branch {
+        left: Box<Node>,
+        right: Box<Node>,
+        info: TextInfo,
+        depth: usize,
+    },
+}
+
+impl Default for Node {
+    fn default() -> Self {
+        Node::Leaf {
+            text: String::new(),
+            info: TextInfo::default(),
+        }
+    }
+}
+
+impl Node {
+    /// Builds a balanced tree holding the provided text
+    fn build(text: &str) -> Self {
+        let mut chunks = Vec::new();
+        let mut rest = text;
+        while rest.len() > MAX_LEAF_SIZE / 2 {
+            let mut at = MAX_LEAF_SIZE / 2;
+            while !rest.is_char_boundary(at) {
+                at += 1;
+            }
+            let (chunk, tail) = rest.split_at(at);
+            chunks.push(chunk);
+            rest = tail;
+        }
+        chunks.push(rest);
+        Node::build_from_chunks(&chunks)
+    }
+
+    fn build_from_chunks(chunks: &[&str]) -> Self {
+        if chunks.len() == 1 {
+            Node::Leaf {
+                text: chunks[0].to_string(),
+                info: TextInfo::of(chunks[0]),
+            }
+        } else {
+            let (left, right) = chunks.split_at(chunks.len() / 2);
+            Node::branch(
+                Node::build_from_chunks(left),
+                Node::build_from_chunks(right),
+            )
+        }
+    }
+
+    fn branch(left: Node, right: Node) -> Self {
+        Node::Branch {
+            info: left.info().combine(&right.info()),
+            depth: 1 + left.depth().max(right.depth()),
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+
+    fn info(&self) -> TextInfo {
+        match self {
+            Node::Leaf { info, .. } | Node::Branch { info, .. } => *info,
+        }
+    }
+
+    fn depth(&self) -> usize {
+        match self {
+            Node::Leaf { .. } => 0,
+            Node::Branch { depth, .. } => *depth,
+        }
+    }
+
+    /// Recomputes the cached values of a branch after one of its children changed
+    fn update(&mut self) {
+        if let Node::Branch {
+            left,
+            right,
+            info,
+            depth,
+        } = self
+        {
+            *info = left.info().combine(&right.info());
+            *depth = 1 + left.depth().max(right.depth());
+        }
+    }
+
+    fn insert(&mut self, at: usize, text: &str) {
+        match self {
+            Node::Leaf { text: content, info } => {
+                content.insert_str(at, text);
+                if content.len() > MAX_LEAF_SIZE {
+                    *self = Node::build(content);
+                } else {
+                    *info = info.combine(&TextInfo::of(text));
+                }
+            }
+            Node::Branch { left, right, .. } => {
+                let left_bytes = left.info().bytes;
+                if at <= left_bytes {
+                    left.insert(at, text);
+                } else {
+                    right.insert(at - left_bytes, text);
+                }
+                self.update();
+            }
+        }
+    }
+
+    fn remove(&mut self, range: Range<usize>) {
+        match self {
+            Node::Leaf { text, info } => {
+                text.replace_range(range, "");
+                *info = TextInfo::of(text);
+            }
+            Node::Branch { left, right, .. } => {
+                let left_bytes = left.info().bytes;
+                if range.start < left_bytes {
+                    left.remove(range.start..range.end.min(left_bytes));
+                }
+                if range.end > left_bytes {
+                    right.remove(range.start.max(left_bytes) - left_bytes..range.end - left_bytes);
+                }
+
+                // An empty child is replaced by its sibling
+                if left.info().bytes == 0 {
+                    let right = std::mem::take(right.as_mut());
+                    *self = right;
+                } else if right.info().bytes == 0 {
+                    let left = std::mem::take(left.as_mut());
+                    *self = left;
+                } else {
+                    self.update();
+                }
+            }
+        }
+    }
+
+    /// Returns the byte offset of the n-th newline (starting from 0)
+    fn newline_offset(&self, n: usize) -> Option<usize> {
+        match self {
+            Node::Leaf { text, .. } => text.match_indices('\n').nth(n).map(|(i, _)| i),
+            Node::Branch { left, right, .. } => {
+                let left_info = left.info();
+                if n < left_info.newlines {
+                    left.newline_offset(n)
+                } else {
+                    right
+                        .newline_offset(n - left_info.newlines)
+                        .map(|i| i + left_info.bytes)
+                }
+            }
+        }
+    }
+
+    /// Returns the number of newlines found before the provided byte offset
+    fn newlines_before(&self, at: usize) -> usize {
+        match self {
+            Node::Leaf { text, .. } => text[..at].bytes().filter(|b| *b == b'\n').count(),
+            Node::Branch { left, right, .. } => {
+                let left_info = left.info();
+                if at <= left_info.bytes {
+                    left.newlines_before(at)
+                } else {
+                    left_info.newlines + right.newlines_before(at - left_info.bytes)
+                }
+            }
+        }
+    }
+
+    fn char_to_byte(&self, at: usize) -> usize {
+        match self {
+            Node::Leaf { text, .. } => text
+                .char_indices()
+                .nth(at)
+                .map(|(i, _)| i)
+                .unwrap_or(text.len()),
+            Node::Branch { left, right, .. } => {
+                let left_info = left.info();
+                if at < left_info.chars {
+                    left.char_to_byte(at)
+                } else {
+                    left_info.bytes + right.char_to_byte(at - left_info.chars)
+                }
+            }
+        }
+    }
+
+    fn byte_to_char(&self, at: usize) -> usize {
+        match self {
+            Node::Leaf { text, .. } => text[..at].chars().count(),
+            Node::Branch { left, right, .. } => {
+                let left_info = left.info();
+                if at <= left_info.bytes {
+                    left.byte_to_char(at)
+                } else {
+                    left_info.chars + right.byte_to_char(at - left_info.bytes)
+                }
+            }
+        }
+    }
+
+    /// Appends the text found in the provided range to `out`
+    fn collect(&self, range: Range<usize>, out: &mut String) {
+        match self {
+            Node::Leaf { text, .. } => out.push_str(&text[range]),
+            Node::Branch { left, right, .. } => {
+                let left_bytes = left.info().bytes;
+                if range.start < left_bytes {
+                    left.collect(range.start..range.end.min(left_bytes), out);
+                }
+                if range.end > left_bytes {
+                    right.collect(range.start.max(left_bytes) - left_bytes..range.end - left_bytes, out);
+                }
+            }
+        }
+    }
+}
+
+/// The text buffer holds the content of the file that is being edited.
+///
+/// It is implemented as a rope: a balanced binary tree whose leaves hold small chunks of text.
+/// Inserting or deleting text only touches a single branch of the tree, so edits stay
+/// logarithmic even for very large files, instead of shifting the entire content of a line or of
+/// the document.
+///
+/// All the offsets used by this API are byte offsets in the UTF-8 text, unless stated otherwise.
+/// Lines are separated by `'\n'`, which means that a buffer always has at least one line.
+#[derive(Clone, Debug, Default)]
+pub struct TextBuffer {
+    root: Node,
+}
+
+impl TextBuffer {
+    pub fn new() -> Self {
+        Self {
+            root: Node::default(),
+        }
+    }
+
+    /// Total number of bytes in the buffer
+    pub fn len_bytes(&self) -> usize {
+        self.root.info().bytes
+    }
+
+    /// Total number of chars in the buffer
+    pub fn len_chars(&self) -> usize {
+        self.root.info().chars
+    }
+
+    /// Number of lines in the buffer (an empty buffer has one empty line)
+    pub fn len_lines(&self) -> usize {
+        self.root.info().newlines + 1
+    }
+
+    /// Inserts the text at the provided byte offset
+    pub fn insert(&mut self, at: usize, text: &str) {
+        assert!(at <= self.len_bytes(), "insertion out of bounds");
+        if text.is_empty() {
+            return;
+        }
+        self.root.insert(at, text);
+        self.rebalance_if_needed();
+    }
+
+    /// Removes the provided byte range from the buffer
+    pub fn remove(&mut self, range: Range<usize>) {
+        assert!(range.end <= self.len_bytes(), "removal out of bounds");
+        if range.is_empty() {
+            return;
+        }
+        self.root.remove(range);
+    }
+
+    /// Returns the byte offset at which the provided line starts.
+    /// Asking for the line after the last one returns the length of the buffer.
+    pub fn line_to_byte(&self, line: usize) -> usize {
+        if line == 0 {
+            0
+        } else {
+            self.root
+                .newline_offset(line - 1)
+                .map(|i| i + 1)
+                .unwrap_or(self.len_bytes())
+        }
+    }
+
+    /// Returns the index of the line containing the provided byte offset
+    pub fn byte_to_line(&self, at: usize) -> usize {
+        self.root.newlines_before(at.min(self.len_bytes()))
+    }
+
+    /// Converts a char index into a byte offset
+    pub fn char_to_byte(&self, at: usize) -> usize {
+        self.root.char_to_byte(at)
+    }
+
+    /// Converts a byte offset into a char index
+    pub fn byte_to_char(&self, at: usize) -> usize {
+        self.root.byte_to_char(at.min(self.len_bytes()))
+    }
+
+    /// Returns the byte range covered by the provided line, without its trailing newline
+    pub fn line_range(&self, line: usize) -> Range<usize> {
+        let start = self.line_to_byte(line);
+        let end = if line + 1 < self.len_lines() {
+            self.line_to_byte(line + 1) - 1
+        } else {
+            self.len_bytes()
+        };
+        start..end
+    }
+
+    /// Returns the content of the provided line, without its trailing newline
+    pub fn line(&self, line: usize) -> String {
+        self.slice(self.line_range(line))
+    }
+
+    /// Returns a copy of the text found in the provided byte range
+    pub fn slice(&self, range: Range<usize>) -> String {
+        let mut out = String::with_capacity(range.len());
+        if !range.is_empty() {
+            self.root.collect(range, &mut out);
+        }
+        out
+    }
+
+    /// Iterates over the chunks of text stored in the buffer, in order
+    pub fn chunks(&self) -> Chunks<'_> {
+        Chunks {
+            stack: vec![&self.root],
+        }
+    }
+
+    fn rebalance_if_needed(&mut self) {
+        if self.root.depth() > MAX_DEPTH {
+            self.root = Node::build(&self.to_string());
+        }
+    }
+}
+
+impl From<&str> for TextBuffer {
+    fn from(text: &str) -> Self {
+        Self {
+            root: Node::build(text),
+        }
+    }
+}
+
+impl fmt::Display for TextBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for chunk in self.chunks() {
+            f.write_str(chunk)?;
+        }
+        Ok(())
+    }
+}
+
+/// Iterator over the chunks of a `TextBuffer`
+pub struct Chunks<'a> {
+    stack: Vec<&'a Node>,
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(node) = self.stack.pop() {
+            match node {
+                Node::Leaf { text, .. } => {
+                    if !text.is_empty() {
+                        return Some(text);
+                    }
+                }
+                Node::Branch { left, right, .. } => {
+                    self.stack.push(right);
+                    self.stack.push(left);
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::text_buffer::*;
+
+    fn long_text(n_lines: usize) -> String {
+        (0..n_lines)
+            .map(|i| format!("line number {i}"))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn test_lines() {
+        let buffer = TextBuffer::from("Hello world\nanother sentence\n\nAnd a last sentence");
+        assert_eq!(buffer.len_lines(), 4);
+        assert_eq!(buffer.line(0), "Hello world");
+        assert_eq!(buffer.line(1), "another sentence");
+        assert_eq!(buffer.line(2), "");
+        assert_eq!(buffer.line(3), "And a last sentence");
+        assert_eq!(buffer.line_to_byte(1), 12);
+        assert_eq!(buffer.byte_to_line(12), 1);
+        assert_eq!(buffer.byte_to_line(11), 0);
+    }
+
+    #[test]
+    fn test_empty_buffer() {
+        let buffer = TextBuffer::new();
+        assert_eq!(buffer.len_lines(), 1);
+        assert_eq!(buffer.line(0), "");
+    }
+
+    #[test]
+    fn test_insert_and_remove() {
+        let mut buffer = TextBuffer::from("Hello world");
+        buffer.insert(5, ",");
+        assert_eq!(buffer.to_string(), "Hello, world");
+        buffer.insert(12, "\n!");
+        assert_eq!(buffer.len_lines(), 2);
+        assert_eq!(buffer.line(1), "!");
+        buffer.remove(5..6);
+        buffer.remove(11..12);
+        assert_eq!(buffer.to_string(), "Hello world!");
+        assert_eq!(buffer.len_lines(), 1);
+    }
+
+    #[test]
+    fn test_large_buffer() {
+        let text = long_text(10000);
+        let mut buffer = TextBuffer::from(text.as_str());
+        assert_eq!(buffer.len_lines(), 10000);
+        assert_eq!(buffer.line(5678), "line number 5678");
+
+        // Edit in the middle of the document
+        let at = buffer.line_to_byte(5678);
+        buffer.insert(at, "edited ");
+        assert_eq!(buffer.line(5678), "edited line number 5678");
+        buffer.remove(buffer.line_range(5678));
+        assert_eq!(buffer.line(5678), "");
+        assert_eq!(buffer.line(5679), "line number 5679");
+
+        // Remove a range spanning many leaves
+        let start = buffer.line_to_byte(10);
+        let end = buffer.line_to_byte(9990);
+        buffer.remove(start..end);
+        assert_eq!(buffer.len_lines(), 20);
+        assert_eq!(buffer.line(10), "line number 9990");
+    }
+
+    #[test]
+    fn test_repeated_typing() {
+        let mut buffer = TextBuffer::from(long_text(100).as_str());
+        let mut expected = long_text(100);
+        let at = buffer.line_to_byte(50);
+        for i in 0..50000 {
+            let ch = if i % 100 == 0 { "\n" } else { "a" };
+            buffer.insert(at + i, ch);
+            expected.insert_str(at + i, ch);
+        }
+        assert_eq!(buffer.to_string(), expected);
+        assert!(buffer.root.depth() <= MAX_DEPTH);
+    }
+
+    #[test]
+    fn test_char_byte_conversion() {
+        let buffer = TextBuffer::from("héllo\nwörld");
+        assert_eq!(buffer.len_chars(), 11);
+        assert_eq!(buffer.char_to_byte(2), 3);
+        assert_eq!(buffer.byte_to_char(3), 2);
+        assert_eq!(buffer.line(1), "wörld");
+        assert_eq!(buffer.line_range(1).len(), 6);
+    }
+}
@@ -1,45 +1,614 @@
-use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use crate::{editor_action::EditorAction, cursor::Cursor};
+use crate::cursor::Cursor;
 
-/// An undo redo context is an element which can be undo / redo.
-/// It consists of the editor action, and some additional data.
-#[derive(Debug)]
-struct UndoRedoContext {
-    action: EditorAction,
-    cursor: Cursor
+/// A change made to the text buffer: at the byte `offset`, the text `removed` was replaced by the
+/// text `inserted`. Either of them can be empty.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TextChange {
+    pub offset: usize,
+    pub removed: String,
+    pub inserted: String,
 }
 
+impl TextChange {
+    /// Returns the change that cancels this one
+    pub fn inverted(&self) -> TextChange {
+        TextChange {
+            offset: self.offset,
+            removed: self.inserted.clone(),
+            inserted: self.removed.clone(),
+        }
+    }
+}
+
+/// An undo step is the list of changes that are undone (or redone) together, with the position
+/// of the cursor before and after them.
+#[derive(Clone, Debug)]
+pub struct UndoStep {
+    pub changes: Vec<TextChange>,
+    pub cursor_before: Cursor,
+    pub cursor_after: Cursor,
+}
+
+/// First line of an undo file, with the version of the format
+const UNDO_FILE_HEADER: &str = "red-undo 1";
+
+/// Returns the path of the undo file of the provided file: `.name.un~`, in the same directory
+pub fn undo_file_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{name}.un~"))
+}
+
+/// Returns a hash of the provided text (64 bits FNV-1a), used to check that an undo file matches
+/// the content of a file.
+pub fn content_hash(text: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in text.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Escapes the backslashes and the newlines, so that a text fits on a single line
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '\\' {
+            match chars.next() {
+                Some('n') => result.push('\n'),
+                Some(other) => result.push(other),
+                None => {}
+            }
+        } else {
+            result.push(ch);
+        }
+    }
+    result
+}
+
+/// A move in the undo tree: a step to undo (revert its changes in the reverse order and restore
+/// the cursor before it) or to redo (apply its changes in order and restore the cursor after it).
+#[derive(Clone, Debug)]
+pub enum UndoMove {
+    Undo(UndoStep),
+    Redo(UndoStep),
+}
+
+/// How far to travel in the history of the text, chronologically
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeTravel {
+    /// Go to the text state with the provided number of states before (negative) or after
+    /// (positive) the current one, whatever the branch they belong to
+    Steps(i64),
+    /// Go to the text state that was current the provided number of seconds before (negative)
+    /// or after (positive) the current one
+    Seconds(i64),
+}
+
+/// A state of the text, which is a node of the undo tree.
+#[derive(Clone, Debug)]
+struct UndoState {
+    /// The step going from the parent state to this state (empty for the root)
+    step: UndoStep,
+    parent: usize,
+    /// The child state that a redo goes to, which is the last one that was visited
+    redo_child: Option<usize>,
+    /// The time at which the state was created
+    time: SystemTime,
+}
 
 /// The undo redo manager is the class in charge of the 'u' and 'C-r' commands.
+///
+/// The model records every change made to the buffer in the manager. Changes are collected in the
+/// current transaction, which starts with `begin_transaction` and ends with `end_transaction`.
+/// Transactions can be nested: all the changes are grouped in a single undo step until the
+/// outermost transaction ends. For instance, an insert session is a transaction that contains
+/// the transactions of all the keys typed during the session.
+///
+/// The history is an undo tree: when a change is made after undoing, a new branch starts and the
+/// undone states are kept, so that they can be reached again with `g-`, `g+`, `:earlier` and
+/// `:later`. Each state is identified by its sequence number, which is its index in `states`
+/// (the root, with sequence number 0, is the text before any change).
 pub struct UndoRedoManager {
-    /// The buffer keeps track of the actions
-    buffer: VecDeque<UndoRedoContext>
+    states: Vec<UndoState>,
+    /// Sequence number of the current state
+    current_state: usize,
+    /// The step being recorded
+    current: Option<UndoStep>,
+    /// Number of transactions that are currently open
+    depth: usize,
 }
 
-
 impl UndoRedoManager {
     pub fn new() -> Self {
-        Self { buffer: VecDeque::new() }
+        let root = UndoState {
+            step: UndoStep {
+                changes: Vec::new(),
+                cursor_before: Cursor { x: 0, y: 0 },
+                cursor_after: Cursor { x: 0, y: 0 },
+            },
+            parent: 0,
+            redo_child: None,
+            time: SystemTime::now(),
+        };
+        Self {
+            states: vec![root],
+            current_state: 0,
+            current: None,
+            depth: 0,
+        }
     }
 
-    /// Add an action in the manager, so that it can be undone later on.
-    pub fn add_action(&mut self, action: EditorAction, mut cursor: Cursor) {
-        cursor.x += 1;
-        self.buffer.push_back(UndoRedoContext { action, cursor });
-        eprintln!("buffer = {:?}", self.buffer);
+    /// Returns the sequence number of the current text state
+    pub fn get_sequence_number(&self) -> usize {
+        self.current_state
     }
 
-    /// Returns the action to undo at the provided position
-    pub fn undo(&mut self) -> Option<(EditorAction, Cursor)> {
-        if let Some(to_redo) = self.buffer.pop_back() {
-            eprintln!("Action to cancel: {:#?}", to_redo.action);
-            Some(
-                (to_redo.action.undo_action(), to_redo.cursor)
-            )
-        } else {
-            None
+    /// Opens a transaction. If no transaction was open, a new step starts with the provided cursor
+    /// position.
+    pub fn begin_transaction(&mut self, cursor: Cursor) {
+        if self.depth == 0 {
+            self.current = Some(UndoStep {
+                changes: Vec::new(),
+                cursor_before: cursor,
+                cursor_after: cursor,
+            });
+        }
+        self.depth += 1;
+    }
+
+    /// Adds a change to the current step
+    pub fn record(&mut self, change: TextChange) {
+        if let Some(step) = self.current.as_mut() {
+            step.changes.push(change);
+        }
+    }
+
+    /// Closes a transaction. When the outermost transaction is closed, the current step is
+    /// finished: if it changed the buffer, it creates a new state in the undo tree.
+    pub fn end_transaction(&mut self, cursor: Cursor) {
+        if self.depth == 0 {
+            return;
+        }
+        self.depth -= 1;
+        if self.depth > 0 {
+            return;
+        }
+        if let Some(step) = self.current.take() {
+            self.push_step(step, cursor);
         }
     }
+
+    /// Finishes the step being recorded while a transaction is open, before moving in the tree:
+    /// an undo in a macro undoes the changes the macro just made. The transaction stays open,
+    /// and its next changes go in a new step starting at the provided cursor position.
+    pub fn commit_open_step(&mut self, cursor: Cursor) {
+        if self.depth == 0 {
+            return;
+        }
+        if let Some(step) = self.current.replace(UndoStep {
+            changes: Vec::new(),
+            cursor_before: cursor,
+            cursor_after: cursor,
+        }) {
+            self.push_step(step, cursor);
+        }
+    }
+
+    /// Adds the step as a child of the current state, which it becomes, unless it is empty
+    fn push_step(&mut self, mut step: UndoStep, cursor: Cursor) {
+        if step.changes.is_empty() {
+            return;
+        }
+        step.cursor_after = cursor;
+        let seq = self.states.len();
+        self.states.push(UndoState {
+            step,
+            parent: self.current_state,
+            redo_child: None,
+            time: SystemTime::now(),
+        });
+        self.states[self.current_state].redo_child = Some(seq);
+        self.current_state = seq;
+    }
+
+    /// Goes to the parent of the current state. Returns the step to undo.
+    pub fn undo(&mut self) -> Option<UndoStep> {
+        if self.current_state == 0 {
+            return None;
+        }
+        let state = &self.states[self.current_state];
+        let (step, parent) = (state.step.clone(), state.parent);
+        self.states[parent].redo_child = Some(self.current_state);
+        self.current_state = parent;
+        Some(step)
+    }
+
+    /// Goes to the last visited child of the current state. Returns the step to redo.
+    pub fn redo(&mut self) -> Option<UndoStep> {
+        let child = self.states[self.current_state].redo_child?;
+        self.current_state = child;
+        Some(self.states[child].step.clone())
+    }
+
+    /// Travels in the history of the text, and returns the moves to apply to the text to get
+    /// there.
+    pub fn time_travel(&mut self, travel: TimeTravel) -> Vec<UndoMove> {
+        let last = self.states.len() - 1;
+        let target = match travel {
+            TimeTravel::Steps(n) => (self.current_state as i64).saturating_add(n).clamp(0, last as i64) as usize,
+            TimeTravel::Seconds(n) => {
+                let current_time = self.states[self.current_state].time;
+                let delta = Duration::from_secs(n.unsigned_abs());
+                let target_time = if n < 0 {
+                    current_time.checked_sub(delta).unwrap_or(UNIX_EPOCH)
+                } else {
+                    match current_time.checked_add(delta) {
+                        Some(time) => time,
+                        None => return self.goto(last),
+                    }
+                };
+                // The last state that was created before the target time
+                self.states
+                    .iter()
+                    .rposition(|state| state.time <= target_time)
+                    .unwrap_or(0)
+            }
+        };
+        self.goto(target)
+    }
+
+    /// Goes to the state with the provided sequence number, by undoing the states up to the
+    /// common ancestor and then redoing the states down to the target.
+    fn goto(&mut self, target: usize) -> Vec<UndoMove> {
+        let current_path = self.path_to_root(self.current_state);
+        let target_path = self.path_to_root(target);
+        let ancestor = *current_path
+            .iter()
+            .find(|state| target_path.contains(state))
+            .unwrap_or(&0);
+
+        let mut moves = Vec::new();
+        while self.current_state != ancestor {
+            moves.push(UndoMove::Undo(self.undo().unwrap()));
+        }
+        let ancestor_index = target_path.iter().position(|state| *state == ancestor).unwrap();
+        for state in target_path[..ancestor_index].iter().rev() {
+            let parent = self.states[*state].parent;
+            self.states[parent].redo_child = Some(*state);
+            moves.push(UndoMove::Redo(self.redo().unwrap()));
+        }
+        moves
+    }
+
+    /// Writes the whole undo tree in a text format, for the file with the provided path and
+    /// content hash.
+    pub fn serialize(&self, path: &str, hash: u64) -> String {
+        let mut lines = vec![
+            UNDO_FILE_HEADER.to_string(),
+            format!("path {}", escape(path)),
+            format!("hash {hash:016x}"),
+            format!("current {}", self.current_state),
+        ];
+        for state in self.states.iter() {
+            let time = state.time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+            let redo_child = state.redo_child.map(|c| c.to_string()).unwrap_or("-".to_string());
+            let step = &state.step;
+            lines.push(format!(
+                "state {} {} {} {} {} {} {} {}",
+                state.parent,
+                redo_child,
+                time,
+                step.cursor_before.x,
+                step.cursor_before.y,
+                step.cursor_after.x,
+                step.cursor_after.y,
+                step.changes.len()
+            ));
+            for change in step.changes.iter() {
+                lines.push(format!("change {}", change.offset));
+                lines.push(format!("-{}", escape(&change.removed)));
+                lines.push(format!("+{}", escape(&change.inserted)));
+            }
+        }
+        lines.join("\n") + "\n"
+    }
+
+    /// Reads an undo tree written by `serialize`.
+    ///
+    /// Returns None if the data is invalid, or if it was written for another file or for another
+    /// content of the file.
+    pub fn deserialize(data: &str, path: &str, hash: u64) -> Option<Self> {
+        let mut lines = data.lines();
+        if lines.next()? != UNDO_FILE_HEADER
+            || unescape(lines.next()?.strip_prefix("path ")?) != path
+            || u64::from_str_radix(lines.next()?.strip_prefix("hash ")?, 16).ok()? != hash
+        {
+            return None;
+        }
+        let current_state: usize = lines.next()?.strip_prefix("current ")?.parse().ok()?;
+
+        let mut states = Vec::new();
+        while let Some(line) = lines.next() {
+            let fields: Vec<&str> = line.strip_prefix("state ")?.split(' ').collect();
+            if fields.len() != 8 {
+                return None;
+            }
+            let numbers: Vec<usize> = [0, 2, 3, 4, 5, 6, 7]
+                .iter()
+                .map(|i| fields[*i].parse().ok())
+                .collect::<Option<Vec<usize>>>()?;
+            let redo_child = match fields[1] {
+                "-" => None,
+                child => Some(child.parse().ok()?),
+            };
+
+            let mut changes = Vec::new();
+            for _ in 0..numbers[6] {
+                let offset = lines.next()?.strip_prefix("change ")?.parse().ok()?;
+                let removed = unescape(lines.next()?.strip_prefix('-')?);
+                let inserted = unescape(lines.next()?.strip_prefix('+')?);
+                changes.push(TextChange { offset, removed, inserted });
+            }
+
+            states.push(UndoState {
+                step: UndoStep {
+                    changes,
+                    cursor_before: Cursor { x: numbers[2], y: numbers[3] },
+                    cursor_after: Cursor { x: numbers[4], y: numbers[5] },
+                },
+                parent: numbers[0],
+                redo_child,
+                time: UNIX_EPOCH + Duration::from_secs(numbers[1] as u64),
+            });
+        }
+
+        // Make sure that the tree is consistent
+        let n = states.len();
+        let is_valid = n > 0
+            && current_state < n
+            && states.iter().enumerate().all(|(i, state)| {
+                (i == 0 || state.parent < i) && state.redo_child.is_none_or(|c| c > i && c < n)
+            });
+        if !is_valid {
+            return None;
+        }
+
+        Some(Self {
+            states,
+            current_state,
+            current: None,
+            depth: 0,
+        })
+    }
+
+    /// Returns the sequence numbers of the states from the provided one up to the root
+    fn path_to_root(&self, mut state: usize) -> Vec<usize> {
+        let mut path = vec![state];
+        while state != 0 {
+            state = self.states[state].parent;
+            path.push(state);
+        }
+        path
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::undo_redo::*;
+
+    fn change(offset: usize, inserted: &str) -> TextChange {
+        TextChange {
+            offset,
+            removed: "".to_string(),
+            inserted: inserted.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_undo_redo_stacks() {
+        let cursor = Cursor { x: 0, y: 0 };
+        let mut mgr = UndoRedoManager::new();
+
+        mgr.begin_transaction(cursor);
+        mgr.record(change(0, "a"));
+        mgr.end_transaction(Cursor { x: 1, y: 0 });
+
+        // Empty steps are not recorded
+        mgr.begin_transaction(cursor);
+        mgr.end_transaction(cursor);
+
+        mgr.begin_transaction(cursor);
+        mgr.record(change(1, "b"));
+        mgr.end_transaction(Cursor { x: 2, y: 0 });
+
+        assert_eq!(mgr.undo().unwrap().changes, vec![change(1, "b")]);
+        assert_eq!(mgr.undo().unwrap().changes, vec![change(0, "a")]);
+        assert!(mgr.undo().is_none());
+        assert_eq!(mgr.redo().unwrap().cursor_after, Cursor { x: 1, y: 0 });
+
+        // A new change starts a new branch: there is nothing to redo
+        mgr.begin_transaction(cursor);
+        mgr.record(change(1, "c"));
+        mgr.end_transaction(cursor);
+        assert!(mgr.redo().is_none());
+    }
+
+    fn record_change(mgr: &mut UndoRedoManager, offset: usize, inserted: &str) {
+        let cursor = Cursor { x: offset, y: 0 };
+        mgr.begin_transaction(cursor);
+        mgr.record(change(offset, inserted));
+        mgr.end_transaction(cursor);
+    }
+
+    /// Returns the inserted text of each move, with a '-' for the undone ones
+    fn describe(moves: &[UndoMove]) -> Vec<String> {
+        moves
+            .iter()
+            .map(|m| match m {
+                UndoMove::Undo(step) => format!("-{}", step.changes[0].inserted),
+                UndoMove::Redo(step) => step.changes[0].inserted.clone(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_undo_tree_branches() {
+        let mut mgr = UndoRedoManager::new();
+        record_change(&mut mgr, 0, "a"); // state 1
+        record_change(&mut mgr, 1, "b"); // state 2
+        mgr.undo();
+        record_change(&mut mgr, 1, "c"); // state 3, on another branch
+        assert_eq!(mgr.get_sequence_number(), 3);
+
+        // Going back chronologically reaches the abandoned branch
+        let moves = mgr.time_travel(TimeTravel::Steps(-1));
+        assert_eq!(describe(&moves), vec!["-c", "b"]);
+        assert_eq!(mgr.get_sequence_number(), 2);
+
+        let moves = mgr.time_travel(TimeTravel::Steps(-5));
+        assert_eq!(describe(&moves), vec!["-b", "-a"]);
+        assert_eq!(mgr.get_sequence_number(), 0);
+
+        let moves = mgr.time_travel(TimeTravel::Steps(3));
+        assert_eq!(describe(&moves), vec!["a", "c"]);
+
+        // Redo follows the last visited branch
+        mgr.time_travel(TimeTravel::Steps(-1));
+        mgr.undo();
+        assert_eq!(mgr.redo().unwrap().changes[0].inserted, "b");
+    }
+
+    #[test]
+    fn test_undo_tree_time_travel() {
+        let mut mgr = UndoRedoManager::new();
+        record_change(&mut mgr, 0, "a");
+        record_change(&mut mgr, 1, "b");
+        record_change(&mut mgr, 2, "c");
+
+        // Pretend the states were created one minute apart
+        let start = mgr.states[0].time;
+        for (i, state) in mgr.states.iter_mut().enumerate() {
+            state.time = start + Duration::from_secs(60 * i as u64);
+        }
+
+        let moves = mgr.time_travel(TimeTravel::Seconds(-90));
+        assert_eq!(describe(&moves), vec!["-c", "-b"]);
+        assert_eq!(mgr.get_sequence_number(), 1);
+
+        let moves = mgr.time_travel(TimeTravel::Seconds(-3600));
+        assert_eq!(describe(&moves), vec!["-a"]);
+
+        let moves = mgr.time_travel(TimeTravel::Seconds(120));
+        assert_eq!(describe(&moves), vec!["a", "b"]);
+
+        // Travels beyond the limits of the history stop at the first or last state
+        let moves = mgr.time_travel(TimeTravel::Steps(i64::MAX));
+        assert_eq!(describe(&moves), vec!["c"]);
+        let moves = mgr.time_travel(TimeTravel::Steps(i64::MIN));
+        assert_eq!(describe(&moves), vec!["-c", "-b", "-a"]);
+        let moves = mgr.time_travel(TimeTravel::Seconds(i64::MAX));
+        assert_eq!(describe(&moves), vec!["a", "b", "c"]);
+        let moves = mgr.time_travel(TimeTravel::Seconds(i64::MIN));
+        assert_eq!(describe(&moves), vec!["-c", "-b", "-a"]);
+    }
+
+    #[test]
+    fn test_nested_transactions() {
+        let mut mgr = UndoRedoManager::new();
+        mgr.begin_transaction(Cursor { x: 0, y: 0 });
+        mgr.record(change(0, "a"));
+        mgr.begin_transaction(Cursor { x: 1, y: 0 });
+        mgr.record(change(1, "b"));
+        mgr.end_transaction(Cursor { x: 2, y: 0 });
+        mgr.record(change(2, "c"));
+        mgr.end_transaction(Cursor { x: 3, y: 0 });
+
+        // Unbalanced transactions are ignored
+        mgr.end_transaction(Cursor { x: 3, y: 0 });
+
+        let step = mgr.undo().unwrap();
+        assert_eq!(step.changes, vec![change(0, "a"), change(1, "b"), change(2, "c")]);
+        assert_eq!(step.cursor_before, Cursor { x: 0, y: 0 });
+        assert_eq!(step.cursor_after, Cursor { x: 3, y: 0 });
+        assert!(mgr.undo().is_none());
+    }
+
+    #[test]
+    fn test_commit_open_step() {
+        let mut mgr = UndoRedoManager::new();
+        mgr.commit_open_step(Cursor { x: 0, y: 0 });
+        assert!(mgr.undo().is_none());
+
+        // The changes of an open transaction can be undone before it is closed
+        mgr.begin_transaction(Cursor { x: 0, y: 0 });
+        mgr.record(change(0, "a"));
+        mgr.commit_open_step(Cursor { x: 1, y: 0 });
+        let step = mgr.undo().unwrap();
+        assert_eq!(step.changes, vec![change(0, "a")]);
+        assert_eq!(step.cursor_after, Cursor { x: 1, y: 0 });
+        mgr.commit_open_step(Cursor { x: 0, y: 0 });
+        mgr.record(change(0, "b"));
+        mgr.end_transaction(Cursor { x: 1, y: 0 });
+
+        let step = mgr.undo().unwrap();
+        assert_eq!(step.changes, vec![change(0, "b")]);
+        assert_eq!(step.cursor_before, Cursor { x: 0, y: 0 });
+        assert!(mgr.undo().is_none());
+    }
+
+    #[test]
+    fn test_serialization() {
+        let mut mgr = UndoRedoManager::new();
+        record_change(&mut mgr, 0, "a\\b");
+        record_change(&mut mgr, 3, "\nc d");
+        mgr.undo();
+        record_change(&mut mgr, 3, "");
+
+        let data = mgr.serialize("/tmp/file.txt", 42);
+        assert!(UndoRedoManager::deserialize(&data, "/tmp/other.txt", 42).is_none());
+        assert!(UndoRedoManager::deserialize(&data, "/tmp/file.txt", 43).is_none());
+        assert!(UndoRedoManager::deserialize(&data[..data.len() - 10], "/tmp/file.txt", 42).is_none());
+
+        let mut restored = UndoRedoManager::deserialize(&data, "/tmp/file.txt", 42).unwrap();
+        assert_eq!(restored.get_sequence_number(), 3);
+        assert_eq!(restored.undo().unwrap().changes, vec![change(3, "")]);
+        let moves = restored.time_travel(TimeTravel::Steps(1));
+        assert_eq!(describe(&moves), vec!["\nc d"]);
+        assert_eq!(restored.undo().unwrap().changes, vec![change(3, "\nc d")]);
+        assert_eq!(restored.undo().unwrap().changes, vec![change(0, "a\\b")]);
+    }
+
+    #[test]
+    fn test_undo_file_path() {
+        assert_eq!(
+            undo_file_path(Path::new("/tmp/dir/notes.txt")),
+            PathBuf::from("/tmp/dir/.notes.txt.un~")
+        );
+        assert_ne!(content_hash("hello"), content_hash("hellp"));
+    }
+
+    #[test]
+    fn test_inverted_change() {
+        let change = TextChange {
+            offset: 3,
+            removed: "abc".to_string(),
+            inserted: "d".to_string(),
+        };
+        let inverted = change.inverted();
+        assert_eq!(inverted.removed, "d");
+        assert_eq!(inverted.inserted, "abc");
+        assert_eq!(inverted.inverted(), change);
+    }
+}
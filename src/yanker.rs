@@ -1,17 +1,265 @@
+use crate::clipboard::Clipboard;
+use std::collections::HashMap;
+
+/// How the content of a register was taken from the text, and how it is put back
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegisterKind {
+    /// A part of a line, or a text spanning over several lines
+    Charwise,
+    /// Whole lines, the content ends with a line break
+    Linewise,
+    /// A rectangle of text, one line of the content per line of the rectangle
+    Blockwise,
+}
+
+/// The content of a register
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Register {
+    pub content: String,
+    pub kind: RegisterKind,
+}
+
+impl Register {
+    pub fn new(content: String, kind: RegisterKind) -> Self {
+        Self { content, kind }
+    }
+}
+
+/// Registers that are set by the editor itself, and cannot be yanked into:
+/// - `.` holds the last inserted text
+/// - `%` holds the name of the file
+/// - `:` holds the last command line
+/// - `/` holds the last search pattern
+pub const READ_ONLY_REGISTERS: [char; 4] = ['.', '%', ':', '/'];
+
+/// The yanker is a register file, like the registers of Vim:
+/// - the named registers `a` to `z`, written with `"a`. Writing with `"A` appends to `a`.
+/// - the register `0` holds the last yank, `1` to `9` the last deletes, the most recent first
+/// - the register `-` holds the last delete made inside a line
+/// - the read-only registers, see [`READ_ONLY_REGISTERS`]
+/// - the registers `+` and `*`, which are the clipboard and the primary selection of the system
+///
+/// The unnamed register `"` is the one used when no register is given: it refers to the last
+/// register written.
 pub struct Yanker {
-    line: Option<String> 
+    /// The registers `a` to `z`
+    named: HashMap<char, Register>,
+    /// The registers `0` to `9`
+    numbered: [Option<Register>; 10],
+    /// The register `-`
+    small_delete: Option<Register>,
+    /// The content of the read-only registers
+    read_only: HashMap<char, String>,
+    /// The register that the unnamed register refers to
+    unnamed: Option<char>,
+    /// Last text written in the registers `+` and `*`, used when the clipboard cannot be read
+    clipboard_registers: HashMap<char, Register>,
+    /// Access to the clipboard of the system
+    clipboard: Clipboard,
 }
 
 impl Yanker {
-    pub fn new() -> Self {
-        Self { line: None }
+    pub fn new(clipboard: Clipboard) -> Self {
+        Self {
+            named: HashMap::new(),
+            numbered: Default::default(),
+            small_delete: None,
+            read_only: HashMap::new(),
+            unnamed: None,
+            clipboard_registers: HashMap::new(),
+            clipboard,
+        }
+    }
+
+    /// Returns true if the text can be yanked or deleted into the register
+    pub fn is_writable(register: char) -> bool {
+        register.is_ascii_alphanumeric() || ['-', '"', '+', '*'].contains(&register)
+    }
+
+    /// Returns true if the register exists
+    pub fn is_valid(register: char) -> bool {
+        Yanker::is_writable(register) || READ_ONLY_REGISTERS.contains(&register)
+    }
+
+    /// Keeps yanked text, in the provided register or in `0`
+    pub fn yank(&mut self, register: Option<char>, content: Register) {
+        match register {
+            None | Some('"') => self.set('0', content),
+            Some(register) => self.set(register, content),
+        }
+    }
+
+    /// Keeps deleted text, in the provided register. Without register, a delete of lines, or of
+    /// a text spanning several lines, goes to `1`, and the previous deletes are shifted to `2`
+    /// to `9`. A delete inside a line goes to `-`.
+    pub fn delete(&mut self, register: Option<char>, content: Register) {
+        match register {
+            None | Some('"') => {
+                if content.kind == RegisterKind::Charwise && !content.content.contains('\n') {
+                    self.set('-', content);
+                } else {
+                    self.numbered[1..].rotate_right(1);
+                    self.set('1', content);
+                }
+            }
+            Some(register) => self.set(register, content),
+        }
+    }
+
+    /// Writes in a register. An uppercase named register appends to the lowercase one.
+    fn set(&mut self, register: char, content: Register) {
+        if !Yanker::is_writable(register) {
+            return;
+        }
+        let content = if register.is_ascii_uppercase() {
+            match self.named.remove(&register.to_ascii_lowercase()) {
+                Some(previous) => Yanker::append(previous, content),
+                None => content,
+            }
+        } else {
+            content
+        };
+        let register = register.to_ascii_lowercase();
+        match register {
+            '0'..='9' => self.numbered[register as usize - '0' as usize] = Some(content),
+            '-' => self.small_delete = Some(content),
+            '+' | '*' => {
+                self.clipboard.copy(register, &content.content);
+                self.clipboard_registers.insert(register, content);
+            }
+            _ => {
+                self.named.insert(register, content);
+            }
+        }
+        self.unnamed = Some(register);
+    }
+
+    /// Appends a content to another one. Appending lines to a register makes it linewise.
+    fn append(mut previous: Register, content: Register) -> Register {
+        if content.kind == RegisterKind::Linewise {
+            if previous.kind != RegisterKind::Linewise && !previous.content.ends_with('\n') {
+                previous.content.push('\n');
+            }
+            previous.kind = RegisterKind::Linewise;
+        }
+        previous.content.push_str(&content.content);
+        previous
+    }
+
+    /// Sets the content of a read-only register
+    pub fn set_read_only(&mut self, register: char, content: String) {
+        self.read_only.insert(register, content);
+    }
+
+    /// Returns the content of a register, `"` being the last register written
+    pub fn get(&self, register: char) -> Option<Register> {
+        let register = register.to_ascii_lowercase();
+        match register {
+            '"' => self.get(self.unnamed?),
+            '0'..='9' => self.numbered[register as usize - '0' as usize].clone(),
+            '-' => self.small_delete.clone(),
+            'a'..='z' => self.named.get(&register).cloned(),
+            '+' | '*' => match self.clipboard.paste() {
+                // A text ending with a line break is made of whole lines
+                Some(text) if text.ends_with('\n') => Some(Register::new(text, RegisterKind::Linewise)),
+                Some(text) => Some(Register::new(text, RegisterKind::Charwise)),
+                None => self.clipboard_registers.get(&register).cloned(),
+            },
+            _ => {
+                let content = self.read_only.get(&register)?;
+                Some(Register::new(content.clone(), RegisterKind::Charwise))
+            }
+        }
+    }
+
+    /// Returns all the registers which are not empty, in the order used by `:registers`
+    pub fn list(&self) -> Vec<(char, Register)> {
+        std::iter::once('"')
+            .chain('0'..='9')
+            .chain('a'..='z')
+            .chain(['-', '+', '*'])
+            .chain(READ_ONLY_REGISTERS)
+            .filter_map(|register| Some((register, self.get(register)?)))
+            .filter(|(_, content)| !content.content.is_empty())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn charwise(text: &str) -> Register {
+        Register::new(text.to_string(), RegisterKind::Charwise)
+    }
+
+    fn linewise(text: &str) -> Register {
+        Register::new(text.to_string(), RegisterKind::Linewise)
+    }
+
+    #[test]
+    fn test_named_registers() {
+        let mut yanker = Yanker::new(Clipboard::Osc52);
+        yanker.yank(Some('a'), charwise("one"));
+        yanker.yank(Some('A'), charwise(" two"));
+        assert_eq!(yanker.get('a'), Some(charwise("one two")));
+        assert_eq!(yanker.get('"'), Some(charwise("one two")));
+        assert_eq!(yanker.get('0'), None);
+
+        // Appending lines makes the register linewise
+        yanker.yank(Some('A'), linewise("three\n"));
+        assert_eq!(yanker.get('a'), Some(linewise("one two\nthree\n")));
+        assert_eq!(yanker.get('b'), None);
     }
 
-    pub fn yank(&mut self, content: String) {
-        self.line = Some(content);
+    #[test]
+    fn test_numbered_registers() {
+        let mut yanker = Yanker::new(Clipboard::Osc52);
+        yanker.yank(None, charwise("yanked"));
+        yanker.delete(None, linewise("first\n"));
+        yanker.delete(None, charwise("word"));
+        yanker.delete(None, charwise("two\nlines"));
+        assert_eq!(yanker.get('0'), Some(charwise("yanked")));
+        assert_eq!(yanker.get('1'), Some(charwise("two\nlines")));
+        assert_eq!(yanker.get('2'), Some(linewise("first\n")));
+        assert_eq!(yanker.get('-'), Some(charwise("word")));
+        assert_eq!(yanker.get('"'), Some(charwise("two\nlines")));
+
+        // The oldest deletes are dropped after "9
+        for i in 0..9 {
+            yanker.delete(None, linewise(&format!("{i}\n")));
+        }
+        assert_eq!(yanker.get('9'), Some(linewise("0\n")));
+        assert_eq!(yanker.get('1'), Some(linewise("8\n")));
+
+        yanker.set_read_only('%', "file.txt".to_string());
+        let registers: Vec<char> = yanker.list().iter().map(|(register, _)| *register).collect();
+        assert_eq!(registers, ['"', '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', '-', '%']);
     }
 
-    pub fn get_content(&self) -> &Option<String> {
-        &self.line
+    #[test]
+    fn test_clipboard_registers() {
+        // The terminal clipboard cannot be read: the last text yanked is put
+        let mut yanker = Yanker::new(Clipboard::Osc52);
+        yanker.yank(Some('+'), charwise("copied"));
+        assert_eq!(yanker.get('+'), Some(charwise("copied")));
+        assert_eq!(yanker.get('*'), None);
+
+        // A stand-in for the clipboard commands
+        let dir = std::env::temp_dir().join(format!("red-registers-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("clipboard");
+        let mut yanker = Yanker::new(Clipboard::Commands {
+            copy: format!("cat > '{}'", file.display()),
+            paste: Some(format!("cat '{}'", file.display())),
+        });
+        yanker.yank(Some('+'), linewise("line\n"));
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "line\n");
+        assert_eq!(yanker.get('"'), Some(linewise("line\n")));
+
+        // The text copied by other programs is put
+        std::fs::write(&file, "from outside").unwrap();
+        assert_eq!(yanker.get('*'), Some(charwise("from outside")));
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }